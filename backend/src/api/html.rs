@@ -0,0 +1,125 @@
+use std::fmt::Write;
+use super::ip_api::IpResponse;
+use crate::utils::country;
+
+/// 浏览器直接访问时的简单页面样式
+const STYLE: &str = "body{font-family:system-ui,sans-serif;max-width:52rem;margin:2rem auto;padding:0 1rem;color:#222}\
+h1{font-size:1.6rem}table{border-collapse:collapse;width:100%;margin-bottom:1.5rem}\
+th,td{text-align:left;padding:.35rem .6rem;border-bottom:1px solid #ddd}th{width:12rem;color:#555;font-weight:500}\
+.badge{display:inline-block;padding:.1rem .5rem;border-radius:.6rem;font-size:.85rem;color:#fff;background:#888}\
+.valid{background:#2e7d32}.invalid{background:#c62828}.not-found{background:#f9a825}.muted{color:#888}";
+
+/// 将查询结果渲染为HTML页面（地图链接、国旗、ASN、上游表格、RPKI状态）
+pub(super) fn render_ip_page(response: &IpResponse) -> String {
+    let info = &response.info;
+    // info.country 可能是本地化名称，国旗按换算后的代码生成
+    let flag = info
+        .country
+        .as_deref()
+        .and_then(|value| country::canonicalize(value, None, &[]))
+        .map(|country| flag_emoji(&country.code))
+        .unwrap_or_default();
+    let mut html = String::new();
+    let _ = write!(
+        html,
+        "<!DOCTYPE html><html><head><meta charset=\"utf-8\"><meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">\
+         <title>{ip}</title><style>{STYLE}</style></head><body><h1>{flag} {ip}</h1><table>",
+        ip = escape(&info.ip),
+    );
+
+    let location = [info.city.as_deref(), info.country.as_deref()]
+        .into_iter()
+        .flatten()
+        .collect::<Vec<_>>()
+        .join(", ");
+    row(&mut html, "位置", (!location.is_empty()).then(|| escape(&location)));
+    row(&mut html, "坐标", info.latitude.zip(info.longitude).map(|(lat, lon)| {
+        format!(
+            "<a href=\"https://www.openstreetmap.org/?mlat={lat}&amp;mlon={lon}#map=10/{lat}/{lon}\">{lat:.4}, {lon:.4}</a>"
+        )
+    }));
+    row(&mut html, "时区", info.time_zone.as_deref().map(escape));
+    row(&mut html, "ASN", info.asn.map(|asn| format!("AS{}", asn)));
+    row(&mut html, "组织", info.organization.as_deref().map(escape));
+    row(&mut html, "网段", info.ip_range.as_deref().or(response.bgp_prefix.as_deref()).map(escape));
+    if let Some(whois) = &response.whois_info {
+        row(&mut html, "网络名称", whois.netname.as_deref().map(escape));
+    }
+    html.push_str("</table>");
+
+    if let Some(bgp) = response.bgp_info.as_ref().filter(|bgp| !bgp.upstreams.is_empty()) {
+        html.push_str("<h2>上游</h2><table><tr><th>ASN</th><th>名称</th></tr>");
+        for upstream in &bgp.upstreams {
+            let _ = write!(
+                html,
+                "<tr><td>{}</td><td>{}</td></tr>",
+                escape(&upstream.asn),
+                upstream.name.as_deref().map(escape).unwrap_or_default(),
+            );
+        }
+        html.push_str("</table>");
+    }
+
+    if !response.rpki_info_list.is_empty() {
+        html.push_str("<h2>RPKI</h2><table><tr><th>路由</th><th>状态</th></tr>");
+        for rpki in &response.rpki_info_list {
+            let class = match rpki.validity.as_str() {
+                "valid" => "valid",
+                "invalid" => "invalid",
+                "not-found" | "unknown" => "not-found",
+                _ => "",
+            };
+            let _ = write!(
+                html,
+                "<tr><td>{} {}</td><td><span class=\"badge {}\">{}</span></td></tr>",
+                escape(&rpki.prefix),
+                escape(&rpki.asn),
+                class,
+                escape(&rpki.validity),
+            );
+        }
+        html.push_str("</table>");
+    }
+
+    if !response.errors.is_empty() {
+        html.push_str("<ul class=\"muted\">");
+        for error in &response.errors {
+            let _ = write!(html, "<li>{}</li>", escape(error));
+        }
+        html.push_str("</ul>");
+    }
+    html.push_str("</body></html>");
+    html
+}
+
+fn row(html: &mut String, label: &str, value: Option<String>) {
+    if let Some(value) = value {
+        let _ = write!(html, "<tr><th>{}</th><td>{}</td></tr>", label, value);
+    }
+}
+
+/// 两位国家代码转换为区域指示符组成的国旗emoji
+fn flag_emoji(code: &str) -> String {
+    if code.len() != 2 || !code.chars().all(|c| c.is_ascii_alphabetic()) {
+        return String::new();
+    }
+    code.to_ascii_uppercase()
+        .chars()
+        .filter_map(|c| char::from_u32(0x1F1E6 + (c as u32 - 'A' as u32)))
+        .collect()
+}
+
+fn escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
@@ -1,21 +1,38 @@
-use crate::maxmind::reader::MaxmindReader;
-use crate::utils::ip_cache::IpCache;
-use crate::utils::whois_client::WhoisClient;
-use crate::utils::bgptools_client::{BgpToolsClient, BgpToolsUpstream};
-use crate::utils::rpki_client::{RpkiClient, RpkiValidity};
-use crate::utils::bgp_api_client::BgpApiClient;
+use crate::config::{Config, DataSource, EndpointGroup, EndpointsConfig, OverrideConfig, PrivateIpBehavior, RiskConfig, SharedConfig};
+use crate::maxmind::reader::{is_reserved_ip, GeoConfidence, LookupFields, SharedReader};
+use crate::maxmind::{MaxmindUpdater, UPDATE_IN_PROGRESS};
+use crate::utils::ip_cache::{CacheBackend, CacheHit};
+use crate::utils::api_keys::ApiKeyUsage;
+use crate::utils::distribution::Distribution;
+use crate::utils::query_history::QueryHistory;
+use crate::utils::kv_store::KvStore;
+use crate::utils::whois_client::{self, WhoisClient, WhoisObject};
+use crate::utils::bgptools_client::{BgpToolsUpstream, UpstreamNode};
+use crate::utils::rpki_client::{RpkiCrossCheck, RpkiValidity, RpkiVerdict};
+use crate::utils::country::{self, CanonicalCountry};
+use crate::utils::privacy::log_ip;
+use crate::utils::risk;
+use crate::utils::publisher::LookupPublisher;
+use crate::utils::webhook;
+use crate::utils::ripestat_client::{HistoricalRouting, RelatedPrefix};
+use chrono::{DateTime, NaiveDate, NaiveDateTime, Utc};
+use crate::utils::language;
+use crate::utils::timezone;
+use crate::utils::upstream::{upstream_tree, LiveUpstreams, QueryTarget, Upstreams};
+use super::html;
 use axum::{
-    extract::Path,
-    http::StatusCode,
-    response::{IntoResponse, Json},
+    extract::{Path, Query},
+    http::{HeaderMap, HeaderName, HeaderValue, StatusCode},
+    response::{Html, IntoResponse, Json, Response},
     Router,
-    routing::get,
+    routing::{get, post},
 };
 use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashSet, VecDeque};
+use futures::StreamExt;
 use std::sync::Arc;
-use std::time::{SystemTime, UNIX_EPOCH};
-use tracing::{info, warn, debug};
-use futures::future::join_all;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tracing::{info, warn, debug, Instrument};
 
 #[derive(Serialize, Deserialize)]
 pub struct IpInfo {
@@ -25,11 +42,73 @@ pub struct IpInfo {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub country: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    pub country_canonical: Option<CanonicalCountry>, // 开启 response.normalize_countries 时提供
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub city: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    pub city_is_approximate: Option<bool>, // city 为行政区名称时为true
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub latitude: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub longitude: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub time_zone: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub utc_offset: Option<String>, // time_zone 在请求时刻的UTC偏移（考虑夏令时）
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub is_anycast: Option<bool>, // 为true时地理位置不可靠
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub embedded_mac: Option<String>, // EUI-64 IPv6地址中嵌入的MAC
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mac_vendor: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub confidence: Option<GeoConfidence>, // 仅Enterprise数据库提供
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user_type: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub asn: Option<u32>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub organization: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub organization_original: Option<String>, // 被覆盖前MaxMind的组织名称
+}
+
+/// ipinfo.io 格式的响应（?compat=ipinfo）
+#[derive(Serialize)]
+struct IpinfoCompat {
+    ip: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    city: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    country: Option<String>, // 两位国家代码
+    #[serde(skip_serializing_if = "Option::is_none")]
+    loc: Option<String>, // "纬度,经度"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    org: Option<String>, // "AS13335 Cloudflare, Inc."
+    #[serde(skip_serializing_if = "Option::is_none")]
+    timezone: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    anycast: Option<bool>,
+}
+
+/// ip-api.com 格式的响应（?compat=ip-api），缺失的字符串字段与原服务一样为空字符串
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct IpApiComCompat {
+    status: &'static str,
+    country: String, // 英文国家名称
+    country_code: String,
+    city: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    lat: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    lon: Option<f64>,
+    timezone: String,
+    isp: String,
+    org: String,
+    #[serde(rename = "as")]
+    as_name: String, // "AS13335 Cloudflare, Inc."
+    query: String,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -41,11 +120,19 @@ pub struct WhoisInfoResponse {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub country: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    pub country_canonical: Option<CanonicalCountry>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub org: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub admin: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub maintainer: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub route: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub origin: Option<String>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub extra: BTreeMap<String, Vec<String>>, // 按属性名排序，保证输出稳定
 }
 
 #[derive(Serialize, Deserialize)]
@@ -57,12 +144,19 @@ pub struct BgpInfoResponse {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub country: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    pub country_canonical: Option<CanonicalCountry>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub registry: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub allocated: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub as_name: Option<String>,
     pub upstreams: Vec<BgpToolsUpstream>,
+    pub upstreams_total: usize, // 截断前的上游数量
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub upstreams_next_offset: Option<usize>, // 还有更多上游时，下一页的 upstream_offset
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub upstream_tree: Option<Vec<UpstreamNode>>, // 指定 ?upstream_depth= 时本页上游的上游拓扑
 }
 
 #[derive(Serialize, Deserialize)]
@@ -72,10 +166,322 @@ pub struct IpResponse {
     pub whois_info: Option<WhoisInfoResponse>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub bgp_info: Option<BgpInfoResponse>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bgp_prefix: Option<String>, // BGP API中宣告该地址的前缀
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bgp_api_raw: Option<String>, // BGP API原始响应，仅开启 upstream.keep_raw_bgp_api 时提供
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub related_prefixes: Vec<RelatedPrefix>, // 覆盖该地址的较大前缀，最不具体的在前
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub related_prefixes_total: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub related_prefixes_next_offset: Option<usize>, // 还有更多相关前缀时，下一页的 related_offset
     #[serde(skip_serializing_if = "Vec::is_empty")]
     pub rpki_info_list: Vec<RpkiValidity>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub rpki_cross_checks: Vec<RpkiCrossCheck>, // 主验证器与额外验证器的结论对比
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub dnsbl: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub risk_score: Option<u8>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub risk_factors: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub historical_routing: Option<HistoricalRouting>, // 指定 ?at= 时的历史路由状态
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub unallocated: Option<bool>, // 各数据源都成功返回但均无分配记录时为true
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub override_prefix: Option<String>, // 命中 overrides 配置时为匹配的前缀
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<String>, // 各数据源之间的国家、ASN不一致
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub errors: Vec<String>, // 未能提供的数据及原因
     #[serde(skip_serializing_if = "Option::is_none")]
     pub cached: Option<u64>, // 缓存时间戳，如果不是缓存则为None
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ttl_seconds: Option<u64>, // 结果的剩余有效期（秒）
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stale: Option<bool>, // 缓存已过期、正在后台刷新时为true
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stale_reason: Option<String>, // 数据源全部不可用、返回宽限期内的旧数据时为 upstream_unavailable
+    pub sources: FieldSources, // info.country / info.organization 实际采用的数据源
+    #[serde(skip)]
+    pub timing: Option<ServerTiming>, // 各阶段耗时，通过 Server-Timing 响应头输出
+    #[serde(skip)]
+    pub data_time: Option<u64>, // 数据查询（写入缓存）的时间，与数据库构建时间共同决定 Last-Modified
+}
+
+/// 查询各阶段的耗时，缓存命中时只有 cache
+#[derive(Debug, Clone, Default)]
+pub struct ServerTiming {
+    pub cache: Duration,
+    pub maxmind_ms: Option<u64>,
+    pub whois_ms: Option<u64>,
+    /// BGP Tools与BGP API并发查询，取较慢者
+    pub bgp_ms: Option<u64>,
+    pub rpki_ms: Option<u64>,
+}
+
+impl ServerTiming {
+    /// 格式化为 Server-Timing 头的值，如 cache;dur=0.12, maxmind;dur=1
+    fn header_value(&self) -> String {
+        let mut metrics = vec![format!("cache;dur={:.2}", self.cache.as_secs_f64() * 1000.0)];
+        for (name, ms) in [("maxmind", self.maxmind_ms), ("whois", self.whois_ms), ("bgp", self.bgp_ms), ("rpki", self.rpki_ms)] {
+            if let Some(ms) = ms {
+                metrics.push(format!("{};dur={}", name, ms));
+            }
+        }
+        metrics.join(", ")
+    }
+}
+
+/// 响应中各字段采用的数据源，没有任何来源提供时为None
+/// MaxMind没有国家时 country 回退到WHOIS或BGP Tools的注册国家，此时为 whois / bgp，表示并非地理定位结果
+#[derive(Serialize, Deserialize, Default, Debug, Clone, PartialEq)]
+pub struct FieldSources {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub country: Option<DataSource>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub organization: Option<DataSource>,
+}
+
+/// 列表字段的分页位置
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Page {
+    pub offset: usize,
+    /// 本页最多返回的条目数，None 表示不限
+    pub limit: Option<usize>,
+}
+
+impl Page {
+    /// 请求的条目数不能超过配置的上限
+    fn requested(offset: Option<usize>, limit: Option<usize>, cap: Option<usize>) -> Self {
+        let limit = match (limit, cap) {
+            (Some(limit), Some(cap)) => Some(limit.min(cap)),
+            (limit, cap) => limit.or(cap),
+        };
+        Self { offset: offset.unwrap_or(0), limit }
+    }
+
+    /// 返回本页的条目，以及还有更多条目时下一页的起始位置
+    fn apply<T: Clone>(&self, items: &[T]) -> (Vec<T>, Option<usize>) {
+        let start = self.offset.min(items.len());
+        let end = self.limit.map_or(items.len(), |limit| start.saturating_add(limit).min(items.len()));
+        (items[start..end].to_vec(), (end < items.len()).then_some(end))
+    }
+}
+
+/// 构建响应时使用的选项（来自配置和请求参数）
+pub struct ResponseOptions {
+    pub risk: RiskConfig,
+    /// 名称的语言优先级，为空时使用默认的中文/英文名称
+    pub languages: Vec<String>,
+    /// 返回的上游AS分页
+    pub upstreams_page: Page,
+    /// 返回的相关前缀分页
+    pub related_prefixes_page: Page,
+    /// 城市未知时使用行政区名称近似
+    pub approximate_city: bool,
+    /// 已知的任播前缀
+    pub anycast_prefixes: Vec<ipnet::IpNet>,
+    /// 国家、组织字段优先采用的数据源
+    pub prefer: DataSource,
+    /// 附加统一格式的国家
+    pub normalize_countries: bool,
+    /// 输出数据源之间的不一致
+    pub source_warnings: bool,
+    /// 缓存条目写入达到该秒数时按未命中处理，重新查询并更新缓存
+    pub max_age: Option<u64>,
+}
+
+impl ResponseOptions {
+    fn from_config(config: &Config) -> Self {
+        Self {
+            risk: config.risk.clone(),
+            languages: Vec::new(),
+            upstreams_page: Page::requested(None, None, config.response.max_upstreams),
+            related_prefixes_page: Page::requested(None, None, config.response.max_related_prefixes),
+            approximate_city: config.response.approximate_city,
+            anycast_prefixes: config.anycast.prefixes.iter().filter_map(|p| p.parse().ok()).collect(),
+            prefer: config.response.prefer,
+            normalize_countries: config.response.normalize_countries,
+            source_warnings: config.response.source_warnings,
+            max_age: None,
+        }
+    }
+    
+    fn with_max_age(mut self, max_age: Option<u64>) -> Self {
+        self.max_age = max_age;
+        self
+    }
+    
+    /// 请求参数中的优先数据源优先于配置
+    fn with_prefer(mut self, prefer: Option<DataSource>) -> Self {
+        if let Some(prefer) = prefer {
+            self.prefer = prefer;
+        }
+        self
+    }
+    
+    /// 按请求参数翻页，每页条目数不超过配置的上限
+    fn with_pages(mut self, query: &IpQuery) -> Self {
+        let upstream_limit = query.upstream_limit.or(query.max_upstreams);
+        self.upstreams_page = Page::requested(query.upstream_offset, upstream_limit, self.upstreams_page.limit);
+        self.related_prefixes_page = Page::requested(query.related_offset, query.related_limit, self.related_prefixes_page.limit);
+        self
+    }
+    
+    /// 按请求选择名称语言：?lang= 优先于 Accept-Language 请求头
+    fn with_languages(mut self, lang: Option<&str>, headers: &HeaderMap) -> Self {
+        self.languages = match lang {
+            Some(lang) => language::parse_lang_param(lang),
+            None => headers
+                .get(axum::http::header::ACCEPT_LANGUAGE)
+                .and_then(|v| v.to_str().ok())
+                .map(language::parse_accept_language)
+                .unwrap_or_default(),
+        };
+        self
+    }
+}
+
+#[derive(Deserialize, Default)]
+pub struct IpQuery {
+    /// 响应格式：json（默认）、geojson 或 msgpack
+    pub format: Option<String>,
+    /// 名称语言，逗号分隔（如 ja,en）
+    pub lang: Option<String>,
+    /// 返回的上游AS数量上限（与 upstream_limit 相同，保留兼容）
+    pub max_upstreams: Option<usize>,
+    /// 上游AS列表的起始位置和本页条目数
+    pub upstream_offset: Option<usize>,
+    pub upstream_limit: Option<usize>,
+    /// 递归查询上游的上游的层数（1 为直接上游，最多3层）
+    pub upstream_depth: Option<usize>,
+    /// 相关前缀列表的起始位置和本页条目数
+    pub related_offset: Option<usize>,
+    pub related_limit: Option<usize>,
+    /// 历史查询时间点（ISO8601，如 2020-01-01 或 2020-01-01T00:00:00Z）
+    pub at: Option<String>,
+    /// 国家、组织字段优先采用的数据源：maxmind、whois 或 bgp
+    pub prefer: Option<String>,
+    /// 按其他IP API的字段命名输出：ipinfo 或 ip-api，便于迁移现有客户端
+    pub compat: Option<String>,
+    /// 缓存条目写入达到该秒数时重新查询（0 表示总是重新查询）
+    pub max_age: Option<u64>,
+    /// 未分配的地址返回404（?strict 或 ?strict=true）
+    pub strict: Option<String>,
+    /// 省略随时间变化的字段，相同输入得到逐字节相同的输出（需开启 response.allow_deterministic）
+    pub deterministic: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct DistributionQuery {
+    /// 国家和ASN各返回的条目数
+    pub limit: Option<usize>,
+}
+
+impl IpQuery {
+    /// 开关参数：出现即为true，false 或 0 除外
+    fn flag(value: Option<&str>) -> bool {
+        value.is_some_and(|value| !matches!(value, "false" | "0"))
+    }
+
+    fn is_strict(&self) -> bool {
+        Self::flag(self.strict.as_deref())
+    }
+
+    fn is_deterministic(&self) -> bool {
+        Self::flag(self.deterministic.as_deref())
+    }
+}
+
+impl IpResponse {
+    /// 用配置的固定值替换国家、组织和标签；被替换的字段不再标注数据源
+    fn apply_override(&mut self, rule: &OverrideConfig, options: &ResponseOptions) {
+        if let Some(country) = &rule.country {
+            self.info.country = Some(country.clone());
+            self.info.country_canonical = options.normalize_countries
+                .then(|| country::canonicalize(country, None, &options.languages))
+                .flatten();
+            self.sources.country = None;
+        }
+        if let Some(organization) = &rule.organization {
+            self.info.organization = Some(organization.clone());
+            self.info.organization_original = None;
+            self.sources.organization = None;
+        }
+        self.tags = rule.tags.clone();
+        self.override_prefix = Some(rule.prefix.clone());
+    }
+
+    /// 去掉缓存时间、剩余TTL、过期标记、UTC偏移和耗时，其余字段只取决于数据源内容
+    fn make_deterministic(&mut self) {
+        self.cached = None;
+        self.ttl_seconds = None;
+        self.stale = None;
+        self.stale_reason = None;
+        self.timing = None;
+        self.info.utc_offset = None;
+    }
+}
+
+#[derive(Deserialize)]
+pub struct PageQuery {
+    pub offset: Option<usize>,
+    pub limit: Option<usize>,
+}
+
+#[derive(Deserialize)]
+pub struct ExpiringQuery {
+    /// 统计未来多少秒内过期的条目
+    pub within: Option<u64>,
+}
+
+/// ASN，可以是数字或 "AS13335" 形式的字符串
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub enum AsnInput {
+    Number(u32),
+    Text(String),
+}
+
+impl AsnInput {
+    fn parse(&self) -> Result<u32, String> {
+        match self {
+            AsnInput::Number(asn) => Ok(*asn),
+            AsnInput::Text(text) => text
+                .trim()
+                .trim_start_matches("AS")
+                .trim_start_matches("as")
+                .parse()
+                .map_err(|_| format!("无效的ASN: {}", text)),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct AsnNamesRequest {
+    pub asns: Vec<AsnInput>,
+}
+
+#[derive(Deserialize)]
+pub struct RawQuery {
+    pub fields: Option<String>,
+}
+
+/// 两个IP的比较结果，无法判断的项为None
+#[derive(Serialize, Deserialize)]
+pub struct CompareResponse {
+    pub first: IpInfo,
+    pub second: IpInfo,
+    pub same_asn: Option<bool>,
+    pub same_country: Option<bool>,
+    pub same_prefix: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub distance_km: Option<f64>,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -84,209 +490,1370 @@ pub struct ErrorResponse {
     pub message: String,
 }
 
+impl ErrorResponse {
+    pub(crate) fn into_response_with(status: StatusCode, message: impl Into<String>) -> Response {
+        let response = ErrorResponse {
+            status: "error".to_string(),
+            message: message.into(),
+        };
+        (status, Json(response)).into_response()
+    }
+}
+
+/// RPKI验证结果的缓存时间，ROA变化比地址分配频繁得多
+const RPKI_CACHE_TTL: std::time::Duration = std::time::Duration::from_secs(60 * 60);
+
+/// MessagePack响应的Content-Type
+const MSGPACK_CONTENT_TYPE: &str = "application/msgpack";
+
+/// /stats/cache/expiring 未指定 within 时的统计窗口（秒）
+const DEFAULT_EXPIRING_WITHIN_SECS: u64 = 300;
+
+/// /asn/names 单次请求的ASN数量上限
+const MAX_ASN_NAMES_BATCH: usize = 1000;
+
+/// 按国家导出网段时默认和最大的单页条目数
+const DEFAULT_COUNTRY_NETWORKS_LIMIT: usize = 1000;
+const MAX_COUNTRY_NETWORKS_LIMIT: usize = 100_000;
+
+/// 按国家导出网段时使用的响应类型，每行一个JSON对象
+const NDJSON_CONTENT_TYPE: &str = "application/x-ndjson";
+
+/// 查询分布统计的时间窗口，窗口结束后计数清零
+const DISTRIBUTION_WINDOW: std::time::Duration = std::time::Duration::from_secs(60 * 60);
+
+/// /stats/distribution 默认返回的条目数
+const DEFAULT_DISTRIBUTION_LIMIT: usize = 20;
+
 pub struct IpApiHandler {
-    reader: Arc<tokio::sync::RwLock<MaxmindReader>>,
-    cache: Arc<IpCache>,
+    reader: SharedReader,
+    cache: Arc<dyn CacheBackend>,
+    config: SharedConfig,
+    handle_cache: Arc<tokio::sync::RwLock<KvStore<String, Vec<WhoisObject>>>>,
+    rpki_cache: Arc<tokio::sync::RwLock<KvStore<String, RpkiValidity>>>,
+    upstreams: Arc<dyn Upstreams>,
+    /// 最近的慢请求，容量由 monitoring.slow_request_buffer 决定
+    slow_requests: Arc<std::sync::Mutex<VecDeque<SlowRequest>>>,
+    /// 按国家和ASN统计的查询分布
+    distribution: Arc<Distribution>,
+    /// 正在后台刷新的IP，避免重复刷新
+    refreshing: Arc<std::sync::Mutex<HashSet<String>>>,
+    /// 各API Key的配额用量
+    api_usage: Arc<ApiKeyUsage>,
+    /// 按IP的查询次数和时间，容量由 monitoring.ip_history_size 决定
+    query_history: Arc<QueryHistory>,
+    /// 查询结果的发布目标，未配置时为None
+    publisher: Option<LookupPublisher>,
 }
 
 impl IpApiHandler {
-    pub fn new(reader: Arc<tokio::sync::RwLock<MaxmindReader>>, cache: Arc<IpCache>) -> Self {
-        Self { reader, cache }
+    /// data_dir 为句柄和RPKI缓存文件所在目录
+    pub fn new(reader: SharedReader, cache: Arc<dyn CacheBackend>, config: SharedConfig, data_dir: &std::path::Path) -> Self {
+        let handle_cache = KvStore::create_shared(data_dir.join("handle_cache.bin"));
+        let rpki_cache = Arc::new(tokio::sync::RwLock::new(
+            KvStore::new(data_dir.join("rpki_cache.bin")).with_ttl(RPKI_CACHE_TTL),
+        ));
+        let upstreams = Arc::new(LiveUpstreams::new(config.clone()));
+        let slow_requests = Arc::new(std::sync::Mutex::new(VecDeque::new()));
+        let distribution = Arc::new(Distribution::new(DISTRIBUTION_WINDOW));
+        let refreshing = Arc::new(std::sync::Mutex::new(HashSet::new()));
+        let api_usage = Arc::new(ApiKeyUsage::new());
+        let query_history = Arc::new(QueryHistory::default());
+        Self { reader, cache, config, handle_cache, rpki_cache, upstreams, slow_requests, distribution, refreshing, api_usage, query_history, publisher: None }
+    }
+    
+    /// 每次 /ip 查询后将结果发布到消息总线
+    pub fn with_publisher(mut self, publisher: Option<LookupPublisher>) -> Self {
+        self.publisher = publisher;
+        self
+    }
+    
+    /// 替换外部数据源（用于测试）
+    #[cfg(test)]
+    pub fn with_upstreams(mut self, upstreams: Arc<dyn Upstreams>) -> Self {
+        self.upstreams = upstreams;
+        self
+    }
+    
+    /// 查询前校验目标：配置为拒绝时的私有/保留地址，以及过大的网段
+    async fn check_lookup_target(&self, input: &str) -> Result<(), Response> {
+        self.validate_lookup_target(input)
+            .await
+            .map_err(|(status, message)| ErrorResponse::into_response_with(status, message))
+    }
+    
+    async fn validate_lookup_target(&self, input: &str) -> Result<(), (StatusCode, String)> {
+        if is_reserved_ip(input)
+            && self.config.read().await.maxmind.private_ip_behavior == PrivateIpBehavior::Reject {
+            return Err((StatusCode::UNPROCESSABLE_ENTITY, format!("不支持查询私有或保留地址: {}", input)));
+        }
+        self.check_prefix_limit(input).await
+    }
+    
+    /// 拒绝比配置的最短前缀更大的网段（如 0.0.0.0/0），这类查询没有意义且浪费上游配额
+    async fn check_prefix_limit(&self, input: &str) -> Result<(), (StatusCode, String)> {
+        let Ok(network) = input.parse::<ipnet::IpNet>() else {
+            return Ok(());
+        };
+        let lookup = self.config.read().await.lookup.clone();
+        let min_len = match network {
+            ipnet::IpNet::V4(_) => lookup.min_prefix_v4,
+            ipnet::IpNet::V6(_) => lookup.min_prefix_v6,
+        };
+        if network.prefix_len() < min_len {
+            return Err((
+                StatusCode::UNPROCESSABLE_ENTITY,
+                format!("前缀 /{} 过大，最短允许 /{}", network.prefix_len(), min_len),
+            ));
+        }
+        Ok(())
+    }
+    
+    /// 校验管理接口令牌（X-Admin-Token 请求头）
+    async fn check_admin_token(&self, headers: &HeaderMap) -> Result<(), Response> {
+        let config = self.config.read().await.clone();
+        let Some(expected) = config.app.admin_token.as_deref().filter(|t| !t.is_empty()) else {
+            return Err(ErrorResponse::into_response_with(StatusCode::FORBIDDEN, "未配置管理令牌，管理接口不可用"));
+        };
+        let provided = headers.get("x-admin-token").map(|v| v.as_bytes()).unwrap_or_default();
+        if !constant_time_eq(provided, expected.as_bytes()) {
+            return Err(ErrorResponse::into_response_with(StatusCode::UNAUTHORIZED, "管理令牌无效"));
+        }
+        Ok(())
+    }
+
+    /// 校验查询接口的API Key（Authorization: Bearer <key>）并计入配额
+    async fn check_api_key(
+        axum::extract::State(state): axum::extract::State<Arc<Self>>,
+        request: axum::extract::Request,
+        next: axum::middleware::Next,
+    ) -> Response {
+        let config = state.config.read().await.clone();
+        let api_keys = &config.api_keys;
+        if api_keys.keys.is_empty() {
+            return next.run(request).await;
+        }
+        let provided = request.headers()
+            .get(axum::http::header::AUTHORIZATION)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("Bearer "))
+            .map(str::trim);
+        let Some(provided) = provided else {
+            if api_keys.allow_anonymous {
+                return next.run(request).await;
+            }
+            return ErrorResponse::into_response_with(StatusCode::UNAUTHORIZED, "缺少API Key");
+        };
+        let Some(key) = api_keys.keys.iter().find(|key| constant_time_eq(key.key.as_bytes(), provided.as_bytes())) else {
+            return ErrorResponse::into_response_with(StatusCode::UNAUTHORIZED, "API Key无效");
+        };
+        if let Err(exceeded) = state.api_usage.try_acquire(key) {
+            let window = if exceeded.window == "minute" { "每分钟" } else { "每天" };
+            let mut response = ErrorResponse::into_response_with(
+                StatusCode::TOO_MANY_REQUESTS,
+                format!("API Key {} 已超出{}请求配额", key.name, window),
+            );
+            response.headers_mut().insert(axum::http::header::RETRY_AFTER, HeaderValue::from(exceeded.retry_after_secs));
+            return response;
+        }
+        next.run(request).await
     }
 
+    /// 合并公开查询接口和运维接口（用于测试）
+    #[cfg(test)]
     pub fn router(self) -> Router {
-        Router::new()
-            .route("/ip/:ip", get(Self::get_ip_info))
-            .route("/stats/cache", get(Self::get_cache_stats))
-            .with_state(Arc::new(self))
+        let (public, admin) = self.into_routers(&EndpointsConfig::default());
+        public.merge(admin)
+    }
+    
+    /// 拆分为公开查询接口和运维接口（健康检查、缓存统计与管理），两者共享同一状态
+    /// 只注册 endpoints 中启用的路由分组；GET路由同时响应HEAD请求（axum自动处理，返回相同响应头和空响应体）
+    pub fn into_routers(self, endpoints: &EndpointsConfig) -> (Router, Router) {
+        // 句柄反查缓存的加载、持久化与过期清理
+        tokio::spawn(KvStore::start_background_tasks(
+            self.handle_cache.clone(),
+            std::time::Duration::from_secs(60 * 10),
+        ));
+        tokio::spawn(KvStore::start_background_tasks(
+            self.rpki_cache.clone(),
+            std::time::Duration::from_secs(60 * 10),
+        ));
+        
+        let state = Arc::new(self);
+        let mut public = Router::new();
+        // 首页中列出的公开接口
+        let mut listed = Vec::new();
+        if endpoints.is_enabled(EndpointGroup::Lookup) {
+            public = public
+                .route("/ip/:ip", get(Self::get_ip_info))
+                .route("/ip/:ip/raw", get(Self::get_ip_raw))
+                .route("/compare/:ip1/:ip2", get(Self::compare_ips));
+            listed.extend(["/ip/:ip", "/ip/:ip/raw", "/compare/:ip1/:ip2"]);
+        }
+        if endpoints.is_enabled(EndpointGroup::Whois) {
+            public = public.route("/handle/:handle", get(Self::get_handle_resources));
+            listed.push("/handle/:handle");
+        }
+        if endpoints.is_enabled(EndpointGroup::Asn) {
+            public = public
+                .route("/rpki/:asn/*prefix", get(Self::get_rpki_validity))
+                .route("/asn/names", post(Self::resolve_asn_names));
+            listed.extend(["/rpki/:asn/*prefix", "/asn/names"]);
+        }
+        // 浏览器直接访问时的首页和图标，避免产生404日志
+        let listed = Arc::new(listed);
+        public = public
+            .route("/", get(move |state| Self::get_index(state, listed.clone())))
+            .route("/favicon.ico", get(|| async { StatusCode::NO_CONTENT }));
+        let mut admin = Router::new().route("/healthz", get(Self::get_health));
+        if endpoints.is_enabled(EndpointGroup::Metrics) {
+            admin = admin
+                .route("/stats/cache", get(Self::get_cache_stats))
+                .route("/stats/cache/expiring", get(Self::get_cache_expiring))
+                .route("/stats/slow", get(Self::get_slow_requests))
+                .route("/stats/distribution", get(Self::get_distribution))
+                .route("/stats/api-keys", get(Self::get_api_key_usage))
+                .route("/stats/ip/:ip", get(Self::get_ip_history));
+        }
+        if endpoints.is_enabled(EndpointGroup::CacheAdmin) {
+            admin = admin.route("/cache/cleanup", post(Self::cleanup_cache));
+        }
+        if endpoints.is_enabled(EndpointGroup::Admin) {
+            admin = admin
+                .route("/admin/update-databases", post(Self::update_databases))
+                .route("/admin/databases", get(Self::get_databases))
+                .route("/admin/countries/:code/networks", get(Self::get_country_networks));
+        }
+        let public = public
+            .route_layer(axum::middleware::from_fn_with_state(state.clone(), Self::check_api_key))
+            .with_state(state.clone());
+        let admin = admin.with_state(state);
+        (public, admin)
     }
 
+    /// 服务名称、版本和可用的查询接口
+    async fn get_index(
+        axum::extract::State(state): axum::extract::State<Arc<Self>>,
+        endpoints: Arc<Vec<&'static str>>,
+    ) -> impl IntoResponse {
+        #[derive(Serialize)]
+        struct Index {
+            name: String,
+            version: &'static str,
+            endpoints: Vec<&'static str>,
+        }
+        
+        let name = state.config.read().await.app.name.clone();
+        Json(Index {
+            name,
+            version: env!("CARGO_PKG_VERSION"),
+            endpoints: endpoints.to_vec(),
+        })
+    }
+    
     async fn get_ip_info(
         Path(ip): Path<String>,
+        Query(query): Query<IpQuery>,
+        headers: HeaderMap,
         axum::extract::State(state): axum::extract::State<Arc<Self>>,
     ) -> impl IntoResponse {
-        // 获取当前时间戳
-        let now = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap_or_default()
-            .as_secs();
-            
-        // 首先尝试从缓存获取
-        if let Some(cached_info) = state.cache.get(&ip).await {
-            info!("从缓存获取IP信息: {}", ip);
-            let response = Self::create_response_from_ip_info(&cached_info, Some(now));
-            return (StatusCode::OK, Json(response)).into_response();
+        let prefer = match query.prefer.as_deref().map(DataSource::parse).transpose() {
+            Ok(prefer) => prefer,
+            Err(e) => return ErrorResponse::into_response_with(StatusCode::BAD_REQUEST, e),
+        };
+        let config = state.config.read().await.clone();
+        if query.is_deterministic() && !config.response.allow_deterministic {
+            return ErrorResponse::into_response_with(StatusCode::BAD_REQUEST, "未开启 response.allow_deterministic");
+        }
+        let options = ResponseOptions::from_config(&config)
+            .with_languages(query.lang.as_deref(), &headers)
+            .with_pages(&query)
+            .with_prefer(prefer)
+            .with_max_age(query.max_age);
+        if ip.contains(',') {
+            return state.get_multiple(&ip, &query, &config, &options).await;
+        }
+        if let Err(response) = state.check_lookup_target(&ip).await {
+            return response;
         }
         
-        // 缓存未命中，从MaxMind查询
-        let reader = state.reader.read().await;
-        
-        match reader.lookup(&ip) {
-            Ok(mut info) => {
-                // 并发请求所有后端信息
-                let ip_cloned = ip.clone();
-                let whois_future = async {
-                    if info.whois_info.is_none() {
-                        match WhoisClient::lookup(&ip_cloned) {
-                            Ok(whois_info) => Some(whois_info),
-                            Err(e) => {
-                                warn!("获取WHOIS信息失败 {}: {}", ip_cloned, e);
-                                None
-                            }
-                        }
-                    } else {
-                        None
-                    }
-                };
-                
-                let bgp_tools_future = async {
-                    if info.bgp_info.is_none() {
-                        match BgpToolsClient::lookup(&ip_cloned).await {
-                            Ok(bgp_info) => Some(bgp_info),
-                            Err(e) => {
-                                warn!("获取BGP Tools信息失败 {}: {}", ip_cloned, e);
-                                None
-                            }
-                        }
-                    } else {
-                        None
-                    }
-                };
-                
-                let bgp_api_future = async {
-                    if info.bgp_api_info.is_none() {
-                        match BgpApiClient::query(&ip_cloned).await {
-                            Ok(bgp_result) => Some(bgp_result),
-                            Err(e) => {
-                                warn!("获取BGP API信息失败 {}: {}", ip_cloned, e);
-                                debug!("获取BGP API信息失败详情 {}: {:?}", ip_cloned, e);
-                                None
-                            }
-                        }
-                    } else {
-                        None
-                    }
-                };
-                
-                // 并发执行所有请求
-                let (whois_result, bgp_tools_result, bgp_api_result) = tokio::join!(
-                    whois_future,
-                    bgp_tools_future,
-                    bgp_api_future
-                );
-                
-                // 处理查询结果
-                if let Some(whois_info) = whois_result {
-                    info.whois_info = Some(whois_info);
+        if let Some(at) = query.at.as_deref() {
+            let at = match parse_timestamp(at) {
+                Ok(at) => at,
+                Err(e) => return ErrorResponse::into_response_with(StatusCode::BAD_REQUEST, e),
+            };
+            return match state.resolve_historical(&ip, at, &options).await {
+                Ok(response) => Self::render(response, &query, &headers),
+                Err(e) => ErrorResponse::into_response_with(StatusCode::BAD_REQUEST, e),
+            };
+        }
+        
+        let override_rule = Self::find_override(&config.overrides, &ip);
+        if let Some(rule) = override_rule.filter(|rule| rule.replace) {
+            let info = crate::maxmind::reader::IpInfo { ip: ip.clone(), ..Default::default() };
+            let mut response = Self::create_response_from_ip_info(&info, None, &options);
+            response.apply_override(rule, &options);
+            return Self::render(response, &query, &headers);
+        }
+        
+        match state.resolve_ip(&ip, &options).await {
+            Ok(response) if query.is_strict() && response.unallocated == Some(true) => {
+                ErrorResponse::into_response_with(StatusCode::NOT_FOUND, format!("地址未分配: {}", ip))
+            }
+            Ok(mut response) => {
+                if let Some(rule) = override_rule {
+                    response.apply_override(rule, &options);
                 }
-                
-                if let Some(bgp_info) = bgp_tools_result {
-                    info.bgp_info = Some(bgp_info);
+                if let Some(depth) = query.upstream_depth.filter(|depth| *depth > 1)
+                    && let Some(bgp) = response.bgp_info.as_mut() {
+                    let tree = upstream_tree(state.upstreams.as_ref(), bgp.asn.as_deref(), &bgp.upstreams, depth).await;
+                    bgp.upstream_tree = Some(tree);
                 }
-                
-                if let Some(bgp_result) = bgp_api_result {
-                    info.bgp_api_info = Some(bgp_result.clone());
-                    
-                    // 处理RPKI查询
-                    if let Some(meta) = info.bgp_api_info.as_ref().unwrap().meta.iter().find(|m| m.origin_asns.is_some()) {
-                        if let (Some(prefix), Some(asns)) = (Some(&info.bgp_api_info.as_ref().unwrap().prefix), &meta.origin_asns) {
-                            info!("准备执行RPKI查询, prefix={}, ASNs={:?}", prefix, asns);
-                            
-                            // 并发查询所有ASN的RPKI信息
-                            let rpki_futures = asns.iter().map(|asn| {
-                                let prefix = prefix.clone();
-                                let asn = asn.clone();
-                                async move {
-                                    let rpki_client = RpkiClient::new("http://rpki.akae.re");
-                                    info!("发送RPKI请求: prefix={}, asn={}", prefix, asn);
-                                    match rpki_client.query(&prefix, &asn).await {
-                                        Ok(validity) => Some(validity),
-                                        Err(e) => {
-                                            warn!("RPKI查询失败 {}: {}", asn, e);
-                                            None
-                                        }
-                                    }
-                                }
-                            }).collect::<Vec<_>>();
-                            
-                            // 等待所有RPKI查询完成
-                            let rpki_results = join_all(rpki_futures).await;
-                            
-                            // 收集有效的RPKI结果
-                            info.rpki_info_list = rpki_results
-                                .into_iter()
-                                .filter_map(|r| r)
-                                .collect();
-                        }
-                    }
+                if query.is_deterministic() {
+                    response.make_deterministic();
                 }
-                
-                // 构建响应
-                let response = Self::create_response_from_ip_info(&info, None);
-                
-                // 将结果存入缓存
-                if let Err(e) = state.cache.set(&ip, info).await {
-                    warn!("无法缓存IP信息 {}: {}", ip, e);
+                if let Some(publisher) = &state.publisher {
+                    publisher.publish(&response);
                 }
-                
-                (StatusCode::OK, Json(response)).into_response()
-            },
-            Err(e) => {
-                let response = ErrorResponse {
-                    status: "error".to_string(),
-                    message: e,
-                };
-                
-                (StatusCode::BAD_REQUEST, Json(response)).into_response()
+                // 同一IP的结果只在数据库重新构建或缓存条目刷新后才会变化
+                let build_epoch = state.reader.load().build_epochs().into_values().max().unwrap_or(0);
+                let last_modified = response.data_time.map(|data_time| data_time.max(build_epoch));
+                if let Some(last_modified) = last_modified
+                    && not_modified_since(&headers, last_modified) {
+                    return (StatusCode::NOT_MODIFIED, [(axum::http::header::LAST_MODIFIED, http_date(last_modified))]).into_response();
+                }
+                let timing = response.timing.take();
+                let mut rendered = Self::render(response, &query, &headers);
+                if let Some(timing) = timing
+                    && let Ok(value) = HeaderValue::from_str(&timing.header_value()) {
+                    rendered.headers_mut().insert(HeaderName::from_static("server-timing"), value);
+                }
+                if let Some(last_modified) = last_modified
+                    && rendered.status() == StatusCode::OK
+                    && let Ok(value) = HeaderValue::from_str(&http_date(last_modified)) {
+                    rendered.headers_mut().insert(axum::http::header::LAST_MODIFIED, value);
+                }
+                rendered
             }
+            Err(e) => ErrorResponse::into_response_with(StatusCode::BAD_REQUEST, e),
         }
     }
     
-    fn create_response_from_ip_info(info: &crate::maxmind::reader::IpInfo, cached_timestamp: Option<u64>) -> IpResponse {
-        let ip_info = IpInfo {
-            ip: info.ip.clone(),
-            ip_range: info.ip_range.clone(),
-            country: info.country.clone(),
-            city: info.city.clone(),
-            asn: info.asn,
-            organization: info.organization.clone(),
-        };
+    /// GET /ip/1.1.1.1,8.8.8.8：并发查询多个地址，返回以地址为键的对象，单个地址出错时该键为错误对象
+    /// 始终输出JSON；?format= / ?compat= / ?upstream_depth= 只适用于单个地址
+    async fn get_multiple(self: &Arc<Self>, input: &str, query: &IpQuery, config: &Config, options: &ResponseOptions) -> Response {
+        let mut targets: Vec<&str> = Vec::new();
+        for target in input.split(',').map(str::trim).filter(|target| !target.is_empty()) {
+            if !targets.contains(&target) {
+                targets.push(target);
+            }
+        }
+        let max = config.lookup.max_ips_per_request;
+        if targets.len() > max {
+            return ErrorResponse::into_response_with(StatusCode::BAD_REQUEST, format!("一次最多查询 {} 个地址", max));
+        }
         
-        let mut whois_info = None;
-        let mut bgp_info = None;
+        let results = futures::future::join_all(targets.iter().map(|target| async move {
+            self.validate_lookup_target(target).await.map_err(|(_, message)| message)?;
+            let override_rule = Self::find_override(&config.overrides, target);
+            // 与单个查询一致，替换模式的覆盖规则不查询数据源
+            let mut response = if override_rule.is_some_and(|rule| rule.replace) {
+                let info = crate::maxmind::reader::IpInfo { ip: target.to_string(), ..Default::default() };
+                Self::create_response_from_ip_info(&info, None, options)
+            } else {
+                let response = self.resolve_ip(target, options).await?;
+                if query.is_strict() && response.unallocated == Some(true) {
+                    return Err(format!("地址未分配: {}", target));
+                }
+                response
+            };
+            if let Some(rule) = override_rule {
+                response.apply_override(rule, options);
+            }
+            if query.is_deterministic() {
+                response.make_deterministic();
+            }
+            Ok(response)
+        }))
+        .await;
         
-        // 添加WHOIS信息（如果有）
-        if let Some(whois) = &info.whois_info {
-            whois_info = Some(WhoisInfoResponse {
-                netname: whois.netname.clone(),
-                descr: whois.descr.clone(),
-                country: whois.country.clone(),
-                org: whois.org.clone(),
-                admin: whois.admin_c.clone(),
-                maintainer: whois.mnt_by.clone(),
-            });
+        let mut body = serde_json::Map::new();
+        for (target, result) in targets.into_iter().zip(results) {
+            let value = match result {
+                Ok(response) => serde_json::to_value(response),
+                Err(message) => serde_json::to_value(ErrorResponse { status: "error".to_string(), message }),
+            };
+            body.insert(target.to_string(), value.unwrap_or_default());
+        }
+        (StatusCode::OK, Json(body)).into_response()
+    }
+    
+    /// 分别查询两个IP并比较所属AS、国家、宣告前缀及地理距离
+    async fn compare_ips(
+        Path((ip1, ip2)): Path<(String, String)>,
+        axum::extract::State(state): axum::extract::State<Arc<Self>>,
+    ) -> impl IntoResponse {
+        let options = ResponseOptions::from_config(&state.config.read().await.clone());
+        for ip in [&ip1, &ip2] {
+            if let Err(response) = state.check_lookup_target(ip).await {
+                return response;
+            }
         }
         
-        // 添加BGP Tools信息（如果有）
-        if let Some(bgp) = &info.bgp_info {
-            bgp_info = Some(BgpInfoResponse {
-                asn: bgp.asn.clone(),
-                prefix: bgp.prefix.clone(),
-                country: bgp.country.clone(),
+        let (first, second) = tokio::join!(
+            state.resolve_ip(&ip1, &options),
+            state.resolve_ip(&ip2, &options),
+        );
+        let (first, second) = match (first, second) {
+            (Ok(first), Ok(second)) => (first, second),
+            (Err(e), _) | (_, Err(e)) => {
+                return ErrorResponse::into_response_with(StatusCode::BAD_REQUEST, e);
+            }
+        };
+        
+        fn same<T: PartialEq>(a: Option<T>, b: Option<T>) -> Option<bool> {
+            Some(a? == b?)
+        }
+        
+        let distance_km = match (first.info.latitude, first.info.longitude, second.info.latitude, second.info.longitude) {
+            (Some(lat1), Some(lon1), Some(lat2), Some(lon2)) => Some(haversine_km(lat1, lon1, lat2, lon2)),
+            _ => None,
+        };
+        
+        let response = CompareResponse {
+            same_asn: same(first.info.asn, second.info.asn),
+            same_country: same(first.info.country.as_ref(), second.info.country.as_ref()),
+            same_prefix: same(first.bgp_prefix.as_ref(), second.bgp_prefix.as_ref()),
+            distance_km,
+            first: first.info,
+            second: second.info,
+        };
+        
+        (StatusCode::OK, Json(response)).into_response()
+    }
+    
+    /// 查询IP信息（优先使用缓存，未命中时查询MaxMind并补充外部数据）并构建响应
+    #[tracing::instrument(name = "lookup", skip_all, fields(ip = %log_ip(ip)))]
+    async fn resolve_ip(self: &Arc<Self>, ip: &str, options: &ResponseOptions) -> Result<IpResponse, String> {
+        // 获取当前时间戳
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        
+        if let Some(capacity) = self.config.read().await.monitoring.ip_history_size {
+            self.query_history.record(ip, capacity);
+        }
+        
+        // 首先尝试从缓存获取
+        let cache_start = Instant::now();
+        let mut cached = self.cache.get_with_ttl(ip).await;
+        let mut timing = ServerTiming { cache: cache_start.elapsed(), ..Default::default() };
+        if let Some(max_age) = options.max_age
+            && cached.as_ref().is_some_and(|hit| hit.age_secs >= max_age) {
+            info!("缓存条目已达 max_age={}，重新查询: {}", max_age, log_ip(ip));
+            cached = None;
+        }
+        if let Some(CacheHit { info: mut cached_info, ttl_remaining, stale, age_secs }) = cached {
+            info!(cache_hit = true, stale, "从缓存获取IP信息: {}", log_ip(ip));
+            // 已过期但仍在保留窗口内：先返回旧数据，同时在后台刷新
+            if stale {
+                self.spawn_refresh(ip);
+            }
+            // 缓存键可能是截断后的网段，返回时使用实际查询的IP
+            cached_info.ip = ip.to_string();
+            self.distribution.record(cached_info.country_code.as_deref(), cached_info.asn);
+            self.notify_webhooks(&cached_info).await;
+            let mut response = Self::create_response_from_ip_info(&cached_info, Some(now), options);
+            response.ttl_seconds = Some(ttl_remaining);
+            response.stale = stale.then_some(true);
+            response.timing = Some(timing);
+            response.data_time = Some(now.saturating_sub(age_secs));
+            return Ok(response);
+        }
+        
+        // 缓存未命中，从MaxMind查询
+        let request_start = Instant::now();
+        let maxmind_start = Instant::now();
+        let mut info = match self.reader.load().lookup(ip) {
+            Ok(info) => info,
+            Err(e) => return self.upstream_unavailable_fallback(ip, options, now).await.ok_or(e),
+        };
+        let maxmind_ms = maxmind_start.elapsed().as_millis() as u64;
+        let timings = self.enrich(ip, &mut info).await;
+        if has_no_data(&info)
+            && let Some(response) = self.upstream_unavailable_fallback(ip, options, now).await {
+            return Ok(response);
+        }
+        self.distribution.record(info.country_code.as_deref(), info.asn);
+        self.notify_webhooks(&info).await;
+        info!(
+            cache_hit = false,
+            maxmind_ms,
+            whois_ms = timings.whois_ms,
+            bgp_tools_ms = timings.bgp_tools_ms,
+            bgp_api_ms = timings.bgp_api_ms,
+            dnsbl_ms = timings.dnsbl_ms,
+            rpki_ms = timings.rpki_ms,
+            related_prefixes_ms = timings.related_prefixes_ms,
+            "IP查询完成: {}", log_ip(ip)
+        );
+        self.record_if_slow(ip, request_start.elapsed().as_millis() as u64, maxmind_ms, &timings).await;
+        
+        // 构建响应
+        timing.maxmind_ms = Some(maxmind_ms);
+        timing.whois_ms = Some(timings.whois_ms);
+        timing.bgp_ms = Some(timings.bgp_tools_ms.max(timings.bgp_api_ms));
+        timing.rpki_ms = Some(timings.rpki_ms);
+        let mut response = Self::create_response_from_ip_info(&info, None, options);
+        response.ttl_seconds = Some(self.cache.ttl().await.as_secs());
+        response.timing = Some(timing);
+        response.data_time = Some(now);
+        
+        // 将结果存入缓存；WHOIS被限速时结果不完整，不缓存，下次请求重新查询
+        if timings.whois_rate_limited {
+            info!("WHOIS被限速，跳过缓存: {}", log_ip(ip));
+        } else if let Err(e) = self.cache.set(ip, info).await {
+            warn!("无法缓存IP信息 {}: {}", log_ip(ip), e);
+        }
+        
+        Ok(response)
+    }
+    
+    /// 新查询完全失败时返回宽限期内已硬过期的缓存条目，不写回缓存，避免覆盖旧数据
+    async fn upstream_unavailable_fallback(&self, ip: &str, options: &ResponseOptions, now: u64) -> Option<IpResponse> {
+        let CacheHit { mut info, age_secs, .. } = self.cache.get_expired(ip).await?;
+        warn!("数据源均不可用，返回已过期的缓存: {}", log_ip(ip));
+        // 与缓存命中时相同：缓存键可能是截断后的网段，返回时使用实际查询的IP
+        info.ip = ip.to_string();
+        let mut response = Self::create_response_from_ip_info(&info, Some(now), options);
+        response.ttl_seconds = Some(0);
+        response.stale = Some(true);
+        response.stale_reason = Some("upstream_unavailable".to_string());
+        response.data_time = Some(now.saturating_sub(age_secs));
+        Some(response)
+    }
+    
+    /// 从列表文件预加载缓存（每行一个IP或CIDR），已缓存的跳过，单行失败只记录日志
+    /// 返回新写入缓存的数量
+    pub async fn preload(&self, path: &str, concurrency: usize) -> Result<usize, String> {
+        let content = tokio::fs::read_to_string(path)
+            .await
+            .map_err(|e| format!("读取预加载文件失败 ({}): {}", path, e))?;
+        let targets: Vec<&str> = content
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .collect();
+        info!("开始预加载缓存，共 {} 条", targets.len());
+        
+        let total = targets.len();
+        let processed = std::sync::atomic::AtomicUsize::new(0);
+        let loaded = std::sync::atomic::AtomicUsize::new(0);
+        futures::stream::iter(targets)
+            .for_each_concurrent(concurrency, |target| {
+                let processed = &processed;
+                let loaded = &loaded;
+                async move {
+                    match self.preload_one(target).await {
+                        Ok(true) => {
+                            loaded.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                        }
+                        Ok(false) => {}
+                        Err(e) => warn!("预加载失败 {}: {}", log_ip(target), e),
+                    }
+                    let done = processed.fetch_add(1, std::sync::atomic::Ordering::Relaxed) + 1;
+                    if done.is_multiple_of(100) || done == total {
+                        info!("预加载进度: {}/{}", done, total);
+                    }
+                }
+            })
+            .await;
+        Ok(loaded.into_inner())
+    }
+    
+    /// 查询单个目标并写入缓存，已缓存时返回 false
+    async fn preload_one(&self, target: &str) -> Result<bool, String> {
+        QueryTarget::parse(target)?;
+        if self.cache.contains(target).await {
+            return Ok(false);
+        }
+        let mut info = self.reader.load().lookup(target)?;
+        let report = self.enrich(target, &mut info).await;
+        if report.whois_rate_limited {
+            return Err("WHOIS被限速".to_string());
+        }
+        self.cache.set(target, info).await?;
+        Ok(true)
+    }
+    
+    /// 在后台重新查询并更新缓存，同一IP同时只有一个刷新任务
+    fn spawn_refresh(self: &Arc<Self>, ip: &str) {
+        if !self.refreshing.lock().unwrap_or_else(|e| e.into_inner()).insert(ip.to_string()) {
+            return;
+        }
+        let handler = self.clone();
+        let ip = ip.to_string();
+        tokio::spawn(async move {
+            match handler.reader.load().lookup(&ip) {
+                Ok(mut info) => {
+                    let report = handler.enrich(&ip, &mut info).await;
+                    if report.whois_rate_limited {
+                        info!("WHOIS被限速，后台刷新结果不写入缓存: {}", log_ip(&ip));
+                    } else if let Err(e) = handler.cache.set(&ip, info).await {
+                        warn!("后台刷新缓存失败 {}: {}", log_ip(&ip), e);
+                    }
+                }
+                Err(e) => warn!("后台刷新查询失败 {}: {}", log_ip(&ip), e),
+            }
+            handler.refreshing.lock().unwrap_or_else(|e| e.into_inner()).remove(&ip);
+        });
+    }
+    
+    /// 查询结果命中Webhook规则时在后台推送通知
+    async fn notify_webhooks(&self, info: &crate::maxmind::reader::IpInfo) {
+        let config = self.config.read().await.clone();
+        webhook::notify(info, &config.webhook, &config.risk);
+    }
+    
+    /// 总耗时超过 monitoring.slow_request_ms 时输出警告并记入慢请求缓冲区
+    async fn record_if_slow(&self, ip: &str, total_ms: u64, maxmind_ms: u64, timings: &EnrichReport) {
+        let monitoring = self.config.read().await.monitoring.clone();
+        let Some(threshold_ms) = monitoring.slow_request_ms else {
+            return;
+        };
+        if total_ms < threshold_ms {
+            return;
+        }
+        let ip = log_ip(ip);
+        warn!(
+            total_ms,
+            maxmind_ms,
+            whois_ms = timings.whois_ms,
+            bgp_tools_ms = timings.bgp_tools_ms,
+            bgp_api_ms = timings.bgp_api_ms,
+            dnsbl_ms = timings.dnsbl_ms,
+            rpki_ms = timings.rpki_ms,
+            "慢请求: {} 耗时 {}ms，超过阈值 {}ms", ip, total_ms, threshold_ms
+        );
+        let entry = SlowRequest {
+            ip,
+            timestamp: Utc::now().timestamp() as u64,
+            total_ms,
+            maxmind_ms,
+            whois_ms: timings.whois_ms,
+            bgp_tools_ms: timings.bgp_tools_ms,
+            bgp_api_ms: timings.bgp_api_ms,
+            dnsbl_ms: timings.dnsbl_ms,
+            rpki_ms: timings.rpki_ms,
+        };
+        let mut slow_requests = self.slow_requests.lock().unwrap_or_else(|e| e.into_inner());
+        slow_requests.push_back(entry);
+        while slow_requests.len() > monitoring.slow_request_buffer {
+            slow_requests.pop_front();
+        }
+    }
+    
+    /// 查询指定时间点的数据，结果不读写缓存
+    /// 目前只有路由状态（RIPEstat）支持历史查询，其余数据源在 errors 中说明
+    async fn resolve_historical(&self, ip: &str, at: DateTime<Utc>, options: &ResponseOptions) -> Result<IpResponse, String> {
+        let info = self.reader.load().lookup(ip)?;
+        let target = QueryTarget::parse(ip)?;
+        
+        let mut response = Self::create_response_from_ip_info(&info, None, options);
+        // 风险评分依赖当前的上游数据，历史查询中不输出
+        response.risk_score = None;
+        response.risk_factors.clear();
+        match self.upstreams.bgp_history(&target.prefix, at).await {
+            Ok(routing) => response.historical_routing = Some(routing),
+            Err(e) => {
+                warn!("获取历史路由信息失败 {}: {}", log_ip(ip), e);
+                response.errors.push(format!("historical_routing: {}", e));
+            }
+        }
+        response.errors.extend(
+            ["geolocation: 地理位置为当前数据库数据", "whois: 不支持历史查询", "bgp_tools: 不支持历史查询", "rpki: 不支持历史查询", "dnsbl: 不支持历史查询"]
+                .iter()
+                .map(|note| note.to_string()),
+        );
+        Ok(response)
+    }
+    
+    /// 按请求的格式输出响应；未指定 ?format= 时按 Accept 请求头选择（浏览器得到HTML页面），默认JSON
+    fn render(response: IpResponse, query: &IpQuery, headers: &HeaderMap) -> Response {
+        if let Some(compat) = query.compat.as_deref() {
+            return Self::render_compat(&response, compat);
+        }
+        let accept = headers
+            .get(axum::http::header::ACCEPT)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or_default();
+        let negotiated = if accept.contains(MSGPACK_CONTENT_TYPE) || accept.contains("application/x-msgpack") {
+            Some("msgpack")
+        } else if accept.contains("text/html") {
+            Some("html")
+        } else {
+            None
+        };
+        match query.format.as_deref().or(negotiated) {
+            None | Some("json") => (StatusCode::OK, Json(response)).into_response(),
+            Some("html") => Html(html::render_ip_page(&response)).into_response(),
+            Some("geojson") => Self::render_geojson(&response),
+            Some("msgpack") => Self::render_msgpack(&response),
+            Some(other) => ErrorResponse::into_response_with(StatusCode::BAD_REQUEST, format!("不支持的格式: {}", other)),
+        }
+    }
+    
+    /// 按 ipinfo.io 或 ip-api.com 的结构输出JSON
+    fn render_compat(response: &IpResponse, compat: &str) -> Response {
+        let info = &response.info;
+        // info.country 可能是本地化名称，统一换算为代码和英文名
+        let country = info.country.as_deref().and_then(|value| country::canonicalize(value, None, &[]));
+        let as_org = match (info.asn, &info.organization) {
+            (Some(asn), Some(organization)) => Some(format!("AS{} {}", asn, organization)),
+            (Some(asn), None) => Some(format!("AS{}", asn)),
+            (None, organization) => organization.clone(),
+        };
+        match compat {
+            "ipinfo" => Json(IpinfoCompat {
+                ip: info.ip.clone(),
+                city: info.city.clone(),
+                country: country.map(|c| c.code),
+                loc: info.latitude.zip(info.longitude).map(|(lat, lon)| format!("{:.4},{:.4}", lat, lon)),
+                org: as_org,
+                timezone: info.time_zone.clone(),
+                anycast: info.is_anycast,
+            }).into_response(),
+            "ip-api" => Json(IpApiComCompat {
+                status: "success",
+                country: country.as_ref().and_then(|c| c.name_en.clone()).unwrap_or_default(),
+                country_code: country.map(|c| c.code).unwrap_or_default(),
+                city: info.city.clone().unwrap_or_default(),
+                lat: info.latitude,
+                lon: info.longitude,
+                timezone: info.time_zone.clone().unwrap_or_default(),
+                isp: info.organization.clone().unwrap_or_default(),
+                org: info.organization.clone().unwrap_or_default(),
+                as_name: as_org.unwrap_or_default(),
+                query: info.ip.clone(),
+            }).into_response(),
+            other => ErrorResponse::into_response_with(StatusCode::BAD_REQUEST, format!("不支持的兼容格式: {}（可选 ipinfo、ip-api）", other)),
+        }
+    }
+    
+    /// 输出MessagePack（字段名保留为map键，与JSON结构一致）
+    fn render_msgpack(response: &IpResponse) -> Response {
+        match rmp_serde::to_vec_named(response) {
+            Ok(body) => (StatusCode::OK, [(axum::http::header::CONTENT_TYPE, MSGPACK_CONTENT_TYPE)], body).into_response(),
+            Err(e) => ErrorResponse::into_response_with(StatusCode::INTERNAL_SERVER_ERROR, format!("序列化MessagePack失败: {}", e)),
+        }
+    }
+    
+    /// 输出GeoJSON Point要素，便于直接在地图中使用
+    fn render_geojson(response: &IpResponse) -> Response {
+        let info = &response.info;
+        let (Some(latitude), Some(longitude)) = (info.latitude, info.longitude) else {
+            return ErrorResponse::into_response_with(StatusCode::NOT_FOUND, format!("IP {} 没有可用的坐标信息", info.ip));
+        };
+        
+        let feature = serde_json::json!({
+            "type": "Feature",
+            "geometry": {
+                "type": "Point",
+                "coordinates": [longitude, latitude],
+            },
+            "properties": {
+                "ip": info.ip,
+                "country": info.country,
+                "city": info.city,
+                "asn": info.asn,
+                "organization": info.organization,
+            },
+        });
+        
+        (StatusCode::OK, [(axum::http::header::CONTENT_TYPE, "application/geo+json")], feature.to_string()).into_response()
+    }
+    
+    /// 并发查询WHOIS、BGP Tools、BGP API及RPKI信息并填充到IpInfo
+    /// CIDR输入时，WHOIS和BGP Tools使用网络地址，BGP API使用规范化后的前缀
+    async fn enrich(&self, ip: &str, info: &mut crate::maxmind::reader::IpInfo) -> EnrichReport {
+        let mut timings = EnrichReport::default();
+        let whois_rate_limited = std::sync::atomic::AtomicBool::new(false);
+        let upstreams = self.upstreams.as_ref();
+        let target = QueryTarget::parse(ip).unwrap_or_else(|_| QueryTarget {
+            address: ip.to_string(),
+            prefix: ip.to_string(),
+        });
+        let address = target.address.as_str();
+        let prefix = target.prefix.as_str();
+        
+        let whois_future = async {
+            if info.whois_info.is_none() {
+                match upstreams.whois(address).await {
+                    Ok(whois_info) => Some(whois_info),
+                    Err(e) => {
+                        warn!("获取WHOIS信息失败 {}: {}", log_ip(ip), e);
+                        whois_rate_limited.store(e.starts_with(whois_client::RATE_LIMITED), std::sync::atomic::Ordering::Relaxed);
+                        None
+                    }
+                }
+            } else {
+                None
+            }
+        };
+        
+        let bgp_tools_future = async {
+            if info.bgp_info.is_none() {
+                match upstreams.bgp_tools(address).await {
+                    Ok(bgp_info) => Some(bgp_info),
+                    Err(e) => {
+                        warn!("获取BGP Tools信息失败 {}: {}", log_ip(ip), e);
+                        None
+                    }
+                }
+            } else {
+                None
+            }
+        };
+        
+        let bgp_api_future = async {
+            if info.bgp_api_info.is_none() {
+                match upstreams.bgp_api(prefix).await {
+                    Ok(bgp_result) => Some(bgp_result),
+                    Err(e) => {
+                        warn!("获取BGP API信息失败 {}: {}", log_ip(ip), e);
+                        debug!("获取BGP API信息失败详情 {}: {:?}", log_ip(ip), e);
+                        None
+                    }
+                }
+            } else {
+                None
+            }
+        };
+        
+        let related_prefixes_future = async {
+            if !info.related_prefixes.is_empty() {
+                return None;
+            }
+            match upstreams.related_prefixes(prefix).await {
+                Ok(related) => Some(related),
+                Err(e) => {
+                    warn!("获取相关前缀失败 {}: {}", log_ip(ip), e);
+                    None
+                }
+            }
+        };
+        
+        // DNS黑名单只适用于单个IP
+        let dnsbl_future = async {
+            if ip.contains('/') {
+                return None;
+            }
+            match upstreams.dnsbl(address).await {
+                Some(Ok(listed)) => Some(listed),
+                Some(Err(e)) => {
+                    warn!("DNSBL查询失败 {}: {}", log_ip(ip), e);
+                    None
+                }
+                None => None,
+            }
+        };
+        
+        // 并发执行所有请求
+        let (
+            (whois_result, whois_ms),
+            (bgp_tools_result, bgp_tools_ms),
+            (bgp_api_result, bgp_api_ms),
+            (dnsbl_result, dnsbl_ms),
+            (related_prefixes_result, related_prefixes_ms),
+        ) = tokio::join!(
+            timed("whois", whois_future),
+            timed("bgp_tools", bgp_tools_future),
+            timed("bgp_api", bgp_api_future),
+            timed("dnsbl", dnsbl_future),
+            timed("related_prefixes", related_prefixes_future)
+        );
+        timings.whois_ms = whois_ms;
+        timings.bgp_tools_ms = bgp_tools_ms;
+        timings.bgp_api_ms = bgp_api_ms;
+        timings.dnsbl_ms = dnsbl_ms;
+        timings.related_prefixes_ms = related_prefixes_ms;
+        timings.whois_rate_limited = whois_rate_limited.into_inner();
+        
+        if let Some(listed) = dnsbl_result {
+            info.dnsbl = listed;
+        }
+        
+        if let Some(related) = related_prefixes_result {
+            info.related_prefixes = related;
+        }
+        
+        // 处理查询结果
+        if let Some(whois_info) = whois_result {
+            info.whois_info = Some(whois_info);
+        }
+        
+        if let Some(bgp_info) = bgp_tools_result {
+            info.bgp_info = Some(bgp_info);
+        }
+        
+        if let Some(bgp_result) = bgp_api_result {
+            info.bgp_api_info = Some(bgp_result);
+            
+            // 处理RPKI查询
+            if let Some(bgp_api_info) = &info.bgp_api_info
+                && let Some(meta) = bgp_api_info.meta.iter().find(|m| m.origin_asns.is_some())
+                && let Some(asns) = &meta.origin_asns {
+                let prefix = &bgp_api_info.prefix;
+                // BGP API可能重复报告同一个源AS，去重后再验证
+                let mut asns = asns.clone();
+                asns.sort_by_key(|asn| asn_sort_key(asn));
+                asns.dedup();
+                info!("准备执行RPKI查询, prefix={}, ASNs={:?}", prefix, asns);
+                
+                // 验证器支持时批量验证，否则并发查询每个ASN；同时向额外验证器查询用于交叉验证
+                let ((rpki_info_list, extra_verdicts), rpki_ms) = timed("rpki", async {
+                    tokio::join!(upstreams.rpki_batch(prefix, &asns), upstreams.rpki_cross_check(prefix, &asns))
+                }).await;
+                timings.rpki_ms = rpki_ms;
+                if !extra_verdicts.is_empty() {
+                    let validator_url = self.config.read().await.rpki.validator_url.clone();
+                    info.rpki_cross_checks = cross_check_rpki(prefix, &asns, &validator_url, &rpki_info_list, extra_verdicts);
+                }
+                info.rpki_info_list = sort_rpki_results(rpki_info_list);
+            }
+        }
+        
+        timings
+    }
+    
+    /// 仅查询本地MaxMind数据库，不发起任何网络请求，也不读写缓存
+    /// 可通过 ?fields=asn,country,city 只查询需要的数据库
+    async fn get_ip_raw(
+        Path(ip): Path<String>,
+        Query(query): Query<RawQuery>,
+        axum::extract::State(state): axum::extract::State<Arc<Self>>,
+    ) -> impl IntoResponse {
+        let fields = match query.fields.as_deref() {
+            Some(fields) => match LookupFields::parse(fields) {
+                Ok(fields) => fields,
+                Err(e) => return ErrorResponse::into_response_with(StatusCode::BAD_REQUEST, e),
+            },
+            None => LookupFields::ALL,
+        };
+        if let Err(response) = state.check_lookup_target(&ip).await {
+            return response;
+        }
+        
+        let reader = state.reader.load();
+        match reader.lookup_with(&ip, fields) {
+            Ok(info) => {
+                let ip_info = IpInfo {
+                    ip: info.ip,
+                    ip_range: info.ip_range,
+                    country: info.country,
+                    country_canonical: None,
+                    city: info.city,
+                    city_is_approximate: None,
+                    latitude: info.latitude,
+                    longitude: info.longitude,
+                    utc_offset: info.time_zone.as_deref().and_then(|tz| timezone::utc_offset(tz, Utc::now())),
+                    time_zone: info.time_zone,
+                    is_anycast: info.is_anycast,
+                    embedded_mac: info.embedded_mac,
+                    mac_vendor: info.mac_vendor,
+                    confidence: info.confidence,
+                    user_type: info.user_type,
+                    asn: info.asn,
+                    organization: info.organization,
+                    organization_original: info.organization_original,
+                };
+                (StatusCode::OK, Json(ip_info)).into_response()
+            },
+            Err(e) => ErrorResponse::into_response_with(StatusCode::BAD_REQUEST, e),
+        }
+    }
+    
+    /// 通过WHOIS反查获取与维护者/联系人句柄关联的资源
+    async fn get_handle_resources(
+        Path(handle): Path<String>,
+        axum::extract::State(state): axum::extract::State<Arc<Self>>,
+    ) -> impl IntoResponse {
+        #[derive(Serialize)]
+        struct HandleResponse {
+            handle: String,
+            objects: Vec<WhoisObject>,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            cached: Option<u64>,
+        }
+        
+        let key = handle.to_uppercase();
+        if let Some(objects) = state.handle_cache.read().await.get(&key) {
+            info!("从缓存获取句柄反查结果: {}", handle);
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs();
+            let response = HandleResponse { handle, objects, cached: Some(now) };
+            return (StatusCode::OK, Json(response)).into_response();
+        }
+        
+        let query_handle = handle.clone();
+        let permit = crate::utils::upstream::acquire_permit().await;
+        let result = tokio::task::spawn_blocking(move || WhoisClient::inverse_lookup(&query_handle)).await;
+        drop(permit);
+        match result {
+            Ok(Ok(objects)) => {
+                if let Err(e) = state.handle_cache.write().await.set(key, objects.clone()) {
+                    warn!("无法缓存句柄反查结果 {}: {}", handle, e);
+                }
+                let response = HandleResponse { handle, objects, cached: None };
+                (StatusCode::OK, Json(response)).into_response()
+            },
+            Ok(Err(e)) => ErrorResponse::into_response_with(StatusCode::BAD_REQUEST, e),
+            Err(e) => ErrorResponse::into_response_with(StatusCode::INTERNAL_SERVER_ERROR, format!("WHOIS反查任务失败: {}", e)),
+        }
+    }
+    
+    /// 批量查询ASN的组织名称（来自MaxMind ASN数据库），用于补全没有名称的上游AS列表
+    async fn resolve_asn_names(
+        axum::extract::State(state): axum::extract::State<Arc<Self>>,
+        Json(request): Json<AsnNamesRequest>,
+    ) -> impl IntoResponse {
+        #[derive(Serialize)]
+        struct AsnNames {
+            names: BTreeMap<u32, String>,
+            #[serde(skip_serializing_if = "Vec::is_empty")]
+            unknown: Vec<u32>,
+        }
+        
+        if request.asns.len() > MAX_ASN_NAMES_BATCH {
+            return ErrorResponse::into_response_with(
+                StatusCode::BAD_REQUEST,
+                format!("单次最多查询 {} 个ASN", MAX_ASN_NAMES_BATCH),
+            );
+        }
+        let asns = match request.asns.iter().map(AsnInput::parse).collect::<Result<BTreeSet<u32>, String>>() {
+            Ok(asns) => asns.into_iter().collect::<Vec<_>>(),
+            Err(e) => return ErrorResponse::into_response_with(StatusCode::BAD_REQUEST, e),
+        };
+        
+        // 第一次查询需要遍历ASN数据库建立索引
+        let reader = state.reader.load_full();
+        let lookup_asns = asns.clone();
+        match tokio::task::spawn_blocking(move || reader.asn_names(&lookup_asns)).await {
+            Ok(Ok(found)) => {
+                let unknown = asns.into_iter().filter(|asn| !found.contains_key(asn)).collect();
+                let names = found.into_iter().collect();
+                (StatusCode::OK, Json(AsnNames { names, unknown })).into_response()
+            }
+            Ok(Err(e)) => ErrorResponse::into_response_with(StatusCode::SERVICE_UNAVAILABLE, e),
+            Err(e) => ErrorResponse::into_response_with(StatusCode::INTERNAL_SERVER_ERROR, format!("ASN名称查询任务失败: {}", e)),
+        }
+    }
+    
+    /// 直接查询前缀+源AS的RPKI验证结果，prefix 可直接包含斜杠（如 /rpki/13335/1.1.1.0/24）
+    async fn get_rpki_validity(
+        Path((asn, prefix)): Path<(String, String)>,
+        axum::extract::State(state): axum::extract::State<Arc<Self>>,
+    ) -> impl IntoResponse {
+        let asn = asn.trim_start_matches("AS").trim_start_matches("as");
+        if asn.is_empty() || asn.parse::<u32>().is_err() {
+            return ErrorResponse::into_response_with(StatusCode::BAD_REQUEST, format!("无效的ASN: {}", asn));
+        }
+        let prefix = match prefix.trim_start_matches('/').parse::<ipnet::IpNet>() {
+            Ok(network) => network.trunc().to_string(),
+            Err(e) => return ErrorResponse::into_response_with(StatusCode::BAD_REQUEST, format!("无效的前缀: {}", e)),
+        };
+        
+        let key = format!("{}|{}", prefix, asn);
+        if let Some(validity) = state.rpki_cache.read().await.get(&key) {
+            info!("从缓存获取RPKI验证结果: {} AS{}", prefix, asn);
+            return (StatusCode::OK, Json(validity)).into_response();
+        }
+        
+        match state.upstreams.rpki(&prefix, asn).await {
+            Ok(validity) => {
+                if let Err(e) = state.rpki_cache.write().await.set(key, validity.clone()) {
+                    warn!("无法缓存RPKI验证结果 {} AS{}: {}", prefix, asn, e);
+                }
+                (StatusCode::OK, Json(validity)).into_response()
+            }
+            Err(e) => ErrorResponse::into_response_with(StatusCode::BAD_GATEWAY, e),
+        }
+    }
+    
+    /// 查询目标所在的最长前缀覆盖规则；查询CIDR时规则须完整包含该网段
+    fn find_override<'a>(overrides: &'a [OverrideConfig], ip: &str) -> Option<&'a OverrideConfig> {
+        let target = QueryTarget::parse(ip).ok()?.prefix.parse::<ipnet::IpNet>().ok()?;
+        overrides
+            .iter()
+            .filter_map(|rule| rule.network().ok().map(|network| (rule, network)))
+            .filter(|(_, network)| network.contains(&target))
+            .max_by_key(|(_, network)| network.prefix_len())
+            .map(|(rule, _)| rule)
+    }
+    
+    /// 任播判断：MaxMind标记为任播，或地址位于已知任播前缀内；两者都无法判断时为None
+    fn detect_anycast(info: &crate::maxmind::reader::IpInfo, prefixes: &[ipnet::IpNet]) -> Option<bool> {
+        let addr = QueryTarget::parse(&info.ip)
+            .ok()
+            .and_then(|target| target.address.parse::<std::net::IpAddr>().ok());
+        let listed = addr.is_some_and(|addr| prefixes.iter().any(|p| p.contains(&addr)));
+        if listed || info.is_anycast == Some(true) {
+            Some(true)
+        } else {
+            info.is_anycast
+        }
+    }
+    
+    fn create_response_from_ip_info(info: &crate::maxmind::reader::IpInfo, cached_timestamp: Option<u64>, options: &ResponseOptions) -> IpResponse {
+        let mut city = language::pick_name(&info.city_names, &options.languages).or_else(|| info.city.clone());
+        let mut city_is_approximate = None;
+        if city.is_none() && options.approximate_city && info.latitude.is_some() && info.longitude.is_some() {
+            city = language::pick_name(&info.subdivision_names, &options.languages).or_else(|| info.subdivision.clone());
+            city_is_approximate = city.as_ref().map(|_| true);
+        }
+        
+        // 按优先级选取第一个提供了值的数据源；WHOIS和BGP Tools只有国家代码
+        let mut sources = FieldSources::default();
+        let mut country = None;
+        let mut organization = None;
+        for source in options.prefer.priority() {
+            let (source_country, source_organization) = match source {
+                DataSource::Maxmind => (
+                    language::pick_name(&info.country_names, &options.languages).or_else(|| info.country.clone()),
+                    info.organization.clone(),
+                ),
+                DataSource::Whois => match &info.whois_info {
+                    Some(whois) => (whois.country.clone(), whois.org.clone().or_else(|| whois.netname.clone())),
+                    None => (None, None),
+                },
+                DataSource::Bgp => match &info.bgp_info {
+                    Some(bgp) => (bgp.country.clone(), bgp.as_name.clone()),
+                    None => (None, None),
+                },
+            };
+            if country.is_none() && source_country.is_some() {
+                country = source_country;
+                sources.country = Some(source);
+            }
+            if organization.is_none() && source_organization.is_some() {
+                organization = source_organization;
+                sources.organization = Some(source);
+            }
+        }
+        
+        // MaxMind的国家名称已本地化，统一格式时改用其国家代码
+        let maxmind_country = info.country_code.as_deref().map(|code| (code, &info.country_names));
+        let canonical_country = |value: Option<&String>| {
+            options.normalize_countries
+                .then(|| country::canonicalize(value?, maxmind_country, &options.languages))
+                .flatten()
+        };
+        let country_canonical = match sources.country {
+            Some(DataSource::Maxmind) => canonical_country(info.country_code.as_ref()),
+            _ => canonical_country(country.as_ref()),
+        };
+        
+        let ip_info = IpInfo {
+            ip: info.ip.clone(),
+            ip_range: info.ip_range.clone(),
+            country,
+            country_canonical,
+            city,
+            city_is_approximate,
+            latitude: info.latitude,
+            longitude: info.longitude,
+            time_zone: info.time_zone.clone(),
+            utc_offset: info.time_zone.as_deref().and_then(|tz| timezone::utc_offset(tz, Utc::now())),
+            is_anycast: Self::detect_anycast(info, &options.anycast_prefixes),
+            embedded_mac: info.embedded_mac.clone(),
+            mac_vendor: info.mac_vendor.clone(),
+            confidence: info.confidence.clone(),
+            user_type: info.user_type.clone(),
+            asn: info.asn,
+            organization,
+            organization_original: match sources.organization {
+                Some(DataSource::Maxmind) => info.organization_original.clone(),
+                _ => None,
+            },
+        };
+        
+        let mut whois_info = None;
+        let mut bgp_info = None;
+        
+        // 添加WHOIS信息（如果有）
+        if let Some(whois) = &info.whois_info {
+            whois_info = Some(WhoisInfoResponse {
+                netname: whois.netname.clone(),
+                descr: whois.descr.clone(),
+                country: whois.country.clone(),
+                country_canonical: canonical_country(whois.country.as_ref()),
+                org: whois.org.clone(),
+                admin: whois.admin_c.clone(),
+                maintainer: whois.mnt_by.clone(),
+                route: whois.route.clone(),
+                origin: whois.origin.clone(),
+                extra: whois.extra.clone().into_iter().collect(),
+            });
+        }
+        
+        // 添加BGP Tools信息（如果有）
+        if let Some(bgp) = &info.bgp_info {
+            let (upstreams, upstreams_next_offset) = options.upstreams_page.apply(&bgp.upstreams);
+            bgp_info = Some(BgpInfoResponse {
+                asn: bgp.asn.clone(),
+                prefix: bgp.prefix.clone(),
+                country: bgp.country.clone(),
+                country_canonical: canonical_country(bgp.country.as_ref()),
                 registry: bgp.registry.clone(),
                 allocated: bgp.allocated.clone(),
                 as_name: bgp.as_name.clone(),
-                upstreams: bgp.upstreams.clone(),
+                upstreams,
+                upstreams_total: bgp.upstreams.len(),
+                upstreams_next_offset,
+                upstream_tree: None,
             });
         }
         
+        // 根据已收集的信号计算风险评分
+        let (risk_score, risk_factors) = if options.risk.enabled {
+            let (score, factors) = risk::score(info, &options.risk);
+            (Some(score), factors)
+        } else {
+            (None, Vec::new())
+        };
+        
+        let (related_prefixes, related_prefixes_next_offset) = options.related_prefixes_page.apply(&info.related_prefixes);
         IpResponse {
             info: ip_info,
             whois_info,
             bgp_info,
+            bgp_prefix: info.bgp_api_info.as_ref().map(|bgp_api| bgp_api.prefix.clone()),
+            bgp_api_raw: info.bgp_api_info.as_ref().and_then(|bgp_api| bgp_api.raw_response.clone()),
+            related_prefixes,
+            related_prefixes_total: (!info.related_prefixes.is_empty()).then_some(info.related_prefixes.len()),
+            related_prefixes_next_offset,
             rpki_info_list: info.rpki_info_list.clone(),
+            rpki_cross_checks: info.rpki_cross_checks.clone(),
+            dnsbl: info.dnsbl.clone(),
+            risk_score,
+            risk_factors,
+            historical_routing: None,
+            unallocated: is_unallocated(info).then_some(true),
+            tags: Vec::new(),
+            override_prefix: None,
+            warnings: if options.source_warnings { source_disagreements(info) } else { Vec::new() },
+            errors: Vec::new(),
             cached: cached_timestamp,
+            ttl_seconds: None,
+            stale: None,
+            stale_reason: None,
+            sources,
+            timing: None,
+            data_time: None,
+        }
+    }
+    
+    async fn get_health(
+        axum::extract::State(state): axum::extract::State<Arc<Self>>,
+    ) -> impl IntoResponse {
+        #[derive(Serialize)]
+        struct Health {
+            status: &'static str,
+            databases_loaded: bool,
+            self_test_passed: bool,
+        }
+        
+        let reader = state.reader.load();
+        let databases_loaded = reader.is_loaded();
+        let self_test_passed = reader.self_test_passed();
+        let (status_code, status) = if databases_loaded && self_test_passed {
+            (StatusCode::OK, "ok")
+        } else {
+            (StatusCode::SERVICE_UNAVAILABLE, "degraded")
+        };
+        
+        (status_code, Json(Health { status, databases_loaded, self_test_passed })).into_response()
+    }
+    
+    /// 返回最近的慢请求（最新的在前），需要管理令牌
+    async fn get_slow_requests(
+        headers: HeaderMap,
+        axum::extract::State(state): axum::extract::State<Arc<Self>>,
+    ) -> impl IntoResponse {
+        if let Err(response) = state.check_admin_token(&headers).await {
+            return response;
+        }
+        let slow_requests: Vec<SlowRequest> = state.slow_requests
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .iter()
+            .rev()
+            .cloned()
+            .collect();
+        (StatusCode::OK, Json(slow_requests)).into_response()
+    }
+    
+    /// 返回单个IP的查询次数和首末查询时间，需要管理令牌
+    async fn get_ip_history(
+        Path(ip): Path<String>,
+        headers: HeaderMap,
+        axum::extract::State(state): axum::extract::State<Arc<Self>>,
+    ) -> impl IntoResponse {
+        if let Err(response) = state.check_admin_token(&headers).await {
+            return response;
+        }
+        if state.config.read().await.monitoring.ip_history_size.is_none() {
+            return ErrorResponse::into_response_with(StatusCode::NOT_FOUND, "未开启 monitoring.ip_history_size");
+        }
+        match state.query_history.get(&ip) {
+            Some(history) => (StatusCode::OK, Json(history)).into_response(),
+            None => ErrorResponse::into_response_with(StatusCode::NOT_FOUND, format!("没有 {} 的查询记录", log_ip(&ip))),
+        }
+    }
+    
+    /// 返回当前窗口内查询最多的国家和ASN，需要管理令牌
+    async fn get_distribution(
+        Query(query): Query<DistributionQuery>,
+        headers: HeaderMap,
+        axum::extract::State(state): axum::extract::State<Arc<Self>>,
+    ) -> impl IntoResponse {
+        if let Err(response) = state.check_admin_token(&headers).await {
+            return response;
+        }
+        let limit = query.limit.unwrap_or(DEFAULT_DISTRIBUTION_LIMIT);
+        (StatusCode::OK, Json(state.distribution.snapshot(limit))).into_response()
+    }
+    
+    /// 各API Key的配额和当前用量（按名称，不输出Key本身），需要管理令牌
+    async fn get_api_key_usage(
+        headers: HeaderMap,
+        axum::extract::State(state): axum::extract::State<Arc<Self>>,
+    ) -> impl IntoResponse {
+        if let Err(response) = state.check_admin_token(&headers).await {
+            return response;
+        }
+        
+        #[derive(Serialize)]
+        struct KeyStats {
+            per_minute: Option<u32>,
+            per_day: Option<u32>,
+            #[serde(flatten)]
+            usage: crate::utils::api_keys::KeyUsage,
         }
+        
+        let keys = state.config.read().await.api_keys.keys.clone();
+        let mut usage = state.api_usage.snapshot();
+        let stats: BTreeMap<String, KeyStats> = keys
+            .into_iter()
+            .map(|key| {
+                let usage = usage.remove(&key.name).unwrap_or_default();
+                (key.name, KeyStats { per_minute: key.per_minute, per_day: key.per_day, usage })
+            })
+            .collect();
+        (StatusCode::OK, Json(stats)).into_response()
     }
     
     async fn get_cache_stats(
@@ -307,4 +1874,1190 @@ impl IpApiHandler {
         
         (StatusCode::OK, Json(stats)).into_response()
     }
-} 
\ No newline at end of file
+    
+    /// 未来一段时间内将过期的缓存条目数量，用于预估即将到来的未命中查询量
+    async fn get_cache_expiring(
+        Query(query): Query<ExpiringQuery>,
+        axum::extract::State(state): axum::extract::State<Arc<Self>>,
+    ) -> impl IntoResponse {
+        #[derive(Serialize)]
+        struct CacheExpiring {
+            within_secs: u64,
+            entries: usize,
+        }
+        
+        let within_secs = query.within.unwrap_or(DEFAULT_EXPIRING_WITHIN_SECS);
+        let entries = state.cache.expiring_within(std::time::Duration::from_secs(within_secs)).await;
+        (StatusCode::OK, Json(CacheExpiring { within_secs, entries })).into_response()
+    }
+    
+    /// 手动触发过期缓存清理（需要管理令牌）
+    async fn cleanup_cache(
+        headers: HeaderMap,
+        axum::extract::State(state): axum::extract::State<Arc<Self>>,
+    ) -> impl IntoResponse {
+        if let Err(response) = state.check_admin_token(&headers).await {
+            return response;
+        }
+        
+        let removed = state.cache.cleanup().await;
+        info!("手动清理了 {} 条过期缓存条目", removed);
+        
+        #[derive(Serialize)]
+        struct CleanupResult {
+            removed: usize,
+        }
+        
+        (StatusCode::OK, Json(CleanupResult { removed })).into_response()
+    }
+    
+    /// 已加载数据库的元数据（类型、构建时间、IP版本、节点数、可用语言），需要管理令牌
+    async fn get_databases(
+        headers: HeaderMap,
+        axum::extract::State(state): axum::extract::State<Arc<Self>>,
+    ) -> impl IntoResponse {
+        if let Err(response) = state.check_admin_token(&headers).await {
+            return response;
+        }
+        (StatusCode::OK, Json(state.reader.load().databases_metadata())).into_response()
+    }
+
+    /// 流式导出国家数据库中归属某国家的网段（NDJSON，每行一个网段），需要管理令牌
+    /// 最后一行为 {"returned": N, "next_offset": M}，next_offset 存在时表示还有更多网段
+    async fn get_country_networks(
+        Path(code): Path<String>,
+        Query(query): Query<PageQuery>,
+        headers: HeaderMap,
+        axum::extract::State(state): axum::extract::State<Arc<Self>>,
+    ) -> impl IntoResponse {
+        if let Err(response) = state.check_admin_token(&headers).await {
+            return response;
+        }
+        if code.len() != 2 || !code.chars().all(|c| c.is_ascii_alphabetic()) {
+            return ErrorResponse::into_response_with(StatusCode::BAD_REQUEST, format!("无效的国家代码: {}", code));
+        }
+        let reader = state.reader.load_full();
+        if !reader.has_country_database() {
+            return ErrorResponse::into_response_with(StatusCode::SERVICE_UNAVAILABLE, "国家数据库未加载");
+        }
+        let offset = query.offset.unwrap_or(0);
+        let limit = query.limit.unwrap_or(DEFAULT_COUNTRY_NETWORKS_LIMIT).min(MAX_COUNTRY_NETWORKS_LIMIT);
+        
+        // 在阻塞线程中遍历数据库，逐行发送；客户端断开后停止遍历
+        let (tx, rx) = tokio::sync::mpsc::channel::<String>(256);
+        tokio::task::spawn_blocking(move || {
+            let mut returned = 0;
+            let mut has_more = false;
+            let result = reader.country_networks(&code, offset, |prefix| {
+                if returned == limit {
+                    has_more = true;
+                    return false;
+                }
+                returned += 1;
+                tx.blocking_send(format!("{}\n", serde_json::json!({ "prefix": prefix }))).is_ok()
+            });
+            let summary = match result {
+                Ok(()) => serde_json::json!({
+                    "returned": returned,
+                    "next_offset": has_more.then_some(offset + returned),
+                }),
+                Err(e) => serde_json::json!({ "returned": returned, "error": e }),
+            };
+            let _ = tx.blocking_send(format!("{}\n", summary));
+        });
+        let stream = futures::stream::unfold(rx, |mut rx| async move {
+            rx.recv().await.map(|line| (Ok::<_, std::convert::Infallible>(line), rx))
+        });
+        (
+            [(axum::http::header::CONTENT_TYPE, NDJSON_CONTENT_TYPE)],
+            axum::body::Body::from_stream(stream),
+        ).into_response()
+    }
+
+    /// 立即下载最新的MaxMind数据库并替换读取器，返回新数据库的构建时间
+    async fn update_databases(
+        headers: HeaderMap,
+        axum::extract::State(state): axum::extract::State<Arc<Self>>,
+    ) -> impl IntoResponse {
+        if let Err(response) = state.check_admin_token(&headers).await {
+            return response;
+        }
+        
+        #[derive(Serialize)]
+        struct UpdateResult {
+            status: &'static str,
+            build_epochs: std::collections::BTreeMap<&'static str, u64>,
+        }
+        
+        info!("手动触发MaxMind数据库更新");
+        let maxmind_config = Arc::new(state.config.read().await.maxmind.clone());
+        let mut updater = MaxmindUpdater::new(maxmind_config);
+        match updater.update_and_reload(&state.reader).await {
+            Ok(()) => {
+                let build_epochs = state.reader.load().build_epochs();
+                (StatusCode::OK, Json(UpdateResult { status: "ok", build_epochs })).into_response()
+            }
+            Err(e) if e == UPDATE_IN_PROGRESS => ErrorResponse::into_response_with(StatusCode::CONFLICT, e),
+            Err(e) => {
+                warn!("手动更新MaxMind数据库失败: {}", e);
+                ErrorResponse::into_response_with(StatusCode::BAD_GATEWAY, e)
+            }
+        }
+    }
+}
+
+/// 解析ISO8601时间：完整的RFC3339、不带时区的日期时间（按UTC）或仅日期
+fn parse_timestamp(input: &str) -> Result<DateTime<Utc>, String> {
+    if let Ok(at) = DateTime::parse_from_rfc3339(input) {
+        return Ok(at.with_timezone(&Utc));
+    }
+    if let Ok(at) = NaiveDateTime::parse_from_str(input, "%Y-%m-%dT%H:%M:%S") {
+        return Ok(at.and_utc());
+    }
+    if let Ok(date) = NaiveDate::parse_from_str(input, "%Y-%m-%d")
+        && let Some(at) = date.and_hms_opt(0, 0, 0) {
+        return Ok(at.and_utc());
+    }
+    Err(format!("无效的时间参数: {}", input))
+}
+
+/// 比较令牌时耗时与首个不同字节的位置无关，避免通过响应时间逐字节猜测令牌
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+/// Unix时间戳格式化为HTTP日期（RFC 7231 IMF-fixdate）
+fn http_date(timestamp: u64) -> String {
+    DateTime::<Utc>::from_timestamp(timestamp as i64, 0)
+        .unwrap_or_default()
+        .format("%a, %d %b %Y %H:%M:%S GMT")
+        .to_string()
+}
+
+/// 请求带有 If-Modified-Since 且不早于 last_modified 时为true，无法解析的日期按未带处理
+fn not_modified_since(headers: &HeaderMap, last_modified: u64) -> bool {
+    headers
+        .get(axum::http::header::IF_MODIFIED_SINCE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| DateTime::parse_from_rfc2822(v).ok())
+        .is_some_and(|since| since.timestamp() >= last_modified as i64)
+}
+
+/// 一次补充查询的统计：各上游的耗时（毫秒，未发起的查询为0）及WHOIS是否被限速
+#[derive(Debug, Default)]
+struct EnrichReport {
+    whois_rate_limited: bool,
+    whois_ms: u64,
+    bgp_tools_ms: u64,
+    bgp_api_ms: u64,
+    dnsbl_ms: u64,
+    rpki_ms: u64,
+    related_prefixes_ms: u64,
+}
+
+/// MaxMind和WHOIS、BGP Tools、BGP API都没有提供任何数据
+fn has_no_data(info: &crate::maxmind::reader::IpInfo) -> bool {
+    info.country.is_none()
+        && info.country_code.is_none()
+        && info.asn.is_none()
+        && info.city.is_none()
+        && info.whois_info.is_none()
+        && info.bgp_info.is_none()
+        && info.bgp_api_info.is_none()
+}
+
+/// 超过阈值的请求记录，IP按日志隐私配置处理
+#[derive(Clone, Serialize)]
+struct SlowRequest {
+    ip: String,
+    timestamp: u64,
+    total_ms: u64,
+    maxmind_ms: u64,
+    whois_ms: u64,
+    bgp_tools_ms: u64,
+    bgp_api_ms: u64,
+    dnsbl_ms: u64,
+    rpki_ms: u64,
+}
+
+/// RIPE对非本区域管理或IANA未分配的地址块返回的占位网络名
+const WHOIS_PLACEHOLDER_NETNAMES: [&str; 3] = ["IANA-BLK", "NON-RIPE-NCC-MANAGED-ADDRESS-BLOCK", "ERX-NETBLOCK"];
+
+/// 地址合法但没有任何分配记录：MaxMind无结果，WHOIS只有占位记录，BGP中没有宣告
+/// WHOIS或BGP查询失败（结果缺失）时无法判断，不视为未分配
+fn is_unallocated(info: &crate::maxmind::reader::IpInfo) -> bool {
+    let maxmind_empty = info.asn.is_none() && info.country_code.is_none() && info.city.is_none();
+    let whois_empty = info.whois_info.as_ref().is_some_and(|whois| {
+        let placeholder = whois.netname.as_deref()
+            .is_some_and(|netname| WHOIS_PLACEHOLDER_NETNAMES.iter().any(|p| netname.eq_ignore_ascii_case(p)));
+        whois.route.is_none() && whois.origin.is_none() && (placeholder || (whois.netname.is_none() && whois.country.is_none()))
+    });
+    let bgp_empty = info.bgp_info.as_ref().is_some_and(|bgp| {
+        bgp.asn.as_deref().and_then(|asn| asn.trim().parse::<u32>().ok()).unwrap_or(0) == 0
+    }) && info.bgp_api_info.as_ref().is_none_or(|bgp_api| bgp_api.meta.iter().all(|m| m.origin_asns.as_ref().is_none_or(|asns| asns.is_empty())));
+    maxmind_empty && whois_empty && bgp_empty
+}
+
+/// 比较MaxMind、WHOIS、BGP给出的国家代码和ASN，两两不一致时各生成一条说明
+fn source_disagreements(info: &crate::maxmind::reader::IpInfo) -> Vec<String> {
+    let parse_asn = |asn: &String| asn.trim().trim_start_matches("AS").trim_start_matches("as").parse::<u32>().ok();
+    let countries = [
+        ("MaxMind", info.country_code.clone()),
+        ("WHOIS", info.whois_info.as_ref().and_then(|whois| whois.country.clone())),
+        ("BGP", info.bgp_info.as_ref().and_then(|bgp| bgp.country.clone())),
+    ];
+    let asns = [
+        ("MaxMind", info.asn),
+        ("WHOIS", info.whois_info.as_ref().and_then(|whois| whois.origin.as_ref()).and_then(parse_asn)),
+        ("BGP", info.bgp_info.as_ref().and_then(|bgp| bgp.asn.as_ref()).and_then(parse_asn)),
+    ];
+    let mut warnings = Vec::new();
+    for (i, (source, country)) in countries.iter().enumerate() {
+        for (other_source, other_country) in &countries[i + 1..] {
+            if let (Some(country), Some(other_country)) = (country, other_country)
+                && !country.trim().eq_ignore_ascii_case(other_country.trim()) {
+                warnings.push(format!("{}国家 {} 与{}国家 {} 不一致", source, country, other_source, other_country));
+            }
+        }
+    }
+    for (i, (source, asn)) in asns.iter().enumerate() {
+        for (other_source, other_asn) in &asns[i + 1..] {
+            if let (Some(asn), Some(other_asn)) = (asn, other_asn)
+                && asn != other_asn {
+                warnings.push(format!("{} ASN AS{} 与{} ASN AS{} 不一致", source, asn, other_source, other_asn));
+            }
+        }
+    }
+    warnings
+}
+
+/// 将主验证器的结果与额外验证器的结论按ASN合并，主验证器没有结果的ASN记为查询失败
+fn cross_check_rpki(
+    prefix: &str,
+    asns: &[String],
+    validator_url: &str,
+    primary: &[RpkiValidity],
+    extra: Vec<(String, RpkiVerdict)>,
+) -> Vec<RpkiCrossCheck> {
+    asns.iter()
+        .map(|asn| {
+            let primary_result = primary
+                .iter()
+                .find(|validity| validity.asn == *asn)
+                .cloned()
+                .ok_or_else(|| "主验证器未返回结果".to_string());
+            let verdicts = std::iter::once(RpkiVerdict::from_result(validator_url, primary_result))
+                .chain(extra.iter().filter(|(extra_asn, _)| extra_asn == asn).map(|(_, verdict)| verdict.clone()))
+                .collect();
+            RpkiCrossCheck::new(asn, prefix, verdicts)
+        })
+        .collect()
+}
+
+/// ASN排序键：按数值排序（兼容 AS 前缀），无法解析的排在最后
+fn asn_sort_key(asn: &str) -> (u64, String) {
+    let number = asn.trim().trim_start_matches("AS").trim_start_matches("as").parse().unwrap_or(u64::MAX);
+    (number, asn.to_string())
+}
+
+/// 按ASN和前缀排序并去重，保证相同数据的响应完全一致
+fn sort_rpki_results(mut list: Vec<RpkiValidity>) -> Vec<RpkiValidity> {
+    list.sort_by(|a, b| asn_sort_key(&a.asn).cmp(&asn_sort_key(&b.asn)).then_with(|| a.prefix.cmp(&b.prefix)));
+    list.dedup_by(|a, b| a.asn == b.asn && a.prefix == b.prefix);
+    list
+}
+
+/// 在独立的tracing span中执行上游查询，返回结果及耗时（毫秒）
+async fn timed<F: std::future::Future>(upstream: &'static str, future: F) -> (F::Output, u64) {
+    let start = Instant::now();
+    let output = future.instrument(tracing::info_span!("upstream", upstream)).await;
+    let elapsed_ms = start.elapsed().as_millis() as u64;
+    debug!(upstream, elapsed_ms, "上游查询完成");
+    (output, elapsed_ms)
+}
+
+/// 计算两个坐标之间的大圆距离（公里）
+fn haversine_km(lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> f64 {
+    const EARTH_RADIUS_KM: f64 = 6371.0;
+    let d_lat = (lat2 - lat1).to_radians();
+    let d_lon = (lon2 - lon1).to_radians();
+    let a = (d_lat / 2.0).sin().powi(2)
+        + lat1.to_radians().cos() * lat2.to_radians().cos() * (d_lon / 2.0).sin().powi(2);
+    2.0 * EARTH_RADIUS_KM * a.sqrt().asin()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::utils::upstream::MockUpstreams;
+    use axum::body::Body;
+    use axum::http::Request;
+    use http_body_util::BodyExt;
+    use tokio::sync::RwLock;
+    use tower::ServiceExt;
+
+    fn test_config(database_dir: &str) -> Config {
+        let yaml = format!(r#"
+app:
+  name: test
+  port: 8080
+maxmind:
+  account_id: 1
+  license_key: test
+  update_interval_hours: 24
+  database_dir: "{}"
+  download_urls:
+    asn: https://example.com/asn
+    city: https://example.com/city
+    country: https://example.com/country
+"#, database_dir);
+        serde_yaml::from_str(&yaml).unwrap()
+    }
+
+    fn test_router(dir: &tempfile::TempDir) -> Router {
+        test_handler(dir).router()
+    }
+
+    fn test_handler(dir: &tempfile::TempDir) -> IpApiHandler {
+        let config = test_config(&dir.path().display().to_string());
+        let reader = crate::maxmind::MaxmindReader::new(Arc::new(config.maxmind.clone()));
+        let cache = crate::utils::ip_cache::IpCache::new(
+            dir.path().join("ip_cache.bin"),
+            std::time::Duration::from_secs(3600),
+            crate::utils::kv_store::PersistFormat::default(),
+        );
+        IpApiHandler::new(
+            Arc::new(arc_swap::ArcSwap::from_pointee(reader)),
+            Arc::new(cache),
+            Arc::new(RwLock::new(Arc::new(config))),
+            dir.path(),
+        )
+        .with_upstreams(Arc::new(MockUpstreams))
+    }
+
+    async fn get_json(router: Router, uri: &str) -> (StatusCode, serde_json::Value) {
+        let response = router
+            .oneshot(Request::get(uri).body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let status = response.status();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[tokio::test]
+    async fn ip_lookup_assembles_mocked_upstreams() {
+        let dir = tempfile::tempdir().unwrap();
+        let router = test_router(&dir);
+
+        let (status, body) = get_json(router.clone(), "/ip/1.1.1.1").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["info"]["ip"], "1.1.1.1");
+        assert_eq!(body["whois_info"]["netname"], "APNIC-LABS");
+        assert_eq!(body["whois_info"]["origin"], "AS13335");
+        assert_eq!(body["bgp_info"]["asn"], "13335");
+        assert_eq!(body["bgp_info"]["upstreams"][0]["asn"], "AS174");
+        assert_eq!(body["rpki_info_list"][0]["prefix"], "1.1.1.0/24");
+        assert_eq!(body["rpki_info_list"][0]["validity"], "valid");
+        assert_eq!(body["rpki_cross_checks"][0]["asn"], "13335");
+        assert_eq!(body["rpki_cross_checks"][0]["agree"], true);
+        assert_eq!(body["rpki_cross_checks"][0]["verdicts"].as_array().unwrap().len(), 2);
+        assert_eq!(body["dnsbl"][0], "dnsbl.example.org");
+        assert_eq!(body["info"]["is_anycast"], true);
+        assert_eq!(body["risk_factors"][0], "dnsbl_listed");
+        assert!(body.get("cached").is_none());
+        assert_eq!(body["ttl_seconds"], 3600);
+
+        // 第二次请求命中缓存
+        let (status, body) = get_json(router, "/ip/1.1.1.1").await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body["cached"].is_u64());
+        assert!(body["ttl_seconds"].as_u64().unwrap() <= 3600);
+        assert_eq!(body["whois_info"]["netname"], "APNIC-LABS");
+        assert_eq!(body["related_prefixes"][0]["prefix"], "1.0.0.0/8");
+    }
+
+    #[tokio::test]
+    async fn head_requests_return_headers_without_body() {
+        let dir = tempfile::tempdir().unwrap();
+        let router = test_router(&dir);
+
+        for uri in ["/ip/1.1.1.1", "/healthz"] {
+            let response = router
+                .clone()
+                .oneshot(Request::head(uri).body(Body::empty()).unwrap())
+                .await
+                .unwrap();
+            assert_ne!(response.status(), StatusCode::METHOD_NOT_ALLOWED, "{}", uri);
+            assert!(response.headers().contains_key("content-type"), "{}", uri);
+            let body = response.into_body().collect().await.unwrap().to_bytes();
+            assert!(body.is_empty(), "{}", uri);
+        }
+    }
+
+    #[tokio::test]
+    async fn cidr_lookup_enriches_ipv4_and_ipv6_prefixes() {
+        let dir = tempfile::tempdir().unwrap();
+        let router = test_router(&dir);
+
+        let (status, body) = get_json(router.clone(), "/ip/1.1.1.0%2F24").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["info"]["ip"], "1.1.1.0/24");
+        assert_eq!(body["info"]["ip_range"], "1.1.1.0 - 1.1.1.255");
+        assert_eq!(body["whois_info"]["netname"], "APNIC-LABS");
+
+        let (status, body) = get_json(router, "/ip/2606:4700:4700::%2F48").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["info"]["ip"], "2606:4700:4700::/48");
+        assert_eq!(body["bgp_info"]["asn"], "13335");
+    }
+
+    #[tokio::test]
+    async fn geojson_format_requires_coordinates() {
+        let dir = tempfile::tempdir().unwrap();
+        let (status, body) = get_json(test_router(&dir), "/ip/1.1.1.1?format=geojson").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(body["status"], "error");
+    }
+
+    #[tokio::test]
+    async fn compare_reports_shared_prefix() {
+        let dir = tempfile::tempdir().unwrap();
+        let (status, body) = get_json(test_router(&dir), "/compare/1.1.1.1/1.0.0.1").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["same_prefix"], true);
+        assert!(body["same_asn"].is_null());
+        assert!(body.get("distance_km").is_none());
+    }
+
+    #[test]
+    fn haversine_matches_known_distance() {
+        // 北京到上海约1067公里
+        let distance = haversine_km(39.9042, 116.4074, 31.2304, 121.4737);
+        assert!((distance - 1067.0).abs() < 5.0);
+    }
+
+    #[tokio::test]
+    async fn max_upstreams_truncates_list_and_keeps_total() {
+        let dir = tempfile::tempdir().unwrap();
+        let (status, body) = get_json(test_router(&dir), "/ip/1.1.1.1?max_upstreams=0").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["bgp_info"]["upstreams"].as_array().unwrap().len(), 0);
+        assert_eq!(body["bgp_info"]["upstreams_total"], 1);
+        assert_eq!(body["bgp_info"]["upstreams_next_offset"], 0);
+    }
+
+    #[test]
+    fn pages_are_capped_and_report_next_offset() {
+        let items: Vec<u32> = (0..5).collect();
+        let page = Page::requested(Some(1), Some(10), Some(2));
+        assert_eq!(page.apply(&items), (vec![1, 2], Some(3)));
+        assert_eq!(Page::requested(Some(3), None, Some(2)).apply(&items), (vec![3, 4], None));
+        assert_eq!(Page::requested(None, None, None).apply(&items), (items.clone(), None));
+        assert_eq!(Page::requested(Some(9), Some(1), None).apply(&items), (vec![], None));
+    }
+
+    #[tokio::test]
+    async fn prefer_selects_country_and_organization_source() {
+        let dir = tempfile::tempdir().unwrap();
+        let router = test_router(&dir);
+        let (status, body) = get_json(router.clone(), "/ip/1.1.1.1?prefer=bgp").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["info"]["country"], "US");
+        assert_eq!(body["info"]["organization"], "Cloudflare, Inc.");
+        assert_eq!(body["sources"]["country"], "bgp");
+        
+        let (status, body) = get_json(router.clone(), "/ip/1.1.1.1?prefer=whois").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["info"]["country"], "AU");
+        assert_eq!(body["info"]["organization"], "APNIC-LABS");
+        assert_eq!(body["sources"]["organization"], "whois");
+        
+        let (status, _) = get_json(router, "/ip/1.1.1.1?prefer=geofeed").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn country_falls_back_to_registration_country_without_maxmind() {
+        let dir = tempfile::tempdir().unwrap();
+        // 测试环境未加载MaxMind数据库
+        let (status, body) = get_json(test_router(&dir), "/ip/1.1.1.1").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["info"]["country"], "AU");
+        assert_eq!(body["sources"]["country"], "whois");
+    }
+
+    #[tokio::test]
+    async fn compat_presets_rename_fields() {
+        let dir = tempfile::tempdir().unwrap();
+        let router = test_router(&dir);
+        let (status, body) = get_json(router.clone(), "/ip/1.1.1.1?compat=ipinfo").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["ip"], "1.1.1.1");
+        assert_eq!(body["country"], "AU");
+        assert!(body.get("info").is_none());
+
+        let (status, body) = get_json(router.clone(), "/ip/1.1.1.1?compat=ip-api").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["status"], "success");
+        assert_eq!(body["countryCode"], "AU");
+        assert_eq!(body["country"], "Australia");
+        assert_eq!(body["query"], "1.1.1.1");
+
+        let (status, _) = get_json(router, "/ip/1.1.1.1?compat=ipstack").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn expired_entries_are_served_when_every_source_fails() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = test_config(&dir.path().display().to_string());
+        let reader = crate::maxmind::MaxmindReader::new(Arc::new(config.maxmind.clone()));
+        // TTL为0，写入后立即硬过期，只能通过宽限期读取
+        let cache = crate::utils::ip_cache::IpCache::new(
+            dir.path().join("ip_cache.bin"),
+            std::time::Duration::ZERO,
+            crate::utils::kv_store::PersistFormat::default(),
+        )
+        .with_error_grace(std::time::Duration::from_secs(3600));
+        let info = crate::maxmind::reader::IpInfo { asn: Some(13335), ..Default::default() };
+        cache.set("1.1.1.1", info).await.unwrap();
+        let handler = IpApiHandler::new(
+            Arc::new(arc_swap::ArcSwap::from_pointee(reader)),
+            Arc::new(cache),
+            Arc::new(RwLock::new(Arc::new(config))),
+            dir.path(),
+        )
+        .with_upstreams(Arc::new(crate::utils::upstream::FailingUpstreams));
+        let router = handler.router();
+
+        let (status, body) = get_json(router.clone(), "/ip/1.1.1.1").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["info"]["asn"], 13335);
+        assert_eq!(body["stale"], true);
+        assert_eq!(body["stale_reason"], "upstream_unavailable");
+
+        // 没有旧数据时照常返回空结果
+        let (status, body) = get_json(router, "/ip/8.8.8.8").await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body.get("stale").is_none());
+    }
+
+    #[tokio::test]
+    async fn expired_fallback_reports_the_queried_address() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = test_config(&dir.path().display().to_string());
+        let reader = crate::maxmind::MaxmindReader::new(Arc::new(config.maxmind.clone()));
+        let cache = crate::utils::ip_cache::IpCache::new(
+            dir.path().join("ip_cache.bin"),
+            std::time::Duration::ZERO,
+            crate::utils::kv_store::PersistFormat::default(),
+        )
+        .with_error_grace(std::time::Duration::from_secs(3600))
+        .with_ipv6_grouping(Some(64));
+        let info = crate::maxmind::reader::IpInfo { ip: "2606:4700::1111".to_string(), asn: Some(13335), ..Default::default() };
+        cache.set("2606:4700::1111", info).await.unwrap();
+        let handler = IpApiHandler::new(
+            Arc::new(arc_swap::ArcSwap::from_pointee(reader)),
+            Arc::new(cache),
+            Arc::new(RwLock::new(Arc::new(config))),
+            dir.path(),
+        )
+        .with_upstreams(Arc::new(crate::utils::upstream::FailingUpstreams));
+
+        let router = handler.router();
+
+        let (status, body) = get_json(router.clone(), "/ip/2606:4700::ffff").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["stale_reason"], "upstream_unavailable");
+        assert_eq!(body["info"]["ip"], "2606:4700::ffff");
+        assert_eq!(body["info"]["asn"], 13335);
+
+        // Last-Modified 取自条目的写入时间
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+        let response = router.oneshot(Request::get("/ip/2606:4700::ffff").body(Body::empty()).unwrap()).await.unwrap();
+        let last_modified = response.headers()["last-modified"].to_str().unwrap().to_string();
+        assert!([http_date(now), http_date(now - 1)].contains(&last_modified));
+    }
+
+    #[tokio::test]
+    async fn comma_separated_addresses_are_looked_up_together() {
+        let dir = tempfile::tempdir().unwrap();
+        let router = test_router(&dir);
+        let (status, body) = get_json(router.clone(), "/ip/1.1.1.1,8.8.8.8,not-an-ip,1.1.1.1").await;
+        assert_eq!(status, StatusCode::OK);
+        let entries = body.as_object().unwrap();
+        assert_eq!(entries.len(), 3);
+        assert_eq!(body["1.1.1.1"]["info"]["ip"], "1.1.1.1");
+        assert_eq!(body["8.8.8.8"]["info"]["ip"], "8.8.8.8");
+        assert_eq!(body["not-an-ip"]["status"], "error");
+
+        let too_many = (0..11).map(|i| format!("1.1.1.{}", i)).collect::<Vec<_>>().join(",");
+        let (status, _) = get_json(router, &format!("/ip/{}", too_many)).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn max_age_bypasses_older_cache_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        let router = test_router(&dir);
+        let (_, first) = get_json(router.clone(), "/ip/1.1.1.1").await;
+        assert!(first.get("cached").is_none());
+        let (_, cached) = get_json(router.clone(), "/ip/1.1.1.1?max_age=3600").await;
+        assert!(cached.get("cached").is_some());
+        let (status, refreshed) = get_json(router, "/ip/1.1.1.1?max_age=0").await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(refreshed.get("cached").is_none());
+    }
+
+    #[test]
+    fn unallocated_requires_every_source_to_answer_empty() {
+        let mut info = crate::maxmind::reader::IpInfo {
+            whois_info: Some(crate::utils::whois_client::WhoisInfo {
+                netname: Some("IANA-BLK".to_string()),
+                ..Default::default()
+            }),
+            bgp_info: Some(crate::utils::bgptools_client::BgpToolsInfo::default()),
+            ..Default::default()
+        };
+        assert!(is_unallocated(&info));
+        info.bgp_info = None;
+        assert!(!is_unallocated(&info), "BGP查询失败时无法判断");
+        info.bgp_info = Some(crate::utils::bgptools_client::BgpToolsInfo {
+            asn: Some("13335".to_string()),
+            ..Default::default()
+        });
+        assert!(!is_unallocated(&info));
+    }
+
+    #[tokio::test]
+    async fn deterministic_output_is_byte_identical() {
+        let dir = tempfile::tempdir().unwrap();
+        let handler = test_handler(&dir);
+        let shared_config = handler.config.clone();
+        let router = handler.router();
+        let (status, _) = get_json(router.clone(), "/ip/1.1.1.1?deterministic").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        {
+            let mut config = (**shared_config.read().await).clone();
+            config.response.allow_deterministic = true;
+            *shared_config.write().await = Arc::new(config);
+        }
+        let mut bodies = Vec::new();
+        // 第一次未命中缓存，第二次命中缓存
+        for _ in 0..2 {
+            let response = router.clone()
+                .oneshot(Request::get("/ip/1.1.1.1?deterministic=true").body(Body::empty()).unwrap())
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            assert!(!response.headers().contains_key("server-timing"));
+            bodies.push(response.into_body().collect().await.unwrap().to_bytes());
+        }
+        assert_eq!(bodies[0], bodies[1]);
+        let body: serde_json::Value = serde_json::from_slice(&bodies[0]).unwrap();
+        assert!(body.get("cached").is_none());
+        assert!(body.get("ttl_seconds").is_none());
+    }
+
+    #[tokio::test]
+    async fn upstream_depth_builds_a_tree_without_cycles() {
+        let dir = tempfile::tempdir().unwrap();
+        let (status, body) = get_json(test_router(&dir), "/ip/1.1.1.1?upstream_depth=10").await;
+        assert_eq!(status, StatusCode::OK);
+        let tree = &body["bgp_info"]["upstream_tree"];
+        assert_eq!(tree[0]["asn"], "AS174");
+        // AS13335 是源AS，不再出现
+        let second: Vec<&str> = tree[0]["upstreams"].as_array().unwrap().iter().map(|n| n["asn"].as_str().unwrap()).collect();
+        assert_eq!(second, ["AS3356"]);
+        // 深度被限制为3层，AS3356 的上游中 AS174 已出现过
+        let third = &tree[0]["upstreams"][0]["upstreams"];
+        assert_eq!(third.as_array().unwrap().len(), 1);
+        assert_eq!(third[0]["asn"], "AS1299");
+        assert!(third[0].get("upstreams").is_none());
+
+        let (_, body) = get_json(test_router(&dir), "/ip/1.1.1.1").await;
+        assert!(body["bgp_info"].get("upstream_tree").is_none());
+    }
+
+    #[tokio::test]
+    async fn overrides_use_the_longest_matching_prefix() {
+        let dir = tempfile::tempdir().unwrap();
+        let handler = test_handler(&dir);
+        let mut config = (**handler.config.read().await).clone();
+        let rule = |prefix: &str, country: &str, replace: bool| OverrideConfig {
+            prefix: prefix.to_string(),
+            country: Some(country.to_string()),
+            organization: Some("Internal Anchor".to_string()),
+            tags: vec!["pinned".to_string()],
+            replace,
+        };
+        config.overrides = vec![rule("1.0.0.0/8", "JP", false), rule("1.1.1.0/24", "DE", false), rule("9.9.9.9", "CH", true)];
+        *handler.config.write().await = Arc::new(config);
+        let router = handler.router();
+
+        let (status, body) = get_json(router.clone(), "/ip/1.1.1.1").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["info"]["country"], "DE");
+        assert_eq!(body["info"]["organization"], "Internal Anchor");
+        assert_eq!(body["tags"][0], "pinned");
+        assert_eq!(body["override_prefix"], "1.1.1.0/24");
+        // 合并模式下其余字段仍来自数据源
+        assert_eq!(body["whois_info"]["netname"], "APNIC-LABS");
+
+        let (_, body) = get_json(router.clone(), "/ip/1.2.3.4").await;
+        assert_eq!(body["info"]["country"], "JP");
+
+        // 替换模式不查询数据源
+        let (_, body) = get_json(router, "/ip/9.9.9.9").await;
+        assert_eq!(body["info"]["country"], "CH");
+        assert!(body.get("whois_info").is_none());
+    }
+
+    #[tokio::test]
+    async fn batch_lookups_honour_replace_overrides() {
+        let dir = tempfile::tempdir().unwrap();
+        let handler = test_handler(&dir);
+        let mut config = (**handler.config.read().await).clone();
+        config.overrides = vec![OverrideConfig {
+            prefix: "9.9.9.9".to_string(),
+            country: Some("CH".to_string()),
+            organization: Some("Internal Anchor".to_string()),
+            tags: Vec::new(),
+            replace: true,
+        }];
+        *handler.config.write().await = Arc::new(config);
+
+        let (status, body) = get_json(handler.router(), "/ip/9.9.9.9,1.1.1.1").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["9.9.9.9"]["info"]["country"], "CH");
+        assert_eq!(body["9.9.9.9"]["info"]["organization"], "Internal Anchor");
+        assert!(body["9.9.9.9"].get("whois_info").is_none());
+        assert_eq!(body["1.1.1.1"]["whois_info"]["netname"], "APNIC-LABS");
+    }
+
+    #[tokio::test]
+    async fn source_disagreements_are_reported_as_warnings() {
+        let dir = tempfile::tempdir().unwrap();
+        let handler = test_handler(&dir);
+        let shared_config = handler.config.clone();
+        let router = handler.router();
+        let (_, body) = get_json(router.clone(), "/ip/1.1.1.1").await;
+        assert!(body.get("warnings").is_none());
+        {
+            let mut config = (**shared_config.read().await).clone();
+            config.response.source_warnings = true;
+            *shared_config.write().await = Arc::new(config);
+        }
+        // 模拟的WHOIS国家为AU、BGP国家为US，三个来源的ASN均为13335
+        let (status, body) = get_json(router, "/ip/1.1.1.2").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["warnings"], serde_json::json!(["WHOIS国家 AU 与BGP国家 US 不一致"]));
+    }
+
+    #[tokio::test]
+    async fn normalized_countries_share_one_representation() {
+        let dir = tempfile::tempdir().unwrap();
+        let handler = test_handler(&dir);
+        {
+            let mut config = (**handler.config.read().await).clone();
+            config.response.normalize_countries = true;
+            *handler.config.write().await = Arc::new(config);
+        }
+        let router = handler.router();
+        let (status, body) = get_json(router.clone(), "/ip/1.1.1.1?prefer=bgp").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["info"]["country_canonical"]["code"], "US");
+        assert_eq!(body["bgp_info"]["country_canonical"]["name_en"], "United States");
+        assert_eq!(body["whois_info"]["country_canonical"]["name_localized"], "澳大利亚");
+        
+        let (_, body) = get_json(test_router(&dir), "/ip/1.1.1.1").await;
+        assert!(body["bgp_info"].get("country_canonical").is_none());
+    }
+
+    #[tokio::test]
+    async fn historical_lookup_reports_unsupported_upstreams() {
+        let dir = tempfile::tempdir().unwrap();
+        let (status, body) = get_json(test_router(&dir), "/ip/1.1.1.1?at=2020-01-01").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["historical_routing"]["origins"][0], 13335);
+        assert_eq!(body["historical_routing"]["query_time"], "2020-01-01T00:00:00");
+        assert!(body.get("whois_info").is_none());
+        assert!(body["errors"].as_array().unwrap().iter().any(|e| e.as_str().unwrap().starts_with("whois")));
+        
+        let (status, _) = get_json(test_router(&dir), "/ip/1.1.1.1?at=yesterday").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn rpki_endpoint_validates_and_normalizes_input() {
+        let dir = tempfile::tempdir().unwrap();
+        let (status, body) = get_json(test_router(&dir), "/rpki/AS13335/1.1.1.1/24").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["prefix"], "1.1.1.0/24");
+        assert_eq!(body["asn"], "13335");
+        assert_eq!(body["validity"], "valid");
+        
+        let (status, _) = get_json(test_router(&dir), "/rpki/abc/1.1.1.0/24").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let (status, _) = get_json(test_router(&dir), "/rpki/13335/not-a-prefix").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn oversized_prefixes_are_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let (status, _) = get_json(test_router(&dir), "/ip/0.0.0.0%2F0").await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        let (status, _) = get_json(test_router(&dir), "/ip/2001:db8::%2F16/raw").await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    #[tokio::test]
+    async fn database_update_requires_admin_token() {
+        let dir = tempfile::tempdir().unwrap();
+        let response = test_router(&dir)
+            .oneshot(Request::post("/admin/update-databases").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[test]
+    fn admin_tokens_are_compared_exactly() {
+        assert!(constant_time_eq(b"secret", b"secret"));
+        assert!(!constant_time_eq(b"secret", b"secreT"));
+        assert!(!constant_time_eq(b"secret", b"secret2"));
+        assert!(!constant_time_eq(b"", b"secret"));
+    }
+
+    #[tokio::test]
+    async fn database_metadata_requires_admin_token() {
+        let dir = tempfile::tempdir().unwrap();
+        let handler = test_handler(&dir);
+        {
+            let mut config = (**handler.config.read().await).clone();
+            config.app.admin_token = Some("secret".to_string());
+            *handler.config.write().await = Arc::new(config);
+        }
+        let router = handler.router();
+        let (status, _) = get_json(router.clone(), "/admin/databases").await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        let response = router
+            .oneshot(Request::get("/admin/databases").header("x-admin-token", "secret").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        // 测试环境未加载任何数据库
+        assert_eq!(serde_json::from_slice::<serde_json::Value>(&body).unwrap(), serde_json::json!([]));
+    }
+
+    #[tokio::test]
+    async fn country_networks_require_token_and_country_database() {
+        let dir = tempfile::tempdir().unwrap();
+        let handler = test_handler(&dir);
+        {
+            let mut config = (**handler.config.read().await).clone();
+            config.app.admin_token = Some("secret".to_string());
+            *handler.config.write().await = Arc::new(config);
+        }
+        let router = handler.router();
+        let (status, _) = get_json(router.clone(), "/admin/countries/CN/networks").await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        for (uri, expected) in [
+            ("/admin/countries/CHN/networks", StatusCode::BAD_REQUEST),
+            ("/admin/countries/CN/networks", StatusCode::SERVICE_UNAVAILABLE),
+        ] {
+            let response = router.clone()
+                .oneshot(Request::get(uri).header("x-admin-token", "secret").body(Body::empty()).unwrap())
+                .await
+                .unwrap();
+            assert_eq!(response.status(), expected, "{}", uri);
+        }
+    }
+
+    #[tokio::test]
+    async fn api_keys_are_checked_and_quotas_enforced() {
+        let dir = tempfile::tempdir().unwrap();
+        let handler = test_handler(&dir);
+        {
+            let mut config = (**handler.config.read().await).clone();
+            config.app.admin_token = Some("secret".to_string());
+            config.api_keys.keys = vec![crate::config::ApiKeyConfig {
+                name: "team-a".to_string(),
+                key: "key-a".to_string(),
+                per_minute: None,
+                per_day: Some(1),
+            }];
+            config.api_keys.allow_anonymous = false;
+            *handler.config.write().await = Arc::new(config);
+        }
+        let router = handler.router();
+        let lookup = |key: Option<&str>| {
+            let mut request = Request::get("/ip/1.1.1.1");
+            if let Some(key) = key {
+                request = request.header("authorization", format!("Bearer {}", key));
+            }
+            router.clone().oneshot(request.body(Body::empty()).unwrap())
+        };
+        assert_eq!(lookup(None).await.unwrap().status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(lookup(Some("unknown")).await.unwrap().status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(lookup(Some("key-a")).await.unwrap().status(), StatusCode::OK);
+        let limited = lookup(Some("key-a")).await.unwrap();
+        assert_eq!(limited.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert!(limited.headers().contains_key("retry-after"));
+        
+        let response = router
+            .oneshot(Request::get("/stats/api-keys").header("x-admin-token", "secret").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["team-a"]["per_day"], 1);
+        assert_eq!(body["team-a"]["total"], 1);
+        assert!(body["team-a"].get("key").is_none());
+    }
+
+    #[tokio::test]
+    async fn ip_lookup_rejects_invalid_address() {
+        let dir = tempfile::tempdir().unwrap();
+        let (status, body) = get_json(test_router(&dir), "/ip/not-an-ip").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["status"], "error");
+    }
+
+    #[tokio::test]
+    async fn disabled_endpoint_groups_are_not_registered() {
+        let dir = tempfile::tempdir().unwrap();
+        let endpoints = EndpointsConfig { enabled: vec![EndpointGroup::Lookup] };
+        let (public, admin) = test_handler(&dir).into_routers(&endpoints);
+        let router = public.merge(admin);
+        let (status, _) = get_json(router.clone(), "/ip/1.1.1.1").await;
+        assert_eq!(status, StatusCode::OK);
+        for uri in ["/rpki/13335/1.1.1.0/24", "/stats/cache"] {
+            let response = router.clone()
+                .oneshot(Request::get(uri).body(Body::empty()).unwrap())
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::NOT_FOUND, "{}", uri);
+        }
+        let response = router
+            .oneshot(Request::get("/healthz").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_ne!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn health_reports_unavailable_without_passing_self_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let (status, body) = get_json(test_router(&dir), "/healthz").await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body["self_test_passed"], false);
+    }
+
+    #[tokio::test]
+    async fn slow_requests_are_recorded_and_exposed() {
+        let dir = tempfile::tempdir().unwrap();
+        let handler = test_handler(&dir);
+        {
+            let mut config = (**handler.config.read().await).clone();
+            config.app.admin_token = Some("secret".to_string());
+            config.monitoring.slow_request_ms = Some(0);
+            config.monitoring.slow_request_buffer = 1;
+            *handler.config.write().await = Arc::new(config);
+        }
+        let router = handler.router();
+        for uri in ["/ip/1.1.1.1", "/ip/1.0.0.1"] {
+            let (status, _) = get_json(router.clone(), uri).await;
+            assert_eq!(status, StatusCode::OK);
+        }
+        let response = router
+            .oneshot(Request::get("/stats/slow").header("x-admin-token", "secret").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body.as_array().unwrap().len(), 1);
+        assert_eq!(body[0]["ip"], "1.0.0.1");
+    }
+
+    #[tokio::test]
+    async fn ip_query_history_is_exposed() {
+        let dir = tempfile::tempdir().unwrap();
+        let handler = test_handler(&dir);
+        {
+            let mut config = (**handler.config.read().await).clone();
+            config.app.admin_token = Some("secret".to_string());
+            config.monitoring.ip_history_size = Some(10);
+            *handler.config.write().await = Arc::new(config);
+        }
+        let router = handler.router();
+        for _ in 0..2 {
+            let (status, _) = get_json(router.clone(), "/ip/1.1.1.1").await;
+            assert_eq!(status, StatusCode::OK);
+        }
+        let history = |uri: &'static str| {
+            router.clone().oneshot(Request::get(uri).header("x-admin-token", "secret").body(Body::empty()).unwrap())
+        };
+        let response = history("/stats/ip/1.1.1.1").await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["count"], 2);
+        assert_eq!(history("/stats/ip/1.0.0.1").await.unwrap().status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn index_lists_endpoints_and_favicon_is_empty() {
+        let dir = tempfile::tempdir().unwrap();
+        let (status, body) = get_json(test_router(&dir), "/").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["name"], "test");
+        assert!(body["endpoints"].as_array().unwrap().iter().any(|e| e == "/ip/:ip"));
+        let response = test_router(&dir)
+            .oneshot(Request::get("/favicon.ico").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    }
+
+    #[tokio::test]
+    async fn msgpack_is_returned_when_accepted() {
+        let dir = tempfile::tempdir().unwrap();
+        let response = test_router(&dir)
+            .oneshot(Request::get("/ip/1.1.1.1").header("accept", "application/msgpack").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["content-type"], "application/msgpack");
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let decoded: serde_json::Value = rmp_serde::from_slice(&body).unwrap();
+        assert_eq!(decoded["info"]["ip"], "1.1.1.1");
+    }
+
+    #[tokio::test]
+    async fn if_modified_since_returns_not_modified_for_cached_results() {
+        let dir = tempfile::tempdir().unwrap();
+        let router = test_router(&dir);
+        let response = router.clone().oneshot(Request::get("/ip/1.1.1.1").body(Body::empty()).unwrap()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let last_modified = response.headers()["last-modified"].to_str().unwrap().to_string();
+        
+        let conditional = |since: String| {
+            router.clone().oneshot(Request::get("/ip/1.1.1.1").header("if-modified-since", since).body(Body::empty()).unwrap())
+        };
+        let response = conditional(last_modified.clone()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(response.headers()["last-modified"], last_modified.as_str());
+        
+        let response = conditional(http_date(0)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn browsers_get_an_html_page() {
+        let dir = tempfile::tempdir().unwrap();
+        let router = test_router(&dir);
+        let response = router
+            .clone()
+            .oneshot(Request::get("/ip/1.1.1.1").header("accept", "text/html,application/xhtml+xml,*/*;q=0.8").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(response.headers()["content-type"].to_str().unwrap().starts_with("text/html"));
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let page = String::from_utf8(body.to_vec()).unwrap();
+        assert!(page.contains("<h1>"), "{}", page);
+        assert!(page.contains("1.1.1.1"));
+        
+        // 未声明接受HTML的客户端仍得到JSON
+        let response = router.oneshot(Request::get("/ip/1.1.1.1").header("accept", "*/*").body(Body::empty()).unwrap()).await.unwrap();
+        assert!(response.headers()["content-type"].to_str().unwrap().starts_with("application/json"));
+    }
+
+    #[tokio::test]
+    async fn server_timing_reports_lookup_phases() {
+        let dir = tempfile::tempdir().unwrap();
+        let router = test_router(&dir);
+        let server_timing = |response: &Response| response.headers()["server-timing"].to_str().unwrap().to_string();
+        
+        let response = router.clone().oneshot(Request::get("/ip/1.1.1.1").body(Body::empty()).unwrap()).await.unwrap();
+        let timing = server_timing(&response);
+        for phase in ["cache;dur=", "maxmind;dur=", "whois;dur=", "bgp;dur=", "rpki;dur="] {
+            assert!(timing.contains(phase), "{}", timing);
+        }
+        
+        // 缓存命中时只有cache阶段
+        let response = router.oneshot(Request::get("/ip/1.1.1.1").body(Body::empty()).unwrap()).await.unwrap();
+        let timing = server_timing(&response);
+        assert!(timing.starts_with("cache;dur="));
+        assert!(!timing.contains("whois"));
+    }
+
+    #[tokio::test]
+    async fn private_ips_can_be_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let (status, body) = get_json(test_router(&dir), "/ip/10.0.0.1").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["info"]["country"], "保留地址");
+        
+        let handler = test_handler(&dir);
+        {
+            let mut config = (**handler.config.read().await).clone();
+            config.maxmind.private_ip_behavior = PrivateIpBehavior::Reject;
+            *handler.config.write().await = Arc::new(config);
+        }
+        let (status, _) = get_json(handler.router(), "/ip/10.0.0.1").await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    #[tokio::test]
+    async fn asn_names_validates_input() {
+        let dir = tempfile::tempdir().unwrap();
+        let post_json = |body: &'static str| {
+            test_router(&dir).oneshot(
+                Request::post("/asn/names")
+                    .header("content-type", "application/json")
+                    .body(Body::from(body))
+                    .unwrap(),
+            )
+        };
+        let response = post_json(r#"{"asns": ["AS-bogus"]}"#).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        // 测试环境未加载ASN数据库
+        let response = post_json(r#"{"asns": [13335, "AS174"]}"#).await.unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        
+        assert_eq!(AsnInput::Text("as174".to_string()).parse(), Ok(174));
+    }
+
+    #[test]
+    fn rpki_results_are_sorted_and_deduplicated() {
+        let entry = |asn: &str| RpkiValidity {
+            asn: asn.to_string(),
+            prefix: "1.1.1.0/24".to_string(),
+            validity: "valid".to_string(),
+            reason: None,
+            vrps: None,
+        };
+        let sorted = sort_rpki_results(vec![entry("13335"), entry("9"), entry("13335"), entry("AS100")]);
+        let asns: Vec<&str> = sorted.iter().map(|r| r.asn.as_str()).collect();
+        assert_eq!(asns, vec!["9", "AS100", "13335"]);
+    }
+
+    #[tokio::test]
+    async fn preload_fills_cache_and_skips_invalid_lines() {
+        let dir = tempfile::tempdir().unwrap();
+        let list = dir.path().join("preload.txt");
+        std::fs::write(&list, "# 常用地址\n1.1.1.1\nnot-an-ip\n\n1.0.0.0/24\n").unwrap();
+        let handler = test_handler(&dir);
+        let loaded = handler.preload(list.to_str().unwrap(), 2).await.unwrap();
+        assert_eq!(loaded, 2);
+        assert!(handler.cache.contains("1.1.1.1").await);
+        assert_eq!(handler.preload(list.to_str().unwrap(), 2).await.unwrap(), 0);
+    }
+}
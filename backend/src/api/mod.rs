@@ -1,17 +1,131 @@
+mod html;
 mod ip_api;
 
+use axum::body::Body;
+use axum::http::{Request, StatusCode};
+use axum::response::Response;
 use axum::Router;
+use crate::config::{AppConfig, EndpointsConfig};
+use crate::utils::trace_context;
+use std::any::Any as PanicPayload;
+use tower_http::catch_panic::CatchPanicLayer;
 use tower_http::cors::{Any, CorsLayer};
+use tower_http::request_id::{MakeRequestUuid, PropagateRequestIdLayer, SetRequestIdLayer};
+use tower_http::trace::TraceLayer;
 
 pub use ip_api::IpApiHandler;
+use ip_api::ErrorResponse;
 
-pub fn create_router(ip_handler: IpApiHandler) -> Router {
+pub fn create_router(ip_handler: IpApiHandler, app: &AppConfig, endpoints: &EndpointsConfig) -> Router {
+    let (public, admin) = create_routers(ip_handler, app, endpoints);
+    public.merge(admin)
+}
+
+/// 分别创建公开查询路由和运维路由，用于在不同端口上提供服务
+pub fn create_routers(ip_handler: IpApiHandler, app: &AppConfig, endpoints: &EndpointsConfig) -> (Router, Router) {
+    let (public, admin) = ip_handler.into_routers(endpoints);
+    let base_path = normalize_base_path(app.base_path.as_deref());
+    let public = mount(public, base_path.as_deref());
+    let admin = if app.ops_under_base_path {
+        mount(admin, base_path.as_deref())
+    } else {
+        admin
+    };
+    (with_layers(public), with_layers(admin))
+}
+
+/// 规范化挂载路径：去除末尾的斜杠，空路径或 / 视为不挂载
+fn normalize_base_path(base_path: Option<&str>) -> Option<String> {
+    let trimmed = base_path?.trim().trim_end_matches('/');
+    if trimmed.is_empty() {
+        None
+    } else {
+        Some(trimmed.to_string())
+    }
+}
+
+fn mount(router: Router, base_path: Option<&str>) -> Router {
+    match base_path {
+        Some(path) => Router::new().nest(path, router),
+        None => router,
+    }
+}
+
+fn with_layers(router: Router) -> Router {
     let cors = CorsLayer::new()
         .allow_origin(Any)
         .allow_methods(Any)
         .allow_headers(Any);
 
-    Router::new()
-        .merge(ip_handler.router())
+    // 外层先补齐 X-Request-ID（缺失时生成UUID），再记录到请求span并回写到响应头；
+    // 入站的 traceparent 在处理期间生效，供上游请求继续传递
+    router
         .layer(cors)
-} 
\ No newline at end of file
+        .layer(CatchPanicLayer::custom(handle_panic))
+        .layer(axum::middleware::from_fn(trace_context::propagate))
+        .layer(TraceLayer::new_for_http().make_span_with(request_span))
+        .layer(PropagateRequestIdLayer::x_request_id())
+        .layer(SetRequestIdLayer::x_request_id(MakeRequestUuid))
+}
+
+/// 请求span只记录方法和请求ID，路径中的IP由各处理器按隐私配置输出
+fn request_span(request: &Request<Body>) -> tracing::Span {
+    let request_id = request
+        .headers()
+        .get("x-request-id")
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("-");
+    tracing::info_span!("request", method = %request.method(), request_id = %request_id)
+}
+
+/// 将处理器中的panic转换为500错误响应，避免直接断开连接
+fn handle_panic(payload: Box<dyn PanicPayload + Send + 'static>) -> Response {
+    let detail = payload
+        .downcast_ref::<String>()
+        .map(|s| s.as_str())
+        .or_else(|| payload.downcast_ref::<&str>().copied())
+        .unwrap_or("未知错误");
+    tracing::error!("请求处理发生panic: {}", detail);
+    ErrorResponse::into_response_with(StatusCode::INTERNAL_SERVER_ERROR, "服务器内部错误")
+} 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::routing::get;
+    use tower::ServiceExt;
+
+    #[test]
+    fn base_path_is_normalized() {
+        assert_eq!(normalize_base_path(Some("/ipapi/")).as_deref(), Some("/ipapi"));
+        assert_eq!(normalize_base_path(Some("/")), None);
+        assert_eq!(normalize_base_path(None), None);
+    }
+
+    #[tokio::test]
+    async fn handler_panic_becomes_internal_error() {
+        let router: Router = Router::new()
+            .route("/panic", get(|| async { panic!("测试panic") as &str }))
+            .layer(CatchPanicLayer::custom(handle_panic));
+        let response = router
+            .oneshot(Request::get("/panic").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    #[tokio::test]
+    async fn request_id_is_echoed_or_generated() {
+        let router = with_layers(Router::new().route("/", get(|| async { "ok" })));
+        let response = router
+            .clone()
+            .oneshot(Request::get("/").header("x-request-id", "abc-123").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.headers()["x-request-id"], "abc-123");
+        let response = router
+            .oneshot(Request::get("/").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.headers()["x-request-id"].len(), 36);
+    }
+}
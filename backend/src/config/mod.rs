@@ -1,39 +1,835 @@
+use crate::utils::kv_store::PersistFormat;
 use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
 use std::fs::File;
 use std::io::Read;
 use std::path::Path;
+use std::str::FromStr;
 use std::sync::Arc;
+use tokio::sync::RwLock;
+
+/// 可热更新的共享配置
+pub type SharedConfig = Arc<RwLock<Arc<Config>>>;
+
+/// 重启前保持当前运行值的方式：把旧配置中的值写回新配置
+type Restore = fn(&mut Config, &Config);
+
+/// 修改后需要重启才能生效的配置项，reload 时按同一张表报告并保持当前运行值
+const RESTART_REQUIRED_KEYS: &[(&str, Restore)] = &[
+    ("app.port", |new, old| new.app.port = old.app.port),
+    ("app.admin_port", |new, old| new.app.admin_port = old.app.admin_port),
+    ("app.base_path", |new, old| new.app.base_path = old.app.base_path.clone()),
+    ("app.ops_under_base_path", |new, old| new.app.ops_under_base_path = old.app.ops_under_base_path),
+    ("maxmind.database_dir", |new, old| new.maxmind.database_dir = old.maxmind.database_dir.clone()),
+    ("maxmind.start_without_databases", |new, old| new.maxmind.start_without_databases = old.maxmind.start_without_databases),
+    ("maxmind.organization_overrides", |new, old| new.maxmind.organization_overrides = old.maxmind.organization_overrides.clone()),
+    ("maxmind.private_ip_behavior", |new, old| new.maxmind.private_ip_behavior = old.maxmind.private_ip_behavior),
+    ("maxmind.use_mmap", |new, old| new.maxmind.use_mmap = old.maxmind.use_mmap),
+    ("cache.cleanup_interval_secs", |new, old| new.cache.cleanup_interval_secs = old.cache.cleanup_interval_secs),
+    ("cache.ttl_secs", |new, old| new.cache.ttl_secs = old.cache.ttl_secs),
+    ("cache.persist_format", |new, old| new.cache.persist_format = old.cache.persist_format),
+    ("cache.persist_compression", |new, old| new.cache.persist_compression = old.cache.persist_compression),
+    ("cache.ipv6_group_prefix", |new, old| new.cache.ipv6_group_prefix = old.cache.ipv6_group_prefix),
+    ("cache.stale_window_secs", |new, old| new.cache.stale_window_secs = old.cache.stale_window_secs),
+    ("cache.error_grace_secs", |new, old| new.cache.error_grace_secs = old.cache.error_grace_secs),
+    ("cache.slow_persist_warn_ms", |new, old| new.cache.slow_persist_warn_ms = old.cache.slow_persist_warn_ms),
+    ("cache.ttl_jitter_secs", |new, old| new.cache.ttl_jitter_secs = old.cache.ttl_jitter_secs),
+    ("cache.memory_limit_mb", |new, old| new.cache.memory_limit_mb = old.cache.memory_limit_mb),
+    ("cache.disk_tier_dir", |new, old| new.cache.disk_tier_dir = old.cache.disk_tier_dir.clone()),
+    ("cache.disk_tier_max_mb", |new, old| new.cache.disk_tier_max_mb = old.cache.disk_tier_max_mb),
+    ("cache.preload_file", |new, old| new.cache.preload_file = old.cache.preload_file.clone()),
+    ("cache.preload_concurrency", |new, old| new.cache.preload_concurrency = old.cache.preload_concurrency),
+    ("cache.redis_url", |new, old| new.cache.redis_url = old.cache.redis_url.clone()),
+    ("cache.redis_key_prefix", |new, old| new.cache.redis_key_prefix = old.cache.redis_key_prefix.clone()),
+    ("endpoints.enabled", |new, old| new.endpoints.enabled = old.endpoints.enabled.clone()),
+    ("upstream.max_concurrent_requests", |new, old| new.upstream.max_concurrent_requests = old.upstream.max_concurrent_requests),
+    ("upstream.http_proxy", |new, old| new.upstream.http_proxy = old.upstream.http_proxy.clone()),
+    ("upstream.http_retries", |new, old| new.upstream.http_retries = old.upstream.http_retries),
+    ("upstream.connect_timeout_ms", |new, old| new.upstream.connect_timeout_ms = old.upstream.connect_timeout_ms),
+    ("upstream.request_timeout_secs", |new, old| new.upstream.request_timeout_secs = old.upstream.request_timeout_secs),
+    ("upstream.dns_cache_enabled", |new, old| new.upstream.dns_cache_enabled = old.upstream.dns_cache_enabled),
+    ("upstream.dns_cache_size", |new, old| new.upstream.dns_cache_size = old.upstream.dns_cache_size),
+    ("publisher.nats_url", |new, old| new.publisher.nats_url = old.publisher.nats_url.clone()),
+    ("publisher.subject", |new, old| new.publisher.subject = old.publisher.subject.clone()),
+    ("publisher.queue_size", |new, old| new.publisher.queue_size = old.publisher.queue_size),
+];
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Config {
     pub app: AppConfig,
     pub maxmind: MaxmindConfig,
+    #[serde(default)]
+    pub cache: CacheConfig,
+    #[serde(default)]
+    pub privacy: PrivacyConfig,
+    #[serde(default)]
+    pub whois: WhoisConfig,
+    #[serde(default)]
+    pub risk: RiskConfig,
+    #[serde(default)]
+    pub dnsbl: DnsblConfig,
+    #[serde(default)]
+    pub upstream: UpstreamConfig,
+    #[serde(default)]
+    pub rpki: RpkiConfig,
+    #[serde(default)]
+    pub response: ResponseConfig,
+    #[serde(default)]
+    pub lookup: LookupConfig,
+    #[serde(default)]
+    pub anycast: AnycastConfig,
+    #[serde(default)]
+    pub endpoints: EndpointsConfig,
+    #[serde(default)]
+    pub monitoring: MonitoringConfig,
+    #[serde(default)]
+    pub webhook: WebhookConfig,
+    #[serde(default)]
+    pub api_keys: ApiKeysConfig,
+    /// 指定IP/网段的固定响应，多条匹配时取最长前缀
+    #[serde(default)]
+    pub overrides: Vec<OverrideConfig>,
+    #[serde(default)]
+    pub publisher: PublisherConfig,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct AppConfig {
     pub name: String,
     pub port: u16,
+    /// 运维接口（健康检查、缓存统计与管理）的独立端口；未配置时与查询接口共用 port
+    #[serde(default)]
+    pub admin_port: Option<u16>,
+    /// 反向代理下的挂载路径（如 /ipapi），所有查询接口挂载在该路径下
+    #[serde(default)]
+    pub base_path: Option<String>,
+    /// 运维接口（健康检查、缓存统计与管理）是否同样挂载在 base_path 下
+    #[serde(default)]
+    pub ops_under_base_path: bool,
+    /// 管理接口令牌，通过 X-Admin-Token 请求头传递；未配置时管理接口不可用
+    #[serde(default)]
+    pub admin_token: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct CacheConfig {
+    /// 过期条目清理间隔（秒）
+    #[serde(default = "default_cleanup_interval_secs")]
+    pub cleanup_interval_secs: u64,
+    /// 缓存条目存活时间（秒），同时作为响应中的 ttl_seconds 提示
+    #[serde(default = "default_cache_ttl_secs")]
+    pub ttl_secs: u64,
+    /// 缓存文件格式：bincode（默认）或 json
+    #[serde(default)]
+    pub persist_format: PersistFormat,
+    /// 缓存文件使用gzip压缩，读取时自动识别，切换后无需删除旧文件
+    #[serde(default)]
+    pub persist_compression: bool,
+    /// 单个IPv6地址按该长度的前缀共享缓存条目（如 64），未配置时每个地址单独缓存
+    #[serde(default)]
+    pub ipv6_group_prefix: Option<u8>,
+    /// 每个条目的存活时间随机延长 0 到该秒数，分散热门条目的过期时刻，避免同时回源
+    #[serde(default)]
+    pub ttl_jitter_secs: Option<u64>,
+    /// 条目过期后仍保留的秒数；期间命中时立即返回旧数据（stale: true）并在后台刷新
+    #[serde(default)]
+    pub stale_window_secs: Option<u64>,
+    /// 条目硬过期（含保留窗口）后再保留的秒数；期间只有数据源全部不可用时才返回（stale: true, stale_reason: upstream_unavailable）
+    #[serde(default)]
+    pub error_grace_secs: Option<u64>,
+    /// 本地缓存一次持久化（序列化加写入）超过该毫秒数时输出警告；未配置时只记录耗时
+    #[serde(default)]
+    pub slow_persist_warn_ms: Option<u64>,
+    /// 本地缓存的内存占用上限（MB），未配置时为1024
+    #[serde(default)]
+    pub memory_limit_mb: Option<usize>,
+    /// 磁盘二级缓存目录；配置后内存达到上限时最早过期的条目移入磁盘，命中时取回内存
+    #[serde(default)]
+    pub disk_tier_dir: Option<String>,
+    /// 磁盘二级缓存的容量上限（MB），超出时删除最久未访问的条目
+    #[serde(default = "default_disk_tier_max_mb")]
+    pub disk_tier_max_mb: u64,
+    /// 启动时预加载到缓存的IP列表文件（每行一个IP或CIDR，# 开头为注释）
+    #[serde(default)]
+    pub preload_file: Option<String>,
+    /// 预加载时同时查询的IP数量
+    #[serde(default = "default_preload_concurrency")]
+    pub preload_concurrency: usize,
+    /// 多实例共享的Redis缓存地址（如 redis://127.0.0.1:6379/0），配置后代替本地缓存文件
+    #[serde(default)]
+    pub redis_url: Option<String>,
+    /// Redis中缓存键的前缀，多个服务共用同一Redis时用于区分
+    #[serde(default = "default_redis_key_prefix")]
+    pub redis_key_prefix: String,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            cleanup_interval_secs: default_cleanup_interval_secs(),
+            ttl_secs: default_cache_ttl_secs(),
+            persist_format: PersistFormat::default(),
+            persist_compression: false,
+            ipv6_group_prefix: None,
+            ttl_jitter_secs: None,
+            stale_window_secs: None,
+            error_grace_secs: None,
+            slow_persist_warn_ms: None,
+            memory_limit_mb: None,
+            disk_tier_dir: None,
+            disk_tier_max_mb: default_disk_tier_max_mb(),
+            preload_file: None,
+            preload_concurrency: default_preload_concurrency(),
+            redis_url: None,
+            redis_key_prefix: default_redis_key_prefix(),
+        }
+    }
+}
+
+fn default_redis_key_prefix() -> String {
+    "ipapi:ip:".to_string()
+}
+
+fn default_disk_tier_max_mb() -> u64 {
+    10 * 1024
+}
+
+fn default_cache_ttl_secs() -> u64 {
+    60 * 60 * 24 * 7
+}
+
+fn default_cleanup_interval_secs() -> u64 {
+    60
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RiskConfig {
+    /// 是否在响应中输出风险评分
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    #[serde(default)]
+    pub weights: RiskWeights,
+}
+
+impl Default for RiskConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            weights: RiskWeights::default(),
+        }
+    }
+}
+
+/// 各风险因素的权重，总分封顶100
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct RiskWeights {
+    pub rpki_invalid: u32,
+    pub rpki_not_found: u32,
+    pub origin_mismatch: u32,
+    pub no_whois: u32,
+    pub no_abuse_contact: u32,
+    pub no_asn: u32,
+    pub dnsbl_listed: u32,
+}
+
+impl Default for RiskWeights {
+    fn default() -> Self {
+        Self {
+            rpki_invalid: 50,
+            rpki_not_found: 10,
+            origin_mismatch: 30,
+            no_whois: 10,
+            no_abuse_contact: 10,
+            no_asn: 20,
+            dnsbl_listed: 30,
+        }
+    }
+}
+
+fn default_true() -> bool {
+    true
+}
+
+/// 查询接口的API Key（Authorization: Bearer <key>）及各Key的配额
+/// 未配置任何Key时不做检查；未知的Key返回401，超出配额返回429
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ApiKeysConfig {
+    #[serde(default)]
+    pub keys: Vec<ApiKeyConfig>,
+    /// 配置了Key时是否仍允许不带Key的请求（不计配额）
+    #[serde(default = "default_true")]
+    pub allow_anonymous: bool,
+}
+
+impl Default for ApiKeysConfig {
+    fn default() -> Self {
+        Self { keys: Vec::new(), allow_anonymous: true }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ApiKeyConfig {
+    /// 用量统计中显示的名称（如团队名），不暴露Key本身
+    pub name: String,
+    pub key: String,
+    /// 每分钟请求数上限，未配置时不限
+    #[serde(default)]
+    pub per_minute: Option<u32>,
+    /// 每天（UTC）请求数上限，未配置时不限
+    #[serde(default)]
+    pub per_day: Option<u32>,
+}
+
+/// 将每次查询结果发布到NATS主题
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PublisherConfig {
+    /// NATS服务器地址（如 nats://127.0.0.1:4222），未配置时不发布
+    #[serde(default)]
+    pub nats_url: Option<String>,
+    #[serde(default = "default_publisher_subject")]
+    pub subject: String,
+    /// 等待发送的消息上限，NATS不可用时超出的消息被丢弃
+    #[serde(default = "default_publisher_queue_size")]
+    pub queue_size: usize,
+}
+
+impl Default for PublisherConfig {
+    fn default() -> Self {
+        Self {
+            nats_url: None,
+            subject: default_publisher_subject(),
+            queue_size: default_publisher_queue_size(),
+        }
+    }
+}
+
+fn default_publisher_subject() -> String {
+    "ipapi.lookups".to_string()
+}
+
+fn default_publisher_queue_size() -> usize {
+    10_000
+}
+
+/// 查询结果命中规则时向Webhook推送通知，任一规则命中即推送
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct WebhookConfig {
+    /// 接收通知的地址，未配置时不推送
+    #[serde(default)]
+    pub urls: Vec<String>,
+    /// 关注的国家代码（ISO 3166-1，如 KP）
+    #[serde(default)]
+    pub countries: Vec<String>,
+    /// RPKI验证结果为invalid时推送
+    #[serde(default)]
+    pub rpki_invalid: bool,
+    /// 被DNS黑名单收录时推送
+    #[serde(default)]
+    pub dnsbl_listed: bool,
+    /// 风险评分达到该值时推送
+    #[serde(default)]
+    pub min_risk_score: Option<u8>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct DnsblConfig {
+    /// 是否启用DNS黑名单检查
+    #[serde(default)]
+    pub enabled: bool,
+    /// 要查询的DNSBL区域（如 zen.spamhaus.org）
+    #[serde(default)]
+    pub zones: Vec<String>,
+    /// 使用的DNS解析器地址（如 127.0.0.1:53），未配置时使用系统解析器；部分DNSBL禁止通过公共解析器查询
+    #[serde(default)]
+    pub resolver: Option<String>,
+    /// 每个区域的查询超时（毫秒）
+    #[serde(default = "default_dnsbl_timeout_ms")]
+    pub timeout_ms: u64,
+}
+
+impl Default for DnsblConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            zones: Vec::new(),
+            resolver: None,
+            timeout_ms: default_dnsbl_timeout_ms(),
+        }
+    }
+}
+
+fn default_dnsbl_timeout_ms() -> u64 {
+    2000
+}
+
+/// 可单独启用的路由分组
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum EndpointGroup {
+    /// IP查询与对比：/ip/:ip、/ip/:ip/raw、/compare/:ip1/:ip2
+    Lookup,
+    /// WHOIS句柄反查：/handle/:handle
+    Whois,
+    /// ASN相关查询：/rpki/:asn/*prefix、/asn/names
+    Asn,
+    /// 数据库管理：/admin/update-databases、/admin/databases、/admin/countries/:code/networks
+    Admin,
+    /// 运行统计：/stats/cache、/stats/cache/expiring、/stats/slow、/stats/distribution、/stats/api-keys
+    Metrics,
+    /// 缓存管理：/cache/cleanup
+    CacheAdmin,
+}
+
+impl EndpointGroup {
+    pub const ALL: [EndpointGroup; 6] = [
+        EndpointGroup::Lookup,
+        EndpointGroup::Whois,
+        EndpointGroup::Asn,
+        EndpointGroup::Admin,
+        EndpointGroup::Metrics,
+        EndpointGroup::CacheAdmin,
+    ];
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct EndpointsConfig {
+    /// 启用的路由分组，未列出的分组不注册路由（返回404）；/healthz 始终启用
+    #[serde(default = "default_enabled_endpoints")]
+    pub enabled: Vec<EndpointGroup>,
+}
+
+impl EndpointsConfig {
+    pub fn is_enabled(&self, group: EndpointGroup) -> bool {
+        self.enabled.contains(&group)
+    }
+}
+
+impl Default for EndpointsConfig {
+    fn default() -> Self {
+        Self {
+            enabled: default_enabled_endpoints(),
+        }
+    }
+}
+
+fn default_enabled_endpoints() -> Vec<EndpointGroup> {
+    EndpointGroup::ALL.to_vec()
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AnycastConfig {
+    /// 已知的任播前缀，MaxMind未标记时按此列表判断
+    #[serde(default = "default_anycast_prefixes")]
+    pub prefixes: Vec<String>,
+}
+
+impl Default for AnycastConfig {
+    fn default() -> Self {
+        Self {
+            prefixes: default_anycast_prefixes(),
+        }
+    }
+}
+
+fn default_anycast_prefixes() -> Vec<String> {
+    [
+        "1.1.1.0/24",
+        "1.0.0.0/24",
+        "8.8.8.0/24",
+        "8.8.4.0/24",
+        "9.9.9.0/24",
+        "149.112.112.0/24",
+        "208.67.222.0/24",
+        "208.67.220.0/24",
+        "2606:4700:4700::/48",
+        "2001:4860:4860::/48",
+        "2620:fe::/48",
+        "2620:119:35::/48",
+    ]
+    .iter()
+    .map(|p| p.to_string())
+    .collect()
+}
+
+/// 内部任播锚点、受制裁网段等需要固定答案的地址，不受MaxMind等数据源影响
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct OverrideConfig {
+    /// 单个IP或CIDR
+    pub prefix: String,
+    #[serde(default)]
+    pub country: Option<String>,
+    #[serde(default)]
+    pub organization: Option<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// 为true时不查询任何数据源，只返回覆盖的字段；否则只替换查询结果中的对应字段
+    #[serde(default)]
+    pub replace: bool,
+}
+
+impl OverrideConfig {
+    /// 单个IP按 /32 或 /128 处理
+    pub fn network(&self) -> Result<ipnet::IpNet, String> {
+        let prefix = self.prefix.trim();
+        prefix.parse::<ipnet::IpNet>()
+            .or_else(|_| prefix.parse::<std::net::IpAddr>().map(ipnet::IpNet::from))
+            .map_err(|e| format!("overrides 中的前缀无效 ({}): {}", self.prefix, e))
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct LookupConfig {
+    /// 允许查询的最短IPv4前缀长度，更大的网段（如 /0）直接拒绝
+    #[serde(default = "default_min_prefix_v4")]
+    pub min_prefix_v4: u8,
+    /// 允许查询的最短IPv6前缀长度
+    #[serde(default = "default_min_prefix_v6")]
+    pub min_prefix_v6: u8,
+    /// GET /ip/1.1.1.1,8.8.8.8 一次最多查询的地址数量
+    #[serde(default = "default_max_ips_per_request")]
+    pub max_ips_per_request: usize,
+}
+
+impl Default for LookupConfig {
+    fn default() -> Self {
+        Self {
+            min_prefix_v4: default_min_prefix_v4(),
+            min_prefix_v6: default_min_prefix_v6(),
+            max_ips_per_request: default_max_ips_per_request(),
+        }
+    }
+}
+
+fn default_max_ips_per_request() -> usize {
+    10
+}
+
+fn default_min_prefix_v4() -> u8 {
+    8
+}
+
+fn default_min_prefix_v6() -> u8 {
+    19
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct ResponseConfig {
+    /// 单次响应返回的上游AS数量上限，?upstream_limit= 只能在此范围内调小，其余通过 ?upstream_offset= 翻页；未配置时不截断
+    #[serde(default)]
+    pub max_upstreams: Option<usize>,
+    /// 单次响应返回的相关前缀数量上限，翻页参数为 ?related_offset= / ?related_limit=；未配置时不截断
+    #[serde(default)]
+    pub max_related_prefixes: Option<usize>,
+    /// 城市未知但有坐标时，使用行政区名称作为近似城市并标记 city_is_approximate
+    #[serde(default)]
+    pub approximate_city: bool,
+    /// 各数据源的国家、组织不一致时优先采用的来源，可被 ?prefer= 覆盖
+    #[serde(default)]
+    pub prefer: DataSource,
+    /// 在 info、whois_info、bgp_info 中附加统一格式的国家（country_canonical：代码、英文名、本地化名称）
+    #[serde(default)]
+    pub normalize_countries: bool,
+    /// MaxMind、WHOIS、BGP之间国家或ASN不一致时，在响应的 warnings 中说明
+    #[serde(default)]
+    pub source_warnings: bool,
+    /// 允许 ?deterministic=true 省略缓存时间、TTL等随时间变化的字段，用于快照测试和响应哈希；生产环境保持关闭
+    #[serde(default)]
+    pub allow_deterministic: bool,
+}
+
+/// 国家、组织字段的数据来源
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum DataSource {
+    #[default]
+    Maxmind,
+    Whois,
+    Bgp,
+}
+
+impl DataSource {
+    /// 未指定优先来源时的默认顺序
+    pub const DEFAULT_ORDER: [DataSource; 3] = [DataSource::Maxmind, DataSource::Whois, DataSource::Bgp];
+
+    pub fn parse(value: &str) -> Result<Self, String> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "maxmind" => Ok(DataSource::Maxmind),
+            "whois" => Ok(DataSource::Whois),
+            "bgp" => Ok(DataSource::Bgp),
+            other => Err(format!("不支持的数据源: {}（可选 maxmind、whois、bgp）", other)),
+        }
+    }
+
+    /// 以 self 为首、其余按默认顺序排列的优先级
+    pub fn priority(self) -> impl Iterator<Item = DataSource> {
+        std::iter::once(self).chain(Self::DEFAULT_ORDER.into_iter().filter(move |s| *s != self))
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct MonitoringConfig {
+    /// 查询总耗时超过该阈值（毫秒）时记录慢请求；未配置时不记录
+    #[serde(default)]
+    pub slow_request_ms: Option<u64>,
+    /// 通过 /stats/slow 保留的最近慢请求数量
+    #[serde(default = "default_slow_request_buffer")]
+    pub slow_request_buffer: usize,
+    /// 按IP记录查询次数和最近查询时间（通过 /stats/ip/:ip 查看）的最大IP数；未配置时不记录
+    #[serde(default)]
+    pub ip_history_size: Option<usize>,
+}
+
+impl Default for MonitoringConfig {
+    fn default() -> Self {
+        Self {
+            slow_request_ms: None,
+            slow_request_buffer: default_slow_request_buffer(),
+            ip_history_size: None,
+        }
+    }
+}
+
+fn default_slow_request_buffer() -> usize {
+    50
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RpkiConfig {
+    /// 验证器支持批量验证接口时，多源AS的前缀只发起一次请求
+    #[serde(default)]
+    pub batch: bool,
+    /// 主RPKI验证器地址（Routinator兼容的 /api/v1/validity 接口）
+    #[serde(default = "default_rpki_validator_url")]
+    pub validator_url: String,
+    /// 用于交叉验证的额外验证器地址，配置后每条路由都会同时查询，结果输出在 rpki_cross_checks 中
+    #[serde(default)]
+    pub extra_validators: Vec<String>,
+}
+
+impl Default for RpkiConfig {
+    fn default() -> Self {
+        Self {
+            batch: false,
+            validator_url: default_rpki_validator_url(),
+            extra_validators: Vec::new(),
+        }
+    }
+}
+
+fn default_rpki_validator_url() -> String {
+    "http://rpki.akae.re".to_string()
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct UpstreamConfig {
+    /// 全局同时进行的外部查询数量上限（WHOIS、BGP Tools、BGP API、RPKI、DNSBL共享）
+    #[serde(default = "default_max_concurrent_requests")]
+    pub max_concurrent_requests: usize,
+    /// 所有外部HTTP请求（包括MaxMind下载）使用的代理，如 http://proxy:3128
+    /// 未配置时沿用 HTTPS_PROXY / HTTP_PROXY 环境变量
+    #[serde(default)]
+    pub http_proxy: Option<String>,
+    /// BGP API、RPKI请求遇到连接错误、超时或5xx时的重试次数，0 表示不重试
+    #[serde(default = "default_http_retries")]
+    pub http_retries: u32,
+    /// 建立连接的超时（毫秒），主机不可达时尽快失败
+    #[serde(default = "default_connect_timeout_ms")]
+    pub connect_timeout_ms: u64,
+    /// 外部查询的整体超时（秒），未配置时使用各数据源的默认值（BGP API 10秒，其余30秒）
+    /// BGP Tools 网页抓取不受此项影响，使用 bgptools_scrape_timeout_secs
+    #[serde(default)]
+    pub request_timeout_secs: Option<u64>,
+    /// BGP Tools 上游网页抓取的整体超时（秒），与 WHOIS 查询的超时分开
+    #[serde(default = "default_bgptools_scrape_timeout_secs")]
+    pub bgptools_scrape_timeout_secs: u64,
+    /// BGP Tools 上游网页允许读取的最大字节数，超过时放弃本次抓取
+    #[serde(default = "default_bgptools_max_body_bytes")]
+    pub bgptools_max_body_bytes: usize,
+    /// 保留BGP API的原始响应并在查询结果中输出（bgp_api_raw），用于排查解析问题；会增大缓存和响应体积
+    #[serde(default)]
+    pub keep_raw_bgp_api: bool,
+    /// 在进程内缓存上游主机名的DNS解析结果（按记录TTL过期），所有HTTP客户端共享
+    #[serde(default = "default_true")]
+    pub dns_cache_enabled: bool,
+    /// DNS缓存最多保存的记录数
+    #[serde(default = "default_dns_cache_size")]
+    pub dns_cache_size: usize,
+}
+
+impl Default for UpstreamConfig {
+    fn default() -> Self {
+        Self {
+            max_concurrent_requests: default_max_concurrent_requests(),
+            http_proxy: None,
+            http_retries: default_http_retries(),
+            connect_timeout_ms: default_connect_timeout_ms(),
+            request_timeout_secs: None,
+            bgptools_scrape_timeout_secs: default_bgptools_scrape_timeout_secs(),
+            bgptools_max_body_bytes: default_bgptools_max_body_bytes(),
+            keep_raw_bgp_api: false,
+            dns_cache_enabled: true,
+            dns_cache_size: default_dns_cache_size(),
+        }
+    }
+}
+
+fn default_preload_concurrency() -> usize {
+    8
+}
+
+fn default_http_retries() -> u32 {
+    2
+}
+
+fn default_connect_timeout_ms() -> u64 {
+    3000
+}
+
+fn default_bgptools_scrape_timeout_secs() -> u64 {
+    10
+}
+
+fn default_bgptools_max_body_bytes() -> usize {
+    2 * 1024 * 1024
+}
+
+fn default_dns_cache_size() -> usize {
+    256
+}
+
+fn default_max_concurrent_requests() -> usize {
+    64
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct WhoisConfig {
+    /// 除内置字段外额外提取的WHOIS属性名（如 remarks, created, status）
+    #[serde(default)]
+    pub extra_fields: Vec<String>,
+    /// 两次WHOIS查询之间的最小间隔（毫秒），避免触发服务器限速
+    #[serde(default = "default_whois_min_interval_ms")]
+    pub min_interval_ms: u64,
+    /// 被限速后暂停WHOIS查询的时间（秒）
+    #[serde(default = "default_whois_backoff_secs")]
+    pub backoff_secs: u64,
+    /// 优先通过RDAP（按IANA引导注册表选择服务器）查询注册信息，失败时回退到43端口WHOIS
+    /// RDAP的地址对象不含路由对象，此时 route 和 origin 为空（风险评分的 origin_mismatch 也不会触发），因此默认关闭
+    #[serde(default)]
+    pub prefer_rdap: bool,
+}
+
+impl Default for WhoisConfig {
+    fn default() -> Self {
+        Self {
+            extra_fields: Vec::new(),
+            min_interval_ms: default_whois_min_interval_ms(),
+            backoff_secs: default_whois_backoff_secs(),
+            prefer_rdap: false,
+        }
+    }
+}
+
+fn default_whois_min_interval_ms() -> u64 {
+    200
+}
+
+fn default_whois_backoff_secs() -> u64 {
+    60
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct PrivacyConfig {
+    /// 日志中截断IP（IPv4清零最后一个字节，IPv6清零后80位）
+    #[serde(default)]
+    pub anonymize_logs: bool,
+    /// 缓存键同样使用截断后的IP（同一网段共享缓存结果）
+    #[serde(default)]
+    pub anonymize_cache_keys: bool,
+    /// 配置后缓存键为IP的HMAC-SHA256摘要，持久化文件和Redis中不出现明文IP，
+    /// 代价是无法再按IP检查缓存内容；更换密钥后原有缓存条目全部失效
+    #[serde(default)]
+    pub cache_key_secret: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct MaxmindConfig {
+    #[serde(default)]
     pub account_id: u64,
+    #[serde(default)]
     pub license_key: String,
     pub update_interval_hours: u64,
+    /// 显式下载地址，优先于 edition_ids
+    #[serde(default)]
     pub download_urls: MaxmindUrls,
+    /// 未配置下载地址时，按版本ID拼接MaxMind标准下载地址
+    #[serde(default)]
+    pub edition_ids: MaxmindEditions,
     pub database_dir: String,
+    /// 数据库下载或加载失败时仍然启动服务，只提供上游数据，并在后台重试加载
+    #[serde(default)]
+    pub start_without_databases: bool,
+    /// ASN到组织名称的覆盖表（如 16276: OVHcloud），查询到的ASN在表中时替换MaxMind的组织名称
+    #[serde(default)]
+    pub organization_overrides: HashMap<u32, String>,
+    /// 私有/保留地址的处理方式：reserved（返回"保留地址"）、reject（返回422）、lookup（照常查询）
+    #[serde(default)]
+    pub private_ip_behavior: PrivateIpBehavior,
+    /// 以内存映射方式打开数据库，多个进程共享操作系统页缓存，不支持时回退为读入内存
+    #[serde(default)]
+    pub use_mmap: bool,
+    /// 同时下载的数据库数量，不宜超过MaxMind允许的并发连接数
+    #[serde(default = "default_download_concurrency")]
+    pub download_concurrency: usize,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+fn default_download_concurrency() -> usize {
+    3
+}
+
+/// 私有/保留地址（RFC1918、回环、链路本地等）的处理方式
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum PrivateIpBehavior {
+    /// 不查询数据库，直接返回"保留地址"
+    #[default]
+    Reserved,
+    /// 拒绝查询
+    Reject,
+    /// 与公网地址一样查询数据库和上游（适用于自建的内网数据库）
+    Lookup,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
 pub struct MaxmindUrls {
+    #[serde(default)]
+    pub asn: String,
+    #[serde(default)]
+    pub city: String,
+    #[serde(default)]
+    pub country: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct MaxmindEditions {
     pub asn: String,
     pub city: String,
     pub country: String,
 }
 
+impl Default for MaxmindEditions {
+    fn default() -> Self {
+        Self {
+            asn: "GeoLite2-ASN".to_string(),
+            city: "GeoLite2-City".to_string(),
+            country: "GeoLite2-Country".to_string(),
+        }
+    }
+}
+
 impl Config {
-    pub fn load<P: AsRef<Path>>(path: P) -> Result<Arc<Config>, String> {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Config, String> {
         let mut file = File::open(path).map_err(|e| format!("打开配置文件失败: {}", e))?;
         let mut contents = String::new();
         file.read_to_string(&mut contents)
@@ -42,10 +838,337 @@ impl Config {
         let config: Config = serde_yaml::from_str(&contents)
             .map_err(|e| format!("解析配置文件失败: {}", e))?;
 
-        Ok(Arc::new(config))
+        Ok(config)
+    }
+
+    /// 使用环境变量覆盖配置文件中的值（优先级：环境变量 > 配置文件）
+    pub fn apply_env_overrides(&mut self) -> Result<(), String> {
+        override_from_env("APP_NAME", &mut self.app.name)?;
+        override_from_env("APP_PORT", &mut self.app.port)?;
+        if let Ok(token) = std::env::var("APP_ADMIN_TOKEN")
+            && !token.trim().is_empty() {
+            self.app.admin_token = Some(token.trim().to_string());
+            tracing::info!("配置项已由环境变量 APP_ADMIN_TOKEN 覆盖");
+        }
+        override_from_env("MAXMIND_ACCOUNT_ID", &mut self.maxmind.account_id)?;
+        override_from_env("MAXMIND_LICENSE_KEY", &mut self.maxmind.license_key)?;
+        override_from_env("MAXMIND_UPDATE_INTERVAL_HOURS", &mut self.maxmind.update_interval_hours)?;
+        override_from_env("MAXMIND_DATABASE_DIR", &mut self.maxmind.database_dir)?;
+        override_from_env("MAXMIND_DOWNLOAD_URL_ASN", &mut self.maxmind.download_urls.asn)?;
+        override_from_env("MAXMIND_DOWNLOAD_URL_CITY", &mut self.maxmind.download_urls.city)?;
+        override_from_env("MAXMIND_DOWNLOAD_URL_COUNTRY", &mut self.maxmind.download_urls.country)?;
+        override_from_env("MAXMIND_EDITION_ID_ASN", &mut self.maxmind.edition_ids.asn)?;
+        override_from_env("MAXMIND_EDITION_ID_CITY", &mut self.maxmind.edition_ids.city)?;
+        override_from_env("MAXMIND_EDITION_ID_COUNTRY", &mut self.maxmind.edition_ids.country)?;
+        Ok(())
     }
+
+    /// 校验配置中的不变量，返回第一个不满足的字段说明
+    pub fn validate(&self) -> Result<(), String> {
+        if self.app.port == 0 {
+            return Err("app.port 必须在 1-65535 之间".to_string());
+        }
+        if let Some(base_path) = &self.app.base_path
+            && !base_path.is_empty()
+            && !base_path.starts_with('/') {
+            return Err(format!("app.base_path 必须以 / 开头: {}", base_path));
+        }
+        match self.app.admin_port {
+            Some(0) => return Err("app.admin_port 必须在 1-65535 之间".to_string()),
+            Some(admin_port) if admin_port == self.app.port => {
+                return Err("app.admin_port 不能与 app.port 相同".to_string());
+            }
+            _ => {}
+        }
+        if self.cache.cleanup_interval_secs == 0 {
+            return Err("cache.cleanup_interval_secs 必须大于 0".to_string());
+        }
+        if self.cache.ttl_secs == 0 {
+            return Err("cache.ttl_secs 必须大于 0".to_string());
+        }
+        if self.cache.preload_concurrency == 0 {
+            return Err("cache.preload_concurrency 必须大于 0".to_string());
+        }
+        if let Some(prefix_len) = self.cache.ipv6_group_prefix
+            && !(1..=128).contains(&prefix_len) {
+            return Err("cache.ipv6_group_prefix 必须在 1-128 之间".to_string());
+        }
+        if self.lookup.min_prefix_v4 > 32 {
+            return Err("lookup.min_prefix_v4 必须在 0-32 之间".to_string());
+        }
+        if self.lookup.min_prefix_v6 > 128 {
+            return Err("lookup.min_prefix_v6 必须在 0-128 之间".to_string());
+        }
+        if self.lookup.max_ips_per_request == 0 {
+            return Err("lookup.max_ips_per_request 必须大于 0".to_string());
+        }
+        for prefix in &self.anycast.prefixes {
+            prefix.parse::<ipnet::IpNet>()
+                .map_err(|e| format!("anycast.prefixes 中的前缀无效 ({}): {}", prefix, e))?;
+        }
+        if self.publisher.queue_size == 0 {
+            return Err("publisher.queue_size 必须大于 0".to_string());
+        }
+        if self.publisher.subject.is_empty() || self.publisher.subject.contains(char::is_whitespace) {
+            return Err("publisher.subject 不能为空或包含空白字符".to_string());
+        }
+        for rule in &self.overrides {
+            rule.network()?;
+        }
+        if self.upstream.max_concurrent_requests == 0 {
+            return Err("upstream.max_concurrent_requests 必须大于 0".to_string());
+        }
+        if self.upstream.connect_timeout_ms == 0 {
+            return Err("upstream.connect_timeout_ms 必须大于 0".to_string());
+        }
+        if self.upstream.request_timeout_secs == Some(0) {
+            return Err("upstream.request_timeout_secs 必须大于 0".to_string());
+        }
+        if self.upstream.bgptools_scrape_timeout_secs == 0 {
+            return Err("upstream.bgptools_scrape_timeout_secs 必须大于 0".to_string());
+        }
+        if self.upstream.bgptools_max_body_bytes == 0 {
+            return Err("upstream.bgptools_max_body_bytes 必须大于 0".to_string());
+        }
+        if let Some(proxy) = &self.upstream.http_proxy {
+            reqwest::Proxy::all(proxy)
+                .map_err(|e| format!("upstream.http_proxy 无效 ({}): {}", proxy, e))?;
+        }
+        if self.privacy.cache_key_secret.as_deref().is_some_and(str::is_empty) {
+            return Err("privacy.cache_key_secret 不能为空".to_string());
+        }
+        if let Some(url) = &self.cache.redis_url {
+            redis::Client::open(url.as_str())
+                .map_err(|e| format!("cache.redis_url 无效 ({}): {}", url, e))?;
+        }
+        for url in std::iter::once(&self.rpki.validator_url).chain(&self.rpki.extra_validators) {
+            reqwest::Url::parse(url)
+                .map_err(|e| format!("RPKI验证器地址无效 ({}): {}", url, e))?;
+        }
+        let mut key_names = HashSet::new();
+        let mut keys = HashSet::new();
+        for key in &self.api_keys.keys {
+            if key.name.trim().is_empty() || key.key.trim().is_empty() {
+                return Err("api_keys.keys 中的 name 和 key 不能为空".to_string());
+            }
+            if !key_names.insert(key.name.as_str()) {
+                return Err(format!("api_keys.keys 中的名称重复: {}", key.name));
+            }
+            if !keys.insert(key.key.as_str()) {
+                return Err(format!("api_keys.keys 中 {} 的Key与其他Key重复", key.name));
+            }
+        }
+        for url in &self.webhook.urls {
+            reqwest::Url::parse(url)
+                .map_err(|e| format!("webhook.urls 中的地址无效 ({}): {}", url, e))?;
+        }
+        if self.maxmind.account_id == 0 {
+            return Err("maxmind.account_id 为空".to_string());
+        }
+        if self.maxmind.license_key.trim().is_empty() {
+            return Err("maxmind.license_key 为空".to_string());
+        }
+        if self.maxmind.download_concurrency == 0 {
+            return Err("maxmind.download_concurrency 必须大于 0".to_string());
+        }
+        if self.maxmind.database_dir.trim().is_empty() {
+            return Err("maxmind.database_dir 为空".to_string());
+        }
+        Self::check_dir_writable(&self.maxmind.database_dir)
+            .map_err(|e| format!("maxmind.database_dir 不可写 ({}): {}", self.maxmind.database_dir, e))?;
+
+        let urls = [
+            ("asn", &self.maxmind.download_urls.asn, &self.maxmind.edition_ids.asn),
+            ("city", &self.maxmind.download_urls.city, &self.maxmind.edition_ids.city),
+            ("country", &self.maxmind.download_urls.country, &self.maxmind.edition_ids.country),
+        ];
+        for (db_type, url, edition_id) in urls {
+            if url.trim().is_empty() {
+                if edition_id.trim().is_empty() {
+                    return Err(format!(
+                        "maxmind.download_urls.{0} 与 maxmind.edition_ids.{0} 不能同时为空",
+                        db_type
+                    ));
+                }
+                continue;
+            }
+            let field = format!("maxmind.download_urls.{}", db_type);
+            match reqwest::Url::parse(url) {
+                Ok(parsed) if parsed.scheme() == "http" || parsed.scheme() == "https" => {}
+                Ok(parsed) => return Err(format!("{} 必须是 http/https URL，当前协议: {}", field, parsed.scheme())),
+                Err(e) => return Err(format!("{} 不是有效的URL ({}): {}", field, url, e)),
+            }
+        }
+        Ok(())
+    }
+
+    /// 确保目录存在并可写入
+    fn check_dir_writable(dir: &str) -> Result<(), String> {
+        let path = Path::new(dir);
+        std::fs::create_dir_all(path).map_err(|e| format!("创建目录失败: {}", e))?;
+        let probe = path.join(".write_test");
+        File::create(&probe).map_err(|e| format!("写入测试文件失败: {}", e))?;
+        let _ = std::fs::remove_file(&probe);
+        Ok(())
+    }
+}
+
+/// 若环境变量存在且非空，则解析后覆盖目标字段
+fn override_from_env<T: FromStr>(key: &str, target: &mut T) -> Result<(), String>
+where
+    T::Err: std::fmt::Display,
+{
+    if let Ok(value) = std::env::var(key)
+        && !value.trim().is_empty() {
+        *target = value.trim().parse()
+            .map_err(|e| format!("环境变量 {} 的值无效: {}", key, e))?;
+        tracing::info!("配置项已由环境变量 {} 覆盖", key);
+    }
+    Ok(())
+}
+
+/// 将配置展开为 "section.field" => 值 的扁平列表，用于比较差异
+fn flatten_config(config: &Config) -> Vec<(String, serde_json::Value)> {
+    fn walk(prefix: &str, value: &serde_json::Value, out: &mut Vec<(String, serde_json::Value)>) {
+        match value {
+            serde_json::Value::Object(map) => {
+                for (key, child) in map {
+                    let path = if prefix.is_empty() { key.clone() } else { format!("{}.{}", prefix, key) };
+                    walk(&path, child, out);
+                }
+            }
+            _ => out.push((prefix.to_string(), value.clone())),
+        }
+    }
+    let mut out = Vec::new();
+    if let Ok(value) = serde_json::to_value(config) {
+        walk("", &value, &mut out);
+    }
+    out
+}
+
+/// 列出两份配置之间发生变化的配置项，包括新配置中已删除的项（如映射表中删除的键）
+fn changed_keys(old: &Config, new: &Config) -> Vec<String> {
+    let old_values = flatten_config(old);
+    let new_values = flatten_config(new);
+    let removed: Vec<String> = old_values
+        .iter()
+        .filter(|(key, _)| !new_values.iter().any(|(k, _)| k == key))
+        .map(|(key, _)| key.clone())
+        .collect();
+    new_values.into_iter()
+        .filter(|(key, value)| old_values.iter().find(|(k, _)| k == key).map(|(_, v)| v) != Some(value))
+        .map(|(key, _)| key)
+        .chain(removed)
+        .collect()
+}
+
+/// 配置项本身或其所在的映射表（如 maxmind.organization_overrides.16276）需要重启才能生效
+fn requires_restart(key: &str) -> bool {
+    RESTART_REQUIRED_KEYS
+        .iter()
+        .any(|(restart_key, _)| key.strip_prefix(restart_key).is_some_and(|rest| rest.is_empty() || rest.starts_with('.')))
+}
+
+fn load_and_validate() -> Result<Config, String> {
+    let mut config = Config::load("config.yaml")?;
+    config.apply_env_overrides()?;
+    config.validate().map_err(|e| format!("配置校验失败: {}", e))?;
+    Ok(config)
 }
 
 pub fn init() -> Result<Arc<Config>, String> {
-    Config::load("config.yaml")
-} 
\ No newline at end of file
+    load_and_validate().map(Arc::new)
+}
+
+/// 重新读取配置文件并热更新可重载的部分，需要重启的配置项保持旧值
+pub async fn reload(shared: &SharedConfig) -> Result<(), String> {
+    let mut new_config = load_and_validate()?;
+    let old_config = shared.read().await.clone();
+
+    let changed = changed_keys(&old_config, &new_config);
+    if changed.is_empty() {
+        tracing::info!("配置文件无变化");
+        return Ok(());
+    }
+
+    for key in &changed {
+        if requires_restart(key) {
+            tracing::warn!("配置项 {} 已修改，但需要重启后才能生效，本次忽略", key);
+        } else {
+            tracing::info!("配置项 {} 已热更新", key);
+        }
+    }
+
+    // 需要重启的配置项保持当前运行值
+    for (_, restore) in RESTART_REQUIRED_KEYS {
+        restore(&mut new_config, &old_config);
+    }
+
+    crate::utils::privacy::configure(&new_config.privacy);
+    crate::utils::whois_client::configure(&new_config.whois);
+    crate::utils::bgptools_client::configure(&new_config.upstream);
+    *shared.write().await = Arc::new(new_config);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MINIMAL_CONFIG: &str = "app: { name: test, port: 8080 }\n\
+        maxmind: { account_id: 1, update_interval_hours: 24, database_dir: data, organization_overrides: { 16276: OVHcloud } }\n";
+
+    #[test]
+    fn map_entries_under_restart_only_keys_require_restart() {
+        let old: Config = serde_yaml::from_str(MINIMAL_CONFIG).unwrap();
+        let mut new = old.clone();
+        new.maxmind.organization_overrides = HashMap::from([(13335, "Cloudflare".to_string())]);
+        new.app.admin_token = Some("secret".to_string());
+
+        let mut changed = changed_keys(&old, &new);
+        changed.sort();
+        assert_eq!(changed, ["app.admin_token", "maxmind.organization_overrides.13335", "maxmind.organization_overrides.16276"]);
+        assert!(requires_restart("maxmind.organization_overrides.13335"));
+        assert!(requires_restart("maxmind.organization_overrides.16276"));
+        assert!(!requires_restart("app.admin_token"));
+        assert!(!requires_restart("app.port_range"));
+    }
+
+    #[test]
+    fn port_43_whois_is_the_default_so_route_and_origin_are_kept() {
+        let config: Config = serde_yaml::from_str(MINIMAL_CONFIG).unwrap();
+        assert!(!config.whois.prefer_rdap);
+        assert!(!WhoisConfig::default().prefer_rdap);
+    }
+
+    #[test]
+    fn every_restart_only_key_is_restored_on_reload() {
+        let old: Config = serde_yaml::from_str(MINIMAL_CONFIG).unwrap();
+        let mut new: Config = serde_yaml::from_str(
+            "app: { name: test, port: 9090, admin_port: 9091, base_path: /ipapi, ops_under_base_path: true, admin_token: secret }\n\
+             maxmind: { account_id: 1, update_interval_hours: 24, database_dir: other, start_without_databases: true, \
+                        organization_overrides: { 13335: Cloudflare }, private_ip_behavior: reject, use_mmap: true }\n\
+             cache: { cleanup_interval_secs: 1, ttl_secs: 1, persist_format: json, persist_compression: true, ipv6_group_prefix: 64, \
+                      stale_window_secs: 1, error_grace_secs: 1, slow_persist_warn_ms: 1, ttl_jitter_secs: 1, memory_limit_mb: 1, \
+                      disk_tier_dir: tier, disk_tier_max_mb: 1, preload_file: preload.txt, preload_concurrency: 1, \
+                      redis_url: redis://127.0.0.1, redis_key_prefix: other }\n\
+             endpoints: { enabled: [lookup] }\n\
+             upstream: { max_concurrent_requests: 1, http_proxy: http://127.0.0.1:3128, http_retries: 9, connect_timeout_ms: 1, \
+                         request_timeout_secs: 1, dns_cache_enabled: false, dns_cache_size: 1 }\n\
+             publisher: { nats_url: nats://127.0.0.1, subject: other, queue_size: 1 }\n",
+        )
+        .unwrap();
+
+        // 测试配置覆盖了表中的每一项
+        let changed = changed_keys(&old, &new);
+        for (key, _) in RESTART_REQUIRED_KEYS {
+            assert!(changed.iter().any(|changed| requires_restart(changed) && changed.starts_with(key)), "{} 未修改", key);
+        }
+
+        for (_, restore) in RESTART_REQUIRED_KEYS {
+            restore(&mut new, &old);
+        }
+        assert_eq!(changed_keys(&old, &new), ["app.admin_token"]);
+    }
+}
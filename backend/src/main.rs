@@ -4,21 +4,62 @@ mod maxmind;
 mod scheduler;
 mod utils;
 
-use api::{create_router, IpApiHandler};
+use api::{create_router, create_routers, IpApiHandler};
 use maxmind::{MaxmindReader, MaxmindUpdater};
 use scheduler::Scheduler;
-use utils::ip_cache::IpCache;
+use utils::disk_tier::DiskTier;
+use utils::ip_cache::{CacheBackend, IpCache};
+use utils::redis_cache::RedisCache;
+use utils::publisher::LookupPublisher;
 use std::sync::Arc;
 use tokio::sync::RwLock;
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 use std::net::SocketAddr;
 use std::path::Path;
+use std::time::Duration;
 
-fn all_mmdb_exists(dir: &str) -> bool {
-    let asn = Path::new(dir).join("GeoLite2-Asn.mmdb");
-    let city = Path::new(dir).join("GeoLite2-City.mmdb");
-    let country = Path::new(dir).join("GeoLite2-Country.mmdb");
-    asn.exists() && city.exists() && country.exists()
+/// 无数据库模式下定期重试加载，成功后停止
+fn spawn_database_retry(reader: maxmind::reader::SharedReader) {
+    const RETRY_INTERVAL: Duration = Duration::from_secs(30);
+
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(RETRY_INTERVAL).await;
+            match MaxmindReader::reload(&reader).await {
+                Ok(()) => {
+                    tracing::info!("MaxMind数据库后台加载成功，恢复完整查询");
+                    break;
+                }
+                Err(e) => tracing::warn!("MaxMind数据库后台加载失败，{:?}后重试: {}", RETRY_INTERVAL, e),
+            }
+        }
+    });
+}
+
+#[cfg(unix)]
+fn spawn_reload_handler(shared_config: config::SharedConfig) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut hangup = match signal(SignalKind::hangup()) {
+        Ok(s) => s,
+        Err(e) => {
+            tracing::error!("注册SIGHUP信号处理失败: {}", e);
+            return;
+        }
+    };
+    tokio::spawn(async move {
+        while hangup.recv().await.is_some() {
+            tracing::info!("收到SIGHUP信号，重新加载配置...");
+            if let Err(e) = config::reload(&shared_config).await {
+                tracing::error!("重新加载配置失败，继续使用当前配置: {}", e);
+            }
+        }
+    });
+}
+
+#[cfg(not(unix))]
+fn spawn_reload_handler(_shared_config: config::SharedConfig) {
+    tracing::info!("当前平台不支持SIGHUP，配置热更新已禁用");
 }
 
 #[tokio::main]
@@ -32,57 +73,121 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     // 加载配置
     let config = config::init().map_err(|e| format!("配置初始化失败: {}", e))?;
     tracing::info!("配置加载成功");
+    utils::privacy::configure(&config.privacy);
+    utils::whois_client::configure(&config.whois);
+    utils::upstream::configure(&config.upstream);
+    utils::bgptools_client::configure(&config.upstream);
     
+    let shared_config: config::SharedConfig = Arc::new(RwLock::new(config.clone()));
+
     // 创建MaxMind数据库更新器
     let maxmind_config = Arc::new(config.maxmind.clone());
     let mut updater = MaxmindUpdater::new(maxmind_config.clone());
     
     // 创建MaxMind数据库读取器
     let reader = MaxmindReader::new(maxmind_config.clone());
-    let reader_arc = Arc::new(RwLock::new(reader));
-    
-    // 创建IP缓存
-    let cache_path = Path::new("data").join("ip_cache.bin");
-    let ip_cache = IpCache::new(cache_path);
-    let ip_cache_arc = Arc::new(ip_cache);
+    let reader_arc: maxmind::reader::SharedReader = Arc::new(arc_swap::ArcSwap::from_pointee(reader));
     
-    // 启动IP缓存后台任务（数据加载、定期持久化、过期清理）
-    ip_cache_arc.start_tasks().await;
+    // 创建IP缓存：配置了Redis时使用多实例共享的Redis缓存，否则使用本地缓存文件
+    let ip_cache_arc: Arc<dyn CacheBackend> = match &config.cache.redis_url {
+        Some(url) => {
+            let mut redis_cache = RedisCache::new(url, &config.cache.redis_key_prefix, Duration::from_secs(config.cache.ttl_secs))?
+                .with_ipv6_grouping(config.cache.ipv6_group_prefix);
+            if let Some(stale_window_secs) = config.cache.stale_window_secs {
+                redis_cache = redis_cache.with_stale_window(Duration::from_secs(stale_window_secs));
+            }
+            if let Some(ttl_jitter_secs) = config.cache.ttl_jitter_secs {
+                redis_cache = redis_cache.with_ttl_jitter(Duration::from_secs(ttl_jitter_secs));
+            }
+            if let Some(error_grace_secs) = config.cache.error_grace_secs {
+                redis_cache = redis_cache.with_error_grace(Duration::from_secs(error_grace_secs));
+            }
+            tracing::info!("IP缓存使用Redis");
+            Arc::new(redis_cache)
+        }
+        None => {
+            let cache_path = Path::new("data").join("ip_cache.bin");
+            let mut ip_cache = IpCache::new(cache_path, Duration::from_secs(config.cache.ttl_secs), config.cache.persist_format)
+                .with_compression(config.cache.persist_compression)
+                .with_slow_persist_threshold(config.cache.slow_persist_warn_ms.map(Duration::from_millis))
+                .with_ipv6_grouping(config.cache.ipv6_group_prefix);
+            if let Some(stale_window_secs) = config.cache.stale_window_secs {
+                ip_cache = ip_cache.with_stale_window(Duration::from_secs(stale_window_secs));
+            }
+            if let Some(ttl_jitter_secs) = config.cache.ttl_jitter_secs {
+                ip_cache = ip_cache.with_ttl_jitter(Duration::from_secs(ttl_jitter_secs));
+            }
+            if let Some(error_grace_secs) = config.cache.error_grace_secs {
+                ip_cache = ip_cache.with_error_grace(Duration::from_secs(error_grace_secs));
+            }
+            if let Some(memory_limit_mb) = config.cache.memory_limit_mb {
+                ip_cache = ip_cache.with_memory_limit(memory_limit_mb * 1024 * 1024);
+            }
+            if let Some(dir) = &config.cache.disk_tier_dir {
+                let disk_tier = DiskTier::open(dir, config.cache.disk_tier_max_mb * 1024 * 1024)?;
+                ip_cache = ip_cache.with_disk_tier(disk_tier);
+            }
+            
+            // 预热缓存：在开始服务之前完成持久化数据的加载，避免重启后的缓存未命中风暴
+            let warm_start = std::time::Instant::now();
+            let warm_entries = ip_cache.warm_up().await;
+            tracing::info!("IP缓存预热完成，条目数: {}，耗时: {:?}", warm_entries, warm_start.elapsed());
+            
+            // 启动IP缓存后台任务（定期持久化、过期清理）
+            ip_cache.start_tasks(Duration::from_secs(config.cache.cleanup_interval_secs));
+            Arc::new(ip_cache)
+        }
+    };
     tracing::info!("IP缓存系统已初始化");
     
     // 启动时如果本地已存在所有mmdb数据库文件，则跳过首次下载
-    if all_mmdb_exists(&config.maxmind.database_dir) {
+    let start_without_databases = config.maxmind.start_without_databases;
+    let mut databases_ready = true;
+    if maxmind::local_databases_exist(&config.maxmind.database_dir) {
         tracing::info!("检测到本地已存在所有mmdb数据库文件，跳过首次下载");
     } else {
         tracing::info!("首次启动，开始下载MaxMind数据库...");
-        updater.update().await.map_err(|e| format!("MaxMind数据库初始化失败: {}", e))?;
+        if let Err(e) = updater.update().await {
+            if !start_without_databases {
+                return Err(format!("MaxMind数据库初始化失败: {}", e).into());
+            }
+            tracing::error!("MaxMind数据库初始化失败，以无数据库模式启动: {}", e);
+        }
     }
     
     // 加载数据库
-    {
-        let mut reader = reader_arc.write().await;
-        reader.load_databases().map_err(|e| format!("加载MaxMind数据库失败: {}", e))?;
+    if let Err(e) = MaxmindReader::reload(&reader_arc).await {
+        if !start_without_databases {
+            return Err(format!("加载MaxMind数据库失败: {}", e).into());
+        }
+        tracing::error!("加载MaxMind数据库失败，以无数据库模式启动，查询只返回上游数据: {}", e);
+        databases_ready = false;
+        spawn_database_retry(reader_arc.clone());
+    }
+    // 自检未通过时仍然启动，但健康检查返回503，避免流量打到数据库损坏的实例
+    if reader_arc.load().self_test_passed() {
+        tracing::info!("MaxMind数据库自检通过");
+    } else if databases_ready {
+        tracing::warn!("MaxMind数据库自检未通过，健康检查将报告为不可用");
     }
 
     // 设置更新定时任务
     let reader_arc_clone = reader_arc.clone();
     let mut scheduler = Scheduler::new();
     
+    let scheduler_config = shared_config.clone();
     scheduler.schedule_daily("maxmind_db_update", 0, 0, move || {
-        let updater_config = maxmind_config.clone();
+        let scheduler_config = scheduler_config.clone();
         let reader_arc_update = reader_arc_clone.clone();
         
         tokio::spawn(async move {
+            // 每次更新时读取最新配置，使热更新的许可证和下载地址生效
+            let updater_config = Arc::new(scheduler_config.read().await.maxmind.clone());
             let mut updater = MaxmindUpdater::new(updater_config);
             
-            if let Err(e) = updater.update().await {
+            // 新数据库加载完成后原子替换，期间查询继续使用旧数据库
+            if let Err(e) = updater.update_and_reload(&reader_arc_update).await {
                 tracing::error!("MaxMind更新失败: {}", e);
-                return;
-            }
-            
-            let mut reader = reader_arc_update.write().await;
-            if let Err(e) = reader.load_databases() {
-                tracing::error!("重新加载MaxMind数据库失败: {}", e);
             }
         });
         
@@ -92,9 +197,39 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     // 启动定时任务调度器
     scheduler.start().await;
     
+    // 收到SIGHUP时重新加载配置
+    spawn_reload_handler(shared_config.clone());
+    
     // 创建HTTP路由
-    let ip_handler = IpApiHandler::new(reader_arc.clone(), ip_cache_arc.clone());
-    let app = create_router(ip_handler);
+    let ip_handler = IpApiHandler::new(reader_arc.clone(), ip_cache_arc.clone(), shared_config.clone(), Path::new("data"))
+        .with_publisher(LookupPublisher::start(&config.publisher));
+    
+    // 在开始服务之前预加载已知的IP列表
+    if let Some(preload_file) = &config.cache.preload_file {
+        let preload_start = std::time::Instant::now();
+        match ip_handler.preload(preload_file, config.cache.preload_concurrency).await {
+            Ok(loaded) => tracing::info!("缓存预加载完成，新增条目: {}，耗时: {:?}", loaded, preload_start.elapsed()),
+            Err(e) => tracing::error!("缓存预加载失败: {}", e),
+        }
+    }
+    let app = match config.app.admin_port {
+        Some(admin_port) => {
+            // 运维接口使用独立端口，公开端口只提供查询接口
+            let (public, admin) = create_routers(ip_handler, &config.app, &config.endpoints);
+            let admin_addr: SocketAddr = format!("0.0.0.0:{}", admin_port)
+                .parse()
+                .expect("无效的地址格式");
+            let admin_listener = tokio::net::TcpListener::bind(admin_addr).await?;
+            tracing::info!("运维接口启动, 监听地址: {}", admin_addr);
+            tokio::spawn(async move {
+                if let Err(e) = axum::serve(admin_listener, admin).await {
+                    tracing::error!("运维接口服务异常退出: {}", e);
+                }
+            });
+            public
+        }
+        None => create_router(ip_handler, &config.app, &config.endpoints),
+    };
     
     // 启动HTTP服务器
     let addr: SocketAddr = format!("0.0.0.0:{}", config.app.port)
@@ -0,0 +1,151 @@
+use log::info;
+use std::fs::File;
+use std::io::{self, BufReader, Read};
+use std::path::{Path, PathBuf};
+
+/// mmdb元数据段的起始标记，解压结果中找不到时视为无效数据库
+const METADATA_MARKER: &[u8] = b"\xab\xcd\xefMaxMind.com";
+
+/// gzip文件头的魔数
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// 镜像站分发的压缩数据库格式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Compression {
+    Brotli,
+    Gzip,
+}
+
+impl Compression {
+    const ALL: [Compression; 2] = [Compression::Brotli, Compression::Gzip];
+
+    fn extension(self) -> &'static str {
+        match self {
+            Compression::Brotli => "br",
+            Compression::Gzip => "gz",
+        }
+    }
+
+    /// brotli没有魔数，只能在解压后校验内容；gzip校验文件头
+    fn check_magic(self, file: &mut File) -> Result<(), String> {
+        if self == Compression::Gzip {
+            let mut magic = [0u8; 2];
+            file.read_exact(&mut magic).map_err(|e| format!("读取文件头失败: {}", e))?;
+            if magic != GZIP_MAGIC {
+                return Err("文件头不是gzip格式".to_string());
+            }
+            io::Seek::rewind(file).map_err(|e| format!("读取文件头失败: {}", e))?;
+        }
+        Ok(())
+    }
+
+    fn decoder(self, file: File) -> Box<dyn Read> {
+        let file = BufReader::new(file);
+        match self {
+            Compression::Brotli => Box::new(brotli::Decompressor::new(file, 4096)),
+            Compression::Gzip => Box::new(flate2::read::GzDecoder::new(file)),
+        }
+    }
+}
+
+fn with_extension(db_path: &Path, compression: Compression) -> PathBuf {
+    let mut path = db_path.as_os_str().to_owned();
+    path.push(".");
+    path.push(compression.extension());
+    PathBuf::from(path)
+}
+
+fn modified(path: &Path) -> Option<std::time::SystemTime> {
+    path.metadata().and_then(|m| m.modified()).ok()
+}
+
+/// 数据库文件或其 .br / .gz 压缩版本存在（压缩版本在加载时解压）
+pub fn database_exists(db_path: &Path) -> bool {
+    db_path.exists() || Compression::ALL.iter().any(|compression| with_extension(db_path, *compression).exists())
+}
+
+/// 数据库目录中存在比 db_path 更新的 .mmdb.br / .mmdb.gz 时，解压到 db_path
+/// 先解压到临时文件并校验，再重命名替换，不会留下不完整的数据库
+pub fn decompress_if_needed(db_path: &Path) -> Result<(), String> {
+    let target_modified = modified(db_path);
+    for compression in Compression::ALL {
+        let compressed_path = with_extension(db_path, compression);
+        let Some(compressed_modified) = modified(&compressed_path) else {
+            continue;
+        };
+        if target_modified.is_some_and(|target| target >= compressed_modified) {
+            continue;
+        }
+        info!("解压数据库 {} -> {}", compressed_path.display(), db_path.display());
+        return decompress(&compressed_path, db_path, compression)
+            .map_err(|e| format!("解压 {} 失败: {}", compressed_path.display(), e));
+    }
+    Ok(())
+}
+
+fn decompress(compressed_path: &Path, db_path: &Path, compression: Compression) -> Result<(), String> {
+    let mut file = File::open(compressed_path).map_err(|e| format!("打开文件失败: {}", e))?;
+    compression.check_magic(&mut file)?;
+    let mut contents = Vec::new();
+    compression
+        .decoder(file)
+        .read_to_end(&mut contents)
+        .map_err(|e| format!("解压失败: {}", e))?;
+    if !contents.windows(METADATA_MARKER.len()).any(|w| w == METADATA_MARKER) {
+        return Err("解压结果不是mmdb数据库".to_string());
+    }
+    let staging_path = db_path.with_extension("mmdb.tmp");
+    std::fs::write(&staging_path, &contents).map_err(|e| format!("写入临时文件失败: {}", e))?;
+    std::fs::rename(&staging_path, db_path).map_err(|e| format!("替换数据库文件失败: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn fake_mmdb() -> Vec<u8> {
+        let mut contents = vec![0u8; 64];
+        contents.extend_from_slice(METADATA_MARKER);
+        contents
+    }
+
+    #[test]
+    fn brotli_and_gzip_databases_are_decompressed() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let asn_path = dir.path().join("GeoLite2-ASN.mmdb");
+        let mut brotli_file = File::create(with_extension(&asn_path, Compression::Brotli)).unwrap();
+        let mut writer = brotli::CompressorWriter::new(&mut brotli_file, 4096, 5, 22);
+        writer.write_all(&fake_mmdb()).unwrap();
+        drop(writer);
+        decompress_if_needed(&asn_path).unwrap();
+        assert_eq!(std::fs::read(&asn_path).unwrap(), fake_mmdb());
+
+        let city_path = dir.path().join("GeoLite2-City.mmdb");
+        let gzip_file = File::create(with_extension(&city_path, Compression::Gzip)).unwrap();
+        let mut writer = flate2::write::GzEncoder::new(gzip_file, flate2::Compression::default());
+        writer.write_all(&fake_mmdb()).unwrap();
+        writer.finish().unwrap();
+        decompress_if_needed(&city_path).unwrap();
+        assert_eq!(std::fs::read(&city_path).unwrap(), fake_mmdb());
+    }
+
+    #[test]
+    fn compressed_databases_count_as_present() {
+        let dir = tempfile::tempdir().unwrap();
+        let db_path = dir.path().join("GeoLite2-ASN.mmdb");
+        assert!(!database_exists(&db_path));
+        std::fs::write(with_extension(&db_path, Compression::Brotli), b"").unwrap();
+        assert!(database_exists(&db_path));
+    }
+
+    #[test]
+    fn invalid_compressed_files_leave_the_target_untouched() {
+        let dir = tempfile::tempdir().unwrap();
+        let db_path = dir.path().join("GeoLite2-Country.mmdb");
+        std::fs::write(with_extension(&db_path, Compression::Gzip), b"not gzip").unwrap();
+        assert!(decompress_if_needed(&db_path).is_err());
+        assert!(!db_path.exists());
+    }
+}
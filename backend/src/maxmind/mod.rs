@@ -1,5 +1,6 @@
+mod compressed;
 mod updater;
 pub mod reader;
 
-pub use updater::MaxmindUpdater;
+pub use updater::{local_databases_exist, MaxmindUpdater, UPDATE_IN_PROGRESS};
 pub use reader::MaxmindReader; 
\ No newline at end of file
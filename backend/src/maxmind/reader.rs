@@ -1,39 +1,195 @@
-use crate::config::MaxmindConfig;
+use crate::config::{MaxmindConfig, PrivateIpBehavior};
+use super::compressed;
 use ipnet::IpNet;
 use log::{error, info};
+use arc_swap::ArcSwap;
 use maxminddb::{geoip2, Reader};
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
 use std::net::IpAddr;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
-use std::sync::Arc;
+use std::sync::{Arc, OnceLock};
 use serde::{Serialize, Deserialize};
 use crate::utils::whois_client::WhoisInfo;
 use crate::utils::bgptools_client::BgpToolsInfo;
 use crate::utils::bgp_api_client::BgpApiResult;
-use crate::utils::rpki_client::RpkiValidity;
+use crate::utils::ripestat_client::RelatedPrefix;
+use crate::utils::rpki_client::{RpkiCrossCheck, RpkiValidity};
+use crate::utils::eui64;
+use crate::utils::privacy::log_ip;
+
+/// 可在查询过程中原子替换的读取器
+pub type SharedReader = Arc<ArcSwap<MaxmindReader>>;
+
+/// 加载后自检使用的知名地址，数据库正常时必然能查到结果
+const SELF_TEST_IPS: [&str; 2] = ["8.8.8.8", "2606:4700:4700::1111"];
+
+/// 数据库内容：读入内存，或以内存映射方式共享操作系统页缓存
+/// 映射随读取器一起释放，原子替换后仍在进行的查询持有旧读取器，映射在最后一个查询结束后才解除
+pub enum DatabaseBuffer {
+    Memory(Vec<u8>),
+    Mapped(memmap2::Mmap),
+}
+
+impl AsRef<[u8]> for DatabaseBuffer {
+    fn as_ref(&self) -> &[u8] {
+        match self {
+            DatabaseBuffer::Memory(buf) => buf,
+            DatabaseBuffer::Mapped(mmap) => mmap,
+        }
+    }
+}
+
+impl DatabaseBuffer {
+    /// 优先使用内存映射，平台不支持或映射失败时读入内存
+    fn open(path: &Path, use_mmap: bool) -> Result<Self, String> {
+        if use_mmap {
+            let file = File::open(path).map_err(|e| format!("打开数据库文件失败: {}", e))?;
+            // SAFETY: 更新器以重命名的方式替换数据库文件，不会原地修改已映射的文件
+            match unsafe { memmap2::Mmap::map(&file) } {
+                Ok(mmap) => return Ok(DatabaseBuffer::Mapped(mmap)),
+                Err(e) => error!("内存映射 {} 失败，改为读入内存: {}", path.display(), e),
+            }
+        }
+        std::fs::read(path)
+            .map(DatabaseBuffer::Memory)
+            .map_err(|e| format!("读取数据库文件失败: {}", e))
+    }
+}
+
+/// 已加载数据库的元数据，来自mmdb文件头
+#[derive(Debug, Clone, Serialize)]
+pub struct DatabaseMetadata {
+    pub name: &'static str,
+    pub database_type: String,
+    pub build_epoch: u64,
+    /// 4 表示只含IPv4，6 表示同时包含IPv4和IPv6
+    pub ip_version: u16,
+    /// 搜索树节点数，可粗略反映数据库包含的网段数量
+    pub node_count: u32,
+    pub record_size: u16,
+    /// 数据库提供的名称语言，即 ?lang= 可用的取值
+    pub languages: Vec<String>,
+    pub description: BTreeMap<String, String>,
+}
 
 pub struct MaxmindReader {
     config: Arc<MaxmindConfig>,
-    asn_reader: Option<Reader<Vec<u8>>>,
-    city_reader: Option<Reader<Vec<u8>>>,
-    country_reader: Option<Reader<Vec<u8>>>,
+    asn_reader: Option<Reader<DatabaseBuffer>>,
+    city_reader: Option<Reader<DatabaseBuffer>>,
+    country_reader: Option<Reader<DatabaseBuffer>>,
+    /// 可选的GeoIP2 Enterprise数据库，提供各字段的置信度
+    enterprise_reader: Option<Reader<DatabaseBuffer>>,
+    self_test_passed: bool,
+    /// ASN到组织名称的索引，第一次按ASN查询名称时遍历ASN数据库建立，数据库重新加载后重建
+    asn_names: OnceLock<HashMap<u32, String>>,
+}
+
+/// 查询时需要访问的数据库
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LookupFields {
+    pub asn: bool,
+    pub city: bool,
+    pub country: bool,
+}
+
+impl LookupFields {
+    pub const ALL: LookupFields = LookupFields { asn: true, city: true, country: true };
+    pub const NONE: LookupFields = LookupFields { asn: false, city: false, country: false };
+
+    /// 从逗号分隔的字段列表解析（asn, organization, city, country）
+    pub fn parse(fields: &str) -> Result<Self, String> {
+        let mut selected = Self::NONE;
+        for field in fields.split(',').map(|f| f.trim()).filter(|f| !f.is_empty()) {
+            match field {
+                "asn" | "organization" => selected.asn = true,
+                "city" => selected.city = true,
+                "country" => selected.country = true,
+                _ => return Err(format!("未知的字段: {}", field)),
+            }
+        }
+        Ok(selected)
+    }
+}
+
+/// Enterprise数据库中各地理字段的置信度（0-100）
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GeoConfidence {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub country: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub subdivision: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub city: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub postal: Option<u8>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct IpInfo {
     pub ip: String,
     pub ip_range: Option<String>,
     pub country: Option<String>,
+    /// ISO 3166-1 国家代码
+    #[serde(default)]
+    pub country_code: Option<String>,
     pub city: Option<String>,
+    /// 各语言的国家名称，用于按请求语言输出
+    #[serde(default)]
+    pub country_names: HashMap<String, String>,
+    /// 各语言的城市名称
+    #[serde(default)]
+    pub city_names: HashMap<String, String>,
+    /// 最小一级行政区（省/州）名称
+    #[serde(default)]
+    pub subdivision: Option<String>,
+    #[serde(default)]
+    pub subdivision_names: HashMap<String, String>,
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
+    /// IANA时区名称（如 Asia/Shanghai）
+    #[serde(default)]
+    pub time_zone: Option<String>,
+    /// MaxMind数据库中的任播标记
+    #[serde(default)]
+    pub is_anycast: Option<bool>,
+    /// IPv6 EUI-64接口标识符中嵌入的MAC地址
+    #[serde(default)]
+    pub embedded_mac: Option<String>,
+    #[serde(default)]
+    pub mac_vendor: Option<String>,
+    /// 仅在加载了Enterprise数据库时提供
+    #[serde(default)]
+    pub confidence: Option<GeoConfidence>,
+    /// Enterprise数据库中的用户类型（如 residential、hosting）
+    #[serde(default)]
+    pub user_type: Option<String>,
     pub asn: Option<u32>,
     pub organization: Option<String>,
+    /// 组织名称被 maxmind.organization_overrides 替换时，MaxMind原本的组织名称
+    #[serde(default)]
+    pub organization_original: Option<String>,
     pub whois_info: Option<WhoisInfo>,
     pub bgp_info: Option<BgpToolsInfo>,
     pub bgp_api_info: Option<BgpApiResult>,
     pub rpki_info_list: Vec<RpkiValidity>,
+    /// 配置了额外RPKI验证器时的交叉验证结果
+    #[serde(default)]
+    pub rpki_cross_checks: Vec<RpkiCrossCheck>,
+    pub dnsbl: Vec<String>,
+    /// 覆盖该地址的较大前缀
+    #[serde(default)]
+    pub related_prefixes: Vec<RelatedPrefix>,
 }
 
-fn is_reserved_ip(ip: &str) -> bool {
+/// 将MaxMind名称表转换为可缓存的形式
+fn owned_names(names: &BTreeMap<&str, &str>) -> HashMap<String, String> {
+    names.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+}
+
+/// 是否为私有或保留地址（CIDR输入不在此判断）
+pub fn is_reserved_ip(ip: &str) -> bool {
     use std::net::IpAddr;
     if let Ok(addr) = ip.parse::<IpAddr>() {
         match addr {
@@ -65,6 +221,9 @@ impl MaxmindReader {
             asn_reader: None,
             city_reader: None,
             country_reader: None,
+            enterprise_reader: None,
+            self_test_passed: false,
+            asn_names: OnceLock::new(),
         }
     }
 
@@ -73,80 +232,253 @@ impl MaxmindReader {
         self.load_asn_database()?;
         self.load_city_database()?;
         self.load_country_database()?;
+        self.load_enterprise_database();
         info!("MaxMind数据库加载完成");
         Ok(())
     }
 
+    /// 在后台线程中加载一份新的数据库实例，完成后原子替换
+    /// 替换之前的查询继续使用旧数据库，整个过程不阻塞查询
+    pub async fn reload(shared: &SharedReader) -> Result<(), String> {
+        let config = shared.load().config.clone();
+        let fresh = tokio::task::spawn_blocking(move || {
+            let mut reader = MaxmindReader::new(config);
+            reader.load_databases()?;
+            match reader.self_test() {
+                Ok(()) => reader.self_test_passed = true,
+                Err(e) => error!("MaxMind数据库自检失败: {}", e),
+            }
+            Ok::<_, String>(reader)
+        })
+        .await
+        .map_err(|e| format!("数据库加载任务失败: {}", e))??;
+        shared.store(Arc::new(fresh));
+        Ok(())
+    }
+
+    /// 各已加载数据库的构建时间（Unix时间戳）
+    pub fn build_epochs(&self) -> BTreeMap<&'static str, u64> {
+        let readers = [
+            ("asn", &self.asn_reader),
+            ("city", &self.city_reader),
+            ("country", &self.country_reader),
+        ];
+        readers
+            .into_iter()
+            .filter_map(|(name, reader)| Some((name, reader.as_ref()?.metadata.build_epoch)))
+            .collect()
+    }
+
+    /// 各已加载数据库（包括可选的Enterprise数据库）的元数据
+    pub fn databases_metadata(&self) -> Vec<DatabaseMetadata> {
+        let readers = [
+            ("asn", &self.asn_reader),
+            ("city", &self.city_reader),
+            ("country", &self.country_reader),
+            ("enterprise", &self.enterprise_reader),
+        ];
+        readers
+            .into_iter()
+            .filter_map(|(name, reader)| {
+                let reader = reader.as_ref()?;
+                let metadata = &reader.metadata;
+                Some(DatabaseMetadata {
+                    name,
+                    database_type: metadata.database_type.clone(),
+                    build_epoch: metadata.build_epoch,
+                    ip_version: metadata.ip_version,
+                    node_count: metadata.node_count,
+                    record_size: metadata.record_size,
+                    languages: metadata.languages.clone(),
+                    description: metadata.description.clone(),
+                })
+            })
+            .collect()
+    }
+
+    /// 是否已加载全部数据库
+    pub fn is_loaded(&self) -> bool {
+        self.asn_reader.is_some() && self.city_reader.is_some() && self.country_reader.is_some()
+    }
+
+    /// 最近一次加载后的自检是否通过
+    pub fn self_test_passed(&self) -> bool {
+        self.self_test_passed
+    }
+
+    /// 查询知名地址确认数据库可用，能捕获文件存在但内容为空或损坏的情况
+    pub fn self_test(&self) -> Result<(), String> {
+        for ip in SELF_TEST_IPS {
+            let info = self.lookup(ip)?;
+            info!(
+                "数据库自检 {}: ASN={:?} 组织={:?} 国家={:?}",
+                ip, info.asn, info.organization, info.country
+            );
+            if info.asn.is_none() || info.country.is_none() {
+                return Err(format!("自检地址 {} 未查询到ASN或国家信息", ip));
+            }
+        }
+        Ok(())
+    }
+
     pub fn lookup(&self, ip_str: &str) -> Result<IpInfo, String> {
+        self.lookup_with(ip_str, LookupFields::ALL)
+    }
+
+    /// 查询ASN对应的组织名称，数据库中没有的ASN不出现在结果中
+    /// 第一次调用时需要遍历整个ASN数据库，应在阻塞线程中执行
+    pub fn asn_names(&self, asns: &[u32]) -> Result<HashMap<u32, String>, String> {
+        let index = match self.asn_names.get() {
+            Some(index) => index,
+            None => {
+                let index = self.build_asn_name_index()?;
+                self.asn_names.get_or_init(|| index)
+            }
+        };
+        Ok(asns
+            .iter()
+            .filter_map(|asn| Some((*asn, index.get(asn)?.clone())))
+            .collect())
+    }
+
+    fn build_asn_name_index(&self) -> Result<HashMap<u32, String>, String> {
+        let reader = self.asn_reader.as_ref().ok_or("ASN数据库未加载")?;
+        let start = std::time::Instant::now();
+        let all = if reader.metadata.ip_version == 4 { "0.0.0.0/0" } else { "::/0" };
+        let network: ipnetwork::IpNetwork = all.parse().map_err(|e| format!("{}", e))?;
+        let mut index = HashMap::new();
+        for item in reader.within::<geoip2::Asn>(network).map_err(|e| format!("遍历ASN数据库失败: {}", e))? {
+            let item = item.map_err(|e| format!("遍历ASN数据库失败: {}", e))?;
+            if let (Some(number), Some(organization)) =
+                (item.info.autonomous_system_number, item.info.autonomous_system_organization) {
+                index.entry(number).or_insert_with(|| organization.to_string());
+            }
+        }
+        for (asn, name) in &self.config.organization_overrides {
+            index.insert(*asn, name.clone());
+        }
+        info!("ASN名称索引已建立，共 {} 个ASN，耗时 {:?}", index.len(), start.elapsed());
+        Ok(index)
+    }
+
+    /// 国家数据库是否已加载
+    pub fn has_country_database(&self) -> bool {
+        self.country_reader.is_some()
+    }
+
+    /// 按数据库顺序遍历国家代码为 country_code 的网段，跳过前 skip 个，emit 返回 false 时停止
+    /// 需要遍历整个国家数据库，应在阻塞线程中调用
+    pub fn country_networks(&self, country_code: &str, skip: usize, mut emit: impl FnMut(String) -> bool) -> Result<(), String> {
+        let reader = self.country_reader.as_ref().ok_or("国家数据库未加载")?;
+        let all = if reader.metadata.ip_version == 4 { "0.0.0.0/0" } else { "::/0" };
+        let network: ipnetwork::IpNetwork = all.parse().map_err(|e| format!("{}", e))?;
+        let mut skipped = 0;
+        for item in reader.within::<geoip2::Country>(network).map_err(|e| format!("遍历国家数据库失败: {}", e))? {
+            let item = item.map_err(|e| format!("遍历国家数据库失败: {}", e))?;
+            let matches = item.info.country
+                .and_then(|country| country.iso_code)
+                .is_some_and(|code| code.eq_ignore_ascii_case(country_code));
+            if !matches {
+                continue;
+            }
+            if skipped < skip {
+                skipped += 1;
+                continue;
+            }
+            if !emit(item.ip_net.to_string()) {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// 只查询 fields 中选中的数据库
+    pub fn lookup_with(&self, ip_str: &str, fields: LookupFields) -> Result<IpInfo, String> {
         if is_reserved_ip(ip_str) {
-            return Ok(IpInfo {
-                ip: ip_str.to_string(),
-                ip_range: None,
-                country: Some("保留地址".to_string()),
-                city: None,
-                asn: None,
-                organization: Some("保留地址".to_string()),
-                whois_info: None,
-                bgp_info: None,
-                bgp_api_info: None,
-                rpki_info_list: Vec::new(),
-            });
+            match self.config.private_ip_behavior {
+                PrivateIpBehavior::Reserved => {
+                    return Ok(IpInfo {
+                        ip: ip_str.to_string(),
+                        country: Some("保留地址".to_string()),
+                        organization: Some("保留地址".to_string()),
+                        ..Default::default()
+                    });
+                }
+                PrivateIpBehavior::Reject => return Err(format!("不支持查询私有或保留地址: {}", ip_str)),
+                PrivateIpBehavior::Lookup => {}
+            }
         }
         let ip_info = if ip_str.contains('/') {
-            self.lookup_cidr(ip_str)?
+            self.lookup_cidr(ip_str, fields)?
         } else {
-            self.lookup_ip(ip_str)?
+            self.lookup_ip(ip_str, fields)?
         };
         Ok(ip_info)
     }
 
-    fn lookup_ip(&self, ip_str: &str) -> Result<IpInfo, String> {
+    fn lookup_ip(&self, ip_str: &str, fields: LookupFields) -> Result<IpInfo, String> {
         let ip = IpAddr::from_str(ip_str)
             .map_err(|e| format!("无效的IP地址: {}", e))?;
         let mut info = IpInfo {
             ip: ip_str.to_string(),
-            ip_range: None,
-            country: None,
-            city: None,
-            asn: None,
-            organization: None,
-            whois_info: None,
-            bgp_info: None,
-            bgp_api_info: None,
-            rpki_info_list: Vec::new(),
+            ..Default::default()
         };
-        if let Some(reader) = &self.asn_reader {
+        (info.embedded_mac, info.mac_vendor) = eui64::mac_fields(ip_str);
+        if fields.asn
+            && let Some(reader) = &self.asn_reader {
             match reader.lookup::<geoip2::Asn>(ip) {
                 Ok(Some(asn)) => {
                     info.asn = asn.autonomous_system_number;
                     info.organization = asn.autonomous_system_organization.map(|s| s.to_string());
+                    if let Some(number) = info.asn
+                        && let Some(name) = self.config.organization_overrides.get(&number) {
+                        info.organization_original = info.organization.replace(name.clone());
+                    }
                 },
                 Ok(None) => {
-                    info!("ASN数据库未找到该IP的ASN信息: {}", ip);
+                    info!("ASN数据库未找到该IP的ASN信息: {}", log_ip(ip_str));
                 },
                 Err(e) => {
                     error!("ASN查询错误: {}", e);
                 }
             }
         }
-        if let Some(reader) = &self.city_reader {
+        if fields.city
+            && let Some(reader) = &self.city_reader {
             match reader.lookup::<geoip2::City>(ip) {
                 Ok(Some(city_record)) => {
-                    if let Some(city) = city_record.city {
-                        if let Some(names) = city.names {
-                            info.city = names.get("zh-CN")
-                                .or_else(|| names.get("en"))
-                                .map(|s| s.to_string());
-                        }
+                    if let Some(traits) = &city_record.traits {
+                        info.is_anycast = traits.is_anycast;
                     }
-                    if info.country.is_none() {
-                        if let Some(country) = city_record.country {
-                            if let Some(names) = country.names {
-                                info.country = names.get("zh-CN")
-                                    .or_else(|| names.get("en"))
-                                    .map(|s| s.to_string());
-                            }
-                        }
+                    if let Some(location) = &city_record.location {
+                        info.latitude = location.latitude;
+                        info.longitude = location.longitude;
+                        info.time_zone = location.time_zone.map(|tz| tz.to_string());
+                    }
+                    if let Some(city) = city_record.city
+                        && let Some(names) = city.names {
+                        info.city = names.get("zh-CN")
+                            .or_else(|| names.get("en"))
+                            .map(|s| s.to_string());
+                        info.city_names = owned_names(&names);
+                    }
+                    if let Some(subdivisions) = &city_record.subdivisions
+                        && let Some(subdivision) = subdivisions.last()
+                        && let Some(names) = &subdivision.names {
+                        info.subdivision = names.get("zh-CN")
+                            .or_else(|| names.get("en"))
+                            .map(|s| s.to_string());
+                        info.subdivision_names = owned_names(names);
+                    }
+                    if fields.country
+                        && info.country.is_none()
+                        && let Some(country) = city_record.country
+                        && let Some(names) = country.names {
+                        info.country_code = country.iso_code.map(|s| s.to_string());
+                        info.country = names.get("zh-CN")
+                            .or_else(|| names.get("en"))
+                            .map(|s| s.to_string());
+                        info.country_names = owned_names(&names);
                     }
                 },
                 Ok(None) => {},
@@ -155,43 +487,89 @@ impl MaxmindReader {
                 }
             }
         }
-        if info.country.is_none() {
-            if let Some(reader) = &self.country_reader {
-                match reader.lookup::<geoip2::Country>(ip) {
-                    Ok(Some(country_record)) => {
-                        if let Some(country) = country_record.country {
-                            if let Some(names) = country.names {
-                                info.country = names.get("zh-CN")
-                                    .or_else(|| names.get("en"))
-                                    .map(|s| s.to_string());
-                            }
-                        }
-                    },
-                    Ok(None) => {},
-                    Err(e) => {
-                        error!("国家查询错误: {}", e);
+        if fields.country
+            && info.country.is_none()
+            && let Some(reader) = &self.country_reader {
+            match reader.lookup::<geoip2::Country>(ip) {
+                Ok(Some(country_record)) => {
+                    if info.is_anycast.is_none()
+                        && let Some(traits) = &country_record.traits {
+                        info.is_anycast = traits.is_anycast;
                     }
+                    if let Some(country) = country_record.country
+                        && let Some(names) = country.names {
+                        info.country_code = country.iso_code.map(|s| s.to_string());
+                        info.country = names.get("zh-CN")
+                            .or_else(|| names.get("en"))
+                            .map(|s| s.to_string());
+                        info.country_names = owned_names(&names);
+                    }
+                },
+                Ok(None) => {},
+                Err(e) => {
+                    error!("国家查询错误: {}", e);
+                }
+            }
+        }
+        if (fields.city || fields.country)
+            && let Some(reader) = &self.enterprise_reader {
+            match reader.lookup::<geoip2::Enterprise>(ip) {
+                Ok(Some(record)) => {
+                    info.confidence = Some(GeoConfidence {
+                        country: record.country.as_ref().and_then(|c| c.confidence),
+                        subdivision: record.subdivisions.as_ref()
+                            .and_then(|s| s.last())
+                            .and_then(|s| s.confidence),
+                        city: record.city.as_ref().and_then(|c| c.confidence),
+                        postal: record.postal.as_ref().and_then(|p| p.confidence),
+                    });
+                    info.user_type = record.traits.as_ref()
+                        .and_then(|t| t.user_type)
+                        .map(|s| s.to_string());
+                },
+                Ok(None) => {},
+                Err(e) => {
+                    error!("Enterprise查询错误: {}", e);
                 }
             }
         }
         Ok(info)
     }
     
-    fn lookup_cidr(&self, cidr_str: &str) -> Result<IpInfo, String> {
+    fn lookup_cidr(&self, cidr_str: &str, fields: LookupFields) -> Result<IpInfo, String> {
         let network = IpNet::from_str(cidr_str)
             .map_err(|e| format!("无效的CIDR: {}", e))?;
         let ip = network.addr();
         let ip_str = ip.to_string();
-        let mut info = self.lookup_ip(&ip_str)?;
+        let mut info = self.lookup_ip(&ip_str, fields)?;
         info.ip = cidr_str.to_string();
-        info.ip_range = Some(format!("{} - {}", network.network(), network.broadcast()));
+        // IPv6没有广播地址，范围的结束地址取网段内最后一个地址
+        let last = match network {
+            IpNet::V4(v4) => IpAddr::V4(v4.broadcast()),
+            IpNet::V6(v6) => IpAddr::V6(std::net::Ipv6Addr::from(u128::from(v6.network()) | !u128::from(v6.netmask()))),
+        };
+        info.ip_range = Some(format!("{} - {}", network.network(), last));
         Ok(info)
     }
 
+    /// 数据库文件路径，目录中有更新的 .br / .gz 压缩版本时先解压
+    fn database_path(&self, file_name: &str) -> PathBuf {
+        let db_path = Path::new(&self.config.database_dir).join(file_name);
+        if let Err(e) = compressed::decompress_if_needed(&db_path) {
+            error!("{}", e);
+        }
+        db_path
+    }
+
+    fn open_database(&self, db_path: &Path) -> Result<Reader<DatabaseBuffer>, String> {
+        let buffer = DatabaseBuffer::open(db_path, self.config.use_mmap)?;
+        Reader::from_source(buffer).map_err(|e| e.to_string())
+    }
+
     fn load_asn_database(&mut self) -> Result<(), String> {
-        let db_path = Path::new(&self.config.database_dir).join("GeoLite2-ASN.mmdb");
+        let db_path = self.database_path("GeoLite2-ASN.mmdb");
         if db_path.exists() {
-            match Reader::open_readfile(&db_path) {
+            match self.open_database(&db_path) {
                 Ok(reader) => {
                     self.asn_reader = Some(reader);
                     info!("ASN数据库加载成功");
@@ -205,9 +583,9 @@ impl MaxmindReader {
     }
     
     fn load_city_database(&mut self) -> Result<(), String> {
-        let db_path = Path::new(&self.config.database_dir).join("GeoLite2-City.mmdb");
+        let db_path = self.database_path("GeoLite2-City.mmdb");
         if db_path.exists() {
-            match Reader::open_readfile(&db_path) {
+            match self.open_database(&db_path) {
                 Ok(reader) => {
                     self.city_reader = Some(reader);
                     info!("城市数据库加载成功");
@@ -221,9 +599,9 @@ impl MaxmindReader {
     }
     
     fn load_country_database(&mut self) -> Result<(), String> {
-        let db_path = Path::new(&self.config.database_dir).join("GeoLite2-Country.mmdb");
+        let db_path = self.database_path("GeoLite2-Country.mmdb");
         if db_path.exists() {
-            match Reader::open_readfile(&db_path) {
+            match self.open_database(&db_path) {
                 Ok(reader) => {
                     self.country_reader = Some(reader);
                     info!("国家数据库加载成功");
@@ -235,4 +613,43 @@ impl MaxmindReader {
             Err(format!("国家数据库文件不存在: {}", db_path.display()))
         }
     }
-} 
\ No newline at end of file
+
+    /// Enterprise数据库为付费数据，文件不存在时跳过
+    fn load_enterprise_database(&mut self) {
+        let db_path = self.database_path("GeoIP2-Enterprise.mmdb");
+        if !db_path.exists() {
+            return;
+        }
+        match self.open_database(&db_path) {
+            Ok(reader) => {
+                self.enterprise_reader = Some(reader);
+                info!("Enterprise数据库加载成功");
+            },
+            Err(e) => error!("加载Enterprise数据库失败，忽略: {}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn database_buffer_maps_or_reads_the_same_bytes() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("test.mmdb");
+        std::fs::write(&path, b"mmdb contents").unwrap();
+
+        let mapped = DatabaseBuffer::open(&path, true).unwrap();
+        assert!(matches!(mapped, DatabaseBuffer::Mapped(_)));
+        let memory = DatabaseBuffer::open(&path, false).unwrap();
+        assert!(matches!(memory, DatabaseBuffer::Memory(_)));
+        assert_eq!(mapped.as_ref(), memory.as_ref());
+
+        // 重命名替换文件后，已有映射仍指向旧内容
+        let staging = dir.path().join("test.mmdb.tmp");
+        std::fs::write(&staging, b"new contents!").unwrap();
+        std::fs::rename(&staging, &path).unwrap();
+        assert_eq!(mapped.as_ref(), b"mmdb contents");
+    }
+}
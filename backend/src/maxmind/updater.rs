@@ -1,22 +1,63 @@
 use crate::config::MaxmindConfig;
+use crate::maxmind::compressed;
+use crate::maxmind::reader::{MaxmindReader, SharedReader};
 use chrono::{DateTime, Utc};
 use log::{info, warn, error, debug};
+use crate::utils::upstream::http_client_builder;
 use reqwest::Client;
 use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use std::time::Duration;
+use futures::StreamExt;
+use futures::future::join_all;
 use tokio::io::AsyncWriteExt;
 
+/// 已有更新在进行时返回的错误
+pub const UPDATE_IN_PROGRESS: &str = "MaxMind数据库正在更新中";
+
+/// 防止定时任务和手动触发的更新重叠
+static UPDATE_LOCK: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+/// MaxMind按版本ID下载的标准接口
+const MAXMIND_DOWNLOAD_ENDPOINT: &str = "https://download.maxmind.com/app/geoip_download";
+
+/// 自动更新的数据库类型
+const DB_TYPES: [&str; 3] = ["asn", "city", "country"];
+
+/// 替换前的数据库保存为 <文件名>.bak，新数据库未通过自检时用于回滚
+fn backup_path(target_path: &Path) -> PathBuf {
+    let mut path = target_path.as_os_str().to_owned();
+    path.push(".bak");
+    PathBuf::from(path)
+}
+
+/// 与读取器加载的文件名一致（MaxMind的ASN数据库为 GeoLite2-ASN）
+fn db_file_name(db_type: &str) -> String {
+    match db_type {
+        "asn" => "GeoLite2-ASN.mmdb".to_string(),
+        _ => format!("GeoLite2-{}.mmdb", db_type.chars().next().unwrap().to_uppercase().collect::<String>() + &db_type[1..]),
+    }
+}
+
+/// 数据库目录中已有全部自动更新的数据库，压缩版本（.br / .gz）也计入，加载时再解压
+pub fn local_databases_exist(database_dir: &str) -> bool {
+    DB_TYPES
+        .iter()
+        .all(|db_type| compressed::database_exists(&Path::new(database_dir).join(db_file_name(db_type))))
+}
+
 pub struct MaxmindUpdater {
     config: Arc<MaxmindConfig>,
     client: Client,
     last_update: Option<DateTime<Utc>>,
+    /// 本次更新中已备份并替换的数据库文件，回滚只恢复这些文件
+    replaced: std::sync::Mutex<Vec<PathBuf>>,
 }
 
 impl MaxmindUpdater {
     pub fn new(config: Arc<MaxmindConfig>) -> Self {
-        let client = Client::builder()
+        let client = http_client_builder()
             .timeout(Duration::from_secs(300))
             .build()
             .expect("构建HTTP客户端失败");
@@ -25,20 +66,89 @@ impl MaxmindUpdater {
             config,
             client,
             last_update: None,
+            replaced: std::sync::Mutex::new(Vec::new()),
         }
     }
 
     pub async fn update(&mut self) -> Result<(), String> {
         info!("开始更新MaxMind数据库...");
         self.ensure_database_dir()?;
-        self.download_and_extract_database("asn").await?;
-        self.download_and_extract_database("city").await?;
-        self.download_and_extract_database("country").await?;
+        self.replaced.lock().unwrap_or_else(|e| e.into_inner()).clear();
+        // 并发下载，同时进行的下载数不超过 download_concurrency；解压仍按数据库分别进行
+        let permits = tokio::sync::Semaphore::new(self.config.download_concurrency.max(1));
+        let results = join_all(DB_TYPES.iter().map(|db_type| async {
+            let _permit = permits.acquire().await.map_err(|e| e.to_string())?;
+            self.download_and_extract_database(db_type).await
+        })).await;
+        let failed: Vec<String> = DB_TYPES
+            .iter()
+            .zip(results)
+            .filter_map(|(db_type, result)| result.err().map(|e| format!("{}: {}", db_type, e)))
+            .collect();
+        if !failed.is_empty() {
+            // 部分数据库已被替换时恢复原版本，避免磁盘上新旧数据库混用
+            let restored = self.rollback().await?;
+            if restored > 0 {
+                warn!("部分数据库更新失败，已回滚 {} 个已替换的数据库", restored);
+            }
+            return Err(format!("{} 个数据库更新失败: {}", failed.len(), failed.join("; ")));
+        }
         self.last_update = Some(Utc::now());
         info!("MaxMind数据库更新完成");
         Ok(())
     }
 
+    /// 下载最新数据库并原子替换读取器；已有更新在进行时立即返回 UPDATE_IN_PROGRESS
+    /// 新数据库加载失败或未通过自检时回滚到替换前的版本并返回错误
+    pub async fn update_and_reload(&mut self, reader: &SharedReader) -> Result<(), String> {
+        let _guard = UPDATE_LOCK.try_lock().map_err(|_| UPDATE_IN_PROGRESS.to_string())?;
+        self.update().await?;
+        let reason = match MaxmindReader::reload(reader).await {
+            Ok(()) if reader.load().self_test_passed() => return Ok(()),
+            Ok(()) => "自检失败".to_string(),
+            Err(e) => format!("加载失败: {}", e),
+        };
+        error!("==================== 新的MaxMind数据库{}，回滚到上一版本 ====================", reason);
+        let restored = self.rollback().await?;
+        if restored == 0 {
+            return Err(format!("新的MaxMind数据库{}，且没有可回滚的备份", reason));
+        }
+        MaxmindReader::reload(reader).await
+            .map_err(|e| format!("回滚后重新加载MaxMind数据库失败: {}", e))?;
+        error!("已回滚 {} 个MaxMind数据库到上一版本", restored);
+        Err(format!("新的MaxMind数据库{}，已回滚到上一版本", reason))
+    }
+
+    /// 用 .bak 备份恢复本次更新中替换的数据库文件，返回恢复的文件数；之前更新遗留的备份不受影响
+    async fn rollback(&self) -> Result<usize, String> {
+        let replaced = std::mem::take(&mut *self.replaced.lock().unwrap_or_else(|e| e.into_inner()));
+        let mut restored = 0;
+        for target_path in replaced {
+            tokio::fs::rename(backup_path(&target_path), &target_path)
+                .await
+                .map_err(|e| format!("回滚 {} 失败: {}", target_path.display(), e))?;
+            restored += 1;
+        }
+        Ok(restored)
+    }
+
+    /// 替换前保留当前数据库：优先使用硬链接，重命名替换后旧文件内容原样留在备份中
+    async fn backup(&self, target_path: &Path) -> Result<(), String> {
+        let backup_path = backup_path(target_path);
+        match tokio::fs::remove_file(&backup_path).await {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(format!("删除旧备份失败: {}", e)),
+        }
+        if tokio::fs::hard_link(target_path, &backup_path).await.is_err() {
+            tokio::fs::copy(target_path, &backup_path)
+                .await
+                .map_err(|e| format!("备份数据库文件失败: {}", e))?;
+        }
+        self.replaced.lock().unwrap_or_else(|e| e.into_inner()).push(target_path.to_path_buf());
+        Ok(())
+    }
+
     fn ensure_database_dir(&self) -> Result<(), String> {
         let path = Path::new(&self.config.database_dir);
         if !path.exists() {
@@ -49,7 +159,7 @@ impl MaxmindUpdater {
 
     async fn download_and_extract_database(&self, db_type: &str) -> Result<(), String> {
         let url = self.get_download_url(db_type)?;
-        info!("准备下载 {} 数据库: {}", db_type, url);
+        info!("准备下载 {} 数据库: {}", db_type, Self::redact_url(&url));
         let account_id = self.config.account_id.to_string();
         let license_key = self.config.license_key.clone();
         let mut last_err = None;
@@ -69,10 +179,14 @@ impl MaxmindUpdater {
                         tokio::time::sleep(Duration::from_secs(2)).await;
                         continue;
                     }
-                    let content = resp.bytes().await.map_err(|e| format!("读取 {} 数据库响应失败: {}", db_type, e))?;
-                    info!("{} 数据库下载完成，大小: {} 字节，开始解压...", db_type, content.len());
+                    let temp_dir = tempfile::Builder::new().prefix("maxmind").tempdir()
+                        .map_err(|e| format!("创建临时目录失败: {}", e))?;
+                    let tar_path = temp_dir.path().join(format!("{}.tar.gz", db_type));
+                    let size = Self::stream_to_file(resp, &tar_path).await
+                        .map_err(|e| format!("读取 {} 数据库响应失败: {}", db_type, e))?;
+                    info!("{} 数据库下载完成，大小: {} 字节，开始解压...", db_type, size);
                     let db_type_owned = db_type.to_string();
-                    match self.extract_tar_gz(content.to_vec(), db_type_owned.clone()).await {
+                    match self.extract_tar_gz(temp_dir, tar_path, db_type_owned.clone()).await {
                         Ok(_) => {
                             info!("成功更新 {} 数据库", db_type_owned);
                             return Ok(());
@@ -96,32 +210,69 @@ impl MaxmindUpdater {
         Err(last_err.unwrap_or_else(|| format!("下载 {} 数据库失败: 未知错误", db_type)))
     }
 
+    /// 优先使用显式配置的下载地址，否则按版本ID拼接MaxMind标准下载地址
     fn get_download_url(&self, db_type: &str) -> Result<String, String> {
-        let url = match db_type {
-            "asn" => &self.config.download_urls.asn,
-            "city" => &self.config.download_urls.city,
-            "country" => &self.config.download_urls.country,
+        let (url, edition_id) = match db_type {
+            "asn" => (&self.config.download_urls.asn, &self.config.edition_ids.asn),
+            "city" => (&self.config.download_urls.city, &self.config.edition_ids.city),
+            "country" => (&self.config.download_urls.country, &self.config.edition_ids.country),
             _ => return Err(format!("无效的数据库类型: {}", db_type)),
         };
-        Ok(url.clone())
+        if !url.trim().is_empty() {
+            return Ok(url.clone());
+        }
+        if edition_id.trim().is_empty() {
+            return Err(format!("{} 数据库未配置下载地址或版本ID", db_type));
+        }
+        let mut url = reqwest::Url::parse(MAXMIND_DOWNLOAD_ENDPOINT)
+            .map_err(|e| format!("无效的MaxMind下载地址: {}", e))?;
+        url.query_pairs_mut()
+            .append_pair("edition_id", edition_id.trim())
+            .append_pair("license_key", &self.config.license_key)
+            .append_pair("suffix", "tar.gz");
+        Ok(url.to_string())
     }
 
-    async fn extract_tar_gz(&self, data: Vec<u8>, db_type: String) -> Result<(), String> {
-        use std::fs::File;
-        info!("解压 {} 数据库，写入临时文件...", db_type);
-        let temp_dir = tempfile::Builder::new().prefix("maxmind").tempdir()
-            .map_err(|e| format!("创建临时目录失败: {}", e))?;
-        let tar_path = temp_dir.path().join(format!("{}.tar.gz", &db_type));
-        let mut file = tokio::fs::File::create(&tar_path)
+    /// 隐藏下载地址中的许可证密钥，用于日志输出
+    fn redact_url(url: &str) -> String {
+        match reqwest::Url::parse(url) {
+            Ok(mut parsed) if parsed.query_pairs().any(|(k, _)| k == "license_key") => {
+                let pairs: Vec<(String, String)> = parsed
+                    .query_pairs()
+                    .map(|(k, v)| {
+                        let v = if k == "license_key" { "***".to_string() } else { v.into_owned() };
+                        (k.into_owned(), v)
+                    })
+                    .collect();
+                parsed.query_pairs_mut().clear().extend_pairs(pairs);
+                parsed.to_string()
+            }
+            _ => url.to_string(),
+        }
+    }
+
+    /// 将响应体逐块写入文件，避免整个压缩包驻留内存，返回写入的字节数
+    async fn stream_to_file(resp: reqwest::Response, path: &Path) -> Result<u64, String> {
+        let mut file = tokio::fs::File::create(path)
             .await
             .map_err(|e| format!("创建临时文件失败: {}", e))?;
-        file.write_all(&data)
-            .await
-            .map_err(|e| format!("写入临时文件失败: {}", e))?;
+        let mut stream = resp.bytes_stream();
+        let mut written = 0u64;
+        while let Some(chunk) = stream.next().await {
+            let chunk = chunk.map_err(|e| format!("接收数据失败: {}", e))?;
+            file.write_all(&chunk)
+                .await
+                .map_err(|e| format!("写入临时文件失败: {}", e))?;
+            written += chunk.len() as u64;
+        }
         file.flush()
             .await
             .map_err(|e| format!("刷新临时文件失败: {}", e))?;
-        drop(file);
+        Ok(written)
+    }
+
+    async fn extract_tar_gz(&self, temp_dir: tempfile::TempDir, tar_path: PathBuf, db_type: String) -> Result<(), String> {
+        use std::fs::File;
         info!("{} 数据库临时文件写入完成: {}，开始解压...", db_type, tar_path.display());
         let temp_dir_path = temp_dir.path().to_path_buf();
         let tar_path_clone = tar_path.clone();
@@ -139,8 +290,7 @@ impl MaxmindUpdater {
             if let Err(e) = archive.unpack(&temp_dir_path) {
                 return Err(format!("解压数据库失败: {}", e));
             }
-            let db_file_name = format!("GeoLite2-{}.mmdb", 
-                db_type_clone.chars().next().unwrap().to_uppercase().collect::<String>() + &db_type_clone[1..]);
+            let db_file_name = db_file_name(&db_type_clone);
             info!("[阻塞线程] 查找解压后的mmdb文件(忽略大小写): {}", db_file_name);
             let mut db_file_path = None;
             for entry in walkdir::WalkDir::new(&temp_dir_path).into_iter().filter_map(|e| e.ok()) {
@@ -160,10 +310,92 @@ impl MaxmindUpdater {
         let (db_file_path, db_file_name) = result;
         info!("复制mmdb文件到目标目录: {}", db_file_name);
         let target_path = Path::new(&db_dir).join(&db_file_name);
-        tokio::fs::copy(db_file_path, &target_path)
+        // 先复制到同目录的临时文件再重命名，原地覆盖会破坏正在使用的内存映射
+        let staging_path = Path::new(&db_dir).join(format!("{}.tmp", db_file_name));
+        tokio::fs::copy(db_file_path, &staging_path)
             .await
             .map_err(|e| format!("复制数据库文件失败: {}", e))?;
+        if target_path.exists() {
+            self.backup(&target_path).await?;
+        }
+        tokio::fs::rename(&staging_path, &target_path)
+            .await
+            .map_err(|e| format!("替换数据库文件失败: {}", e))?;
         info!("成功提取并保存 {} 数据库到 {}", db_type, target_path.display());
         Ok(())
     }
-} 
\ No newline at end of file
+} 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{MaxmindEditions, MaxmindUrls};
+
+    fn test_config(database_dir: &str) -> MaxmindConfig {
+        MaxmindConfig {
+            account_id: 1,
+            license_key: "secret".to_string(),
+            update_interval_hours: 24,
+            download_urls: MaxmindUrls {
+                asn: "https://example.com/asn.tar.gz".to_string(),
+                ..Default::default()
+            },
+            edition_ids: MaxmindEditions::default(),
+            database_dir: database_dir.to_string(),
+            start_without_databases: false,
+            organization_overrides: Default::default(),
+            private_ip_behavior: Default::default(),
+            use_mmap: false,
+            download_concurrency: 3,
+        }
+    }
+
+    #[test]
+    fn download_url_is_built_from_edition_id() {
+        let updater = MaxmindUpdater::new(Arc::new(test_config("data")));
+        assert_eq!(updater.get_download_url("asn").unwrap(), "https://example.com/asn.tar.gz");
+        let city = updater.get_download_url("city").unwrap();
+        assert_eq!(
+            city,
+            "https://download.maxmind.com/app/geoip_download?edition_id=GeoLite2-City&license_key=secret&suffix=tar.gz"
+        );
+        assert!(!MaxmindUpdater::redact_url(&city).contains("secret"));
+    }
+
+    #[test]
+    fn compressed_local_databases_skip_the_initial_download() {
+        let dir = tempfile::tempdir().unwrap();
+        let database_dir = dir.path().to_str().unwrap();
+        std::fs::write(dir.path().join("GeoLite2-ASN.mmdb.br"), b"").unwrap();
+        std::fs::write(dir.path().join("GeoLite2-City.mmdb.gz"), b"").unwrap();
+        assert!(!local_databases_exist(database_dir));
+        std::fs::write(dir.path().join("GeoLite2-Country.mmdb"), b"").unwrap();
+        assert!(local_databases_exist(database_dir));
+    }
+
+    #[tokio::test]
+    async fn rollback_restores_the_backed_up_database() {
+        let dir = tempfile::tempdir().unwrap();
+        let updater = MaxmindUpdater::new(Arc::new(test_config(dir.path().to_str().unwrap())));
+        let target_path = dir.path().join(db_file_name("city"));
+        std::fs::write(&target_path, b"previous").unwrap();
+
+        // 之前更新遗留的备份，本次没有替换该数据库，回滚时不应恢复
+        let asn_path = dir.path().join(db_file_name("asn"));
+        std::fs::write(&asn_path, b"current").unwrap();
+        std::fs::write(backup_path(&asn_path), b"stale").unwrap();
+
+        updater.backup(&target_path).await.unwrap();
+        let staging_path = dir.path().join("staging.mmdb");
+        std::fs::write(&staging_path, b"broken").unwrap();
+        std::fs::rename(&staging_path, &target_path).unwrap();
+        assert_eq!(std::fs::read(backup_path(&target_path)).unwrap(), b"previous");
+
+        assert_eq!(updater.rollback().await.unwrap(), 1);
+        assert_eq!(std::fs::read(&target_path).unwrap(), b"previous");
+        assert!(!backup_path(&target_path).exists());
+        assert_eq!(std::fs::read(&asn_path).unwrap(), b"current");
+
+        // 已回滚的文件不会被再次恢复
+        assert_eq!(updater.rollback().await.unwrap(), 0);
+    }
+}
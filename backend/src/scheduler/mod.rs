@@ -3,8 +3,11 @@ use log::{error, info};
 use std::sync::{Arc, Mutex};
 use tokio::time;
 
+type TaskFn = Arc<dyn Fn() -> Result<(), String> + Send + Sync + 'static>;
+type ScheduledTask = (String, TaskFn, Arc<Mutex<DateTime<Utc>>>, Duration);
+
 pub struct Scheduler {
-    tasks: Vec<(String, Arc<dyn Fn() -> Result<(), String> + Send + Sync + 'static>, Arc<Mutex<DateTime<Utc>>>, Duration)>,
+    tasks: Vec<ScheduledTask>,
 }
 
 impl Scheduler {
@@ -0,0 +1,120 @@
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+use crate::config::ApiKeyConfig;
+
+const MINUTE_SECS: u64 = 60;
+const DAY_SECS: u64 = 24 * 60 * 60;
+
+/// 超出配额时拒绝的原因及距离配额重置的秒数
+#[derive(Debug, PartialEq, Eq)]
+pub struct QuotaExceeded {
+    pub window: &'static str,
+    pub retry_after_secs: u64,
+}
+
+/// 单个API Key的用量，分钟和天均为固定窗口（天按UTC计算）
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct KeyUsage {
+    pub minute: u32,
+    pub day: u32,
+    pub total: u64,
+    #[serde(skip)]
+    minute_window: u64,
+    #[serde(skip)]
+    day_window: u64,
+}
+
+/// 各API Key的用量统计，按Key名称记录，保存在内存中，重启后清零
+#[derive(Default)]
+pub struct ApiKeyUsage {
+    usage: Mutex<BTreeMap<String, KeyUsage>>,
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+impl ApiKeyUsage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 检查配额并计入本次请求；超出配额的请求不计入
+    pub fn try_acquire(&self, key: &ApiKeyConfig) -> Result<(), QuotaExceeded> {
+        self.try_acquire_at(key, now_secs())
+    }
+
+    fn try_acquire_at(&self, key: &ApiKeyConfig, now: u64) -> Result<(), QuotaExceeded> {
+        let mut usage = self.usage.lock().unwrap_or_else(|e| e.into_inner());
+        let entry = usage.entry(key.name.clone()).or_default();
+        let (minute_window, day_window) = (now / MINUTE_SECS, now / DAY_SECS);
+        if entry.minute_window != minute_window {
+            entry.minute_window = minute_window;
+            entry.minute = 0;
+        }
+        if entry.day_window != day_window {
+            entry.day_window = day_window;
+            entry.day = 0;
+        }
+        if key.per_minute.is_some_and(|limit| entry.minute >= limit) {
+            return Err(QuotaExceeded { window: "minute", retry_after_secs: MINUTE_SECS - now % MINUTE_SECS });
+        }
+        if key.per_day.is_some_and(|limit| entry.day >= limit) {
+            return Err(QuotaExceeded { window: "day", retry_after_secs: DAY_SECS - now % DAY_SECS });
+        }
+        entry.minute += 1;
+        entry.day += 1;
+        entry.total += 1;
+        Ok(())
+    }
+
+    /// 各Key当前窗口内的用量，已进入新窗口但尚无请求的Key显示为0
+    pub fn snapshot(&self) -> BTreeMap<String, KeyUsage> {
+        let now = now_secs();
+        let usage = self.usage.lock().unwrap_or_else(|e| e.into_inner());
+        usage
+            .iter()
+            .map(|(name, entry)| {
+                let mut entry = entry.clone();
+                if entry.minute_window != now / MINUTE_SECS {
+                    entry.minute = 0;
+                }
+                if entry.day_window != now / DAY_SECS {
+                    entry.day = 0;
+                }
+                (name.clone(), entry)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quotas_reset_with_their_windows() {
+        let key = ApiKeyConfig {
+            name: "team-a".to_string(),
+            key: "secret".to_string(),
+            per_minute: Some(2),
+            per_day: Some(3),
+        };
+        let usage = ApiKeyUsage::new();
+        let start = 10 * DAY_SECS;
+        assert!(usage.try_acquire_at(&key, start).is_ok());
+        assert!(usage.try_acquire_at(&key, start + 1).is_ok());
+        assert_eq!(
+            usage.try_acquire_at(&key, start + 2),
+            Err(QuotaExceeded { window: "minute", retry_after_secs: 58 })
+        );
+        assert!(usage.try_acquire_at(&key, start + MINUTE_SECS).is_ok());
+        assert_eq!(usage.try_acquire_at(&key, start + 2 * MINUTE_SECS).unwrap_err().window, "day");
+        assert!(usage.try_acquire_at(&key, start + DAY_SECS).is_ok());
+    }
+}
@@ -1,7 +1,8 @@
 use serde::{Deserialize, Serialize};
-use reqwest::Client;
+use super::upstream::{http_client_builder, request_timeout, send_with_retry};
 use std::time::Duration;
 use tracing::info;
+use super::privacy::log_ip;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct BgpApiMeta {
@@ -18,6 +19,9 @@ pub struct BgpApiMeta {
 pub struct BgpApiResult {
     pub prefix: String,
     pub meta: Vec<BgpApiMeta>,
+    /// 原始响应，仅在 upstream.keep_raw_bgp_api 开启时保留
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub raw_response: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -30,31 +34,39 @@ pub struct BgpApiResponse {
 pub struct BgpApiClient;
 
 impl BgpApiClient {
-    pub async fn query(ip: &str) -> Result<BgpApiResult, String> {
-        // 根据 IP 类型添加默认掩码（IPv4: /32, IPv6: /128）
-        let prefix = if ip.contains(':') {
-            format!("{}/128", ip)
+    /// 查询IP或前缀；单个IP按类型补全默认掩码（IPv4: /32, IPv6: /128），CIDR原样使用
+    /// keep_raw 为true时在结果中保留原始响应，用于排查解析问题
+    pub async fn query(target: &str, keep_raw: bool) -> Result<BgpApiResult, String> {
+        let prefix = if target.contains('/') {
+            target.to_string()
+        } else if target.contains(':') {
+            format!("{}/128", target)
         } else {
-            format!("{}/32", ip)
+            format!("{}/32", target)
         };
         let url = format!("https://rest.bgp-api.net/api/v1/prefix/{}/search", prefix);
-        info!("BGP API 请求 URL: {}", url);
-        let client = Client::builder()
-            .timeout(Duration::from_secs(10))
+        info!("BGP API 请求 URL: {}", url.replace(&prefix, &log_ip(&prefix)));
+        let client = http_client_builder()
+            .timeout(request_timeout(Duration::from_secs(10)))
             .build()
             .map_err(|e| format!("创建HTTP客户端失败: {}", e))?;
 
-        let resp = client.get(&url).send().await
+        let resp = send_with_retry(client.get(&url)).await
             .map_err(|e| format!("BGP-API请求失败: {}", e))?;
 
         if !resp.status().is_success() {
             return Err(format!("BGP-API请求失败: 状态码 {}", resp.status()));
         }
 
-        let json: BgpApiResponse = resp.json().await
+        let body = resp.text().await
+            .map_err(|e| format!("读取BGP-API响应失败: {}", e))?;
+        let json: BgpApiResponse = serde_json::from_str(&body)
             .map_err(|e| format!("解析BGP-API响应失败: {}", e))?;
 
-        if let Some(result) = json.result {
+        if let Some(mut result) = json.result {
+            if keep_raw {
+                result.raw_response = Some(body);
+            }
             Ok(result)
         } else {
             Err("BGP-API响应无result".to_string())
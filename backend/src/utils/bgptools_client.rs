@@ -1,10 +1,16 @@
 use std::io::{BufRead, BufReader, Write};
 use std::net::{IpAddr, TcpStream};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
 use std::time::Duration;
+use futures::StreamExt;
 use std::str::FromStr;
 use scraper::{Html, Selector};
 use serde::{Deserialize, Serialize};
 use tracing::{debug, error, info};
+use super::privacy::log_ip;
+use super::trace_context::with_trace_context;
+use super::upstream::http_client_builder;
+use crate::config::UpstreamConfig;
 
 const BGPTOOLS_WHOIS_SERVER: &str = "bgp.tools";
 const BGPTOOLS_WHOIS_PORT: u16 = 43;
@@ -12,13 +18,25 @@ const WHOIS_TIMEOUT: Duration = Duration::from_secs(15);
 const BGPTOOLS_WEBSITE: &str = "https://bgp.tools";
 const USER_AGENT: &str = "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/136.0.0.0 Safari/537.36";
 
+/// 上游网页抓取的整体超时（秒）
+static SCRAPE_TIMEOUT_SECS: AtomicU64 = AtomicU64::new(10);
+
+/// 上游网页允许读取的最大字节数
+static MAX_BODY_BYTES: AtomicUsize = AtomicUsize::new(2 * 1024 * 1024);
+
+/// 应用网页抓取的超时和大小上限，配置热重载后立即生效
+pub fn configure(config: &UpstreamConfig) {
+    SCRAPE_TIMEOUT_SECS.store(config.bgptools_scrape_timeout_secs, Ordering::Relaxed);
+    MAX_BODY_BYTES.store(config.bgptools_max_body_bytes, Ordering::Relaxed);
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct BgpToolsUpstream {
     pub asn: String,
     pub name: Option<String>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct BgpToolsInfo {
     pub asn: Option<String>,
     pub ip: String,
@@ -37,7 +55,7 @@ pub struct BgpToolsClient;
 impl BgpToolsClient {
     /// 查询IP的BGP Tools信息
     pub async fn lookup(ip: &str) -> Result<BgpToolsInfo, String> {
-        debug!("BGP Tools lookup: 查询IP {}", ip);
+        debug!("BGP Tools lookup: 查询IP {}", log_ip(ip));
         // 先获取基本信息
         let whois_info = Self::query_whois(ip)?;
         debug!("BGP Tools whois_info: {:?}", whois_info);
@@ -167,63 +185,161 @@ impl BgpToolsClient {
         }
     }
     
-    /// 从BGP Tools网站获取上游信息
+    /// 从BGP Tools网站获取前缀的上游信息
     async fn fetch_upstreams(prefix: &str) -> Result<Vec<BgpToolsUpstream>, String> {
-        let url = format!("{}/prefix/{}", BGPTOOLS_WEBSITE, prefix);
+        Self::fetch_page_upstreams(&format!("{}/prefix/{}", BGPTOOLS_WEBSITE, prefix)).await
+    }
+
+    /// 从BGP Tools网站获取AS自身的上游信息（asn 可带或不带 AS 前缀）
+    pub async fn fetch_as_upstreams(asn: &str) -> Result<Vec<BgpToolsUpstream>, String> {
+        Self::fetch_page_upstreams(&format!("{}/as/{}", BGPTOOLS_WEBSITE, asn_number(asn))).await
+    }
+
+    async fn fetch_page_upstreams(url: &str) -> Result<Vec<BgpToolsUpstream>, String> {
         info!("BGP Tools fetch_upstreams 请求URL: {}", url);
 
-        let client = reqwest::Client::builder()
-            .timeout(Duration::from_secs(30))
+        let client = http_client_builder()
+            .timeout(Duration::from_secs(SCRAPE_TIMEOUT_SECS.load(Ordering::Relaxed)))
             .user_agent(USER_AGENT)
             .build()
             .map_err(|e| format!("创建HTTP客户端失败: {}", e))?;
 
-        let response = client.get(&url).send().await
+        let response = with_trace_context(client.get(url)).send().await
             .map_err(|e| format!("HTTP请求失败: {}", e))?;
         if !response.status().is_success() {
             return Err(format!("HTTP请求失败: 状态码 {}", response.status()));
         }
-        let html = response.text().await
-            .map_err(|e| format!("读取HTTP响应失败: {}", e))?;
+        let html = read_body_limited(response, MAX_BODY_BYTES.load(Ordering::Relaxed)).await?;
         debug!("BGP Tools fetch_upstreams HTML长度: {}", html.len());
 
-        let document = Html::parse_document(&html);
-
-        // 选择Upstreams所在的上游区域 div
-        let div_selector = Selector::parse("div.grid-row > div.column-half").unwrap();
-        let h2_selector = Selector::parse("h2.heading-medium").unwrap();
-        let ul_selector = Selector::parse("ul").unwrap();
-        let li_selector = Selector::parse("li").unwrap();
-        let a_selector = Selector::parse("a").unwrap();
-
-        let mut upstreams = Vec::new();
-
-        for div in document.select(&div_selector) {
-            // 找到Upstreams标题
-            if let Some(h2) = div.select(&h2_selector).next() {
-                let h2_text = h2.text().collect::<Vec<_>>().join("").trim().to_string();
-                if h2_text.contains("Upstreams") {
-                    // 找ul > li
-                    if let Some(ul) = div.select(&ul_selector).next() {
-                        for li in ul.select(&li_selector) {
-                            let asn = li.select(&a_selector)
-                                .next()
-                                .map(|a| a.text().collect::<Vec<_>>().join("").trim().to_string())
-                                .unwrap_or_default();
-                            // a标签后面的文本节点
-                            let name = li.text().collect::<Vec<_>>().join("").replace(&asn, "").replace("-", "").trim().to_string();
-                            let name = if !name.is_empty() { Some(name) } else { None };
-                            upstreams.push(BgpToolsUpstream { asn, name });
-                        }
+        let upstreams = parse_upstreams(&html);
+        info!("获取到 {} 条上游信息", upstreams.len());
+        for u in &upstreams {
+            debug!("BGP Tools 上游: asn={}, name={:?}", u.asn, u.name);
+        }
+        Ok(upstreams)
+    }
+} 
+
+/// 去掉ASN的 AS 前缀，便于比较不同来源的ASN
+pub fn asn_number(asn: &str) -> &str {
+    let asn = asn.trim();
+    match asn.get(..2) {
+        Some(prefix) if prefix.eq_ignore_ascii_case("AS") => &asn[2..],
+        _ => asn,
+    }
+}
+
+/// 解析页面中 Upstreams 区域的上游列表
+fn parse_upstreams(html: &str) -> Vec<BgpToolsUpstream> {
+    let document = Html::parse_document(html);
+
+    // 选择Upstreams所在的上游区域 div
+    let div_selector = Selector::parse("div.grid-row > div.column-half").unwrap();
+    let h2_selector = Selector::parse("h2.heading-medium").unwrap();
+    let ul_selector = Selector::parse("ul").unwrap();
+    let li_selector = Selector::parse("li").unwrap();
+    let a_selector = Selector::parse("a").unwrap();
+
+    let mut upstreams = Vec::new();
+
+    for div in document.select(&div_selector) {
+        // 找到Upstreams标题
+        if let Some(h2) = div.select(&h2_selector).next() {
+            let h2_text = h2.text().collect::<Vec<_>>().join("").trim().to_string();
+            if h2_text.contains("Upstreams") {
+                // 找ul > li
+                if let Some(ul) = div.select(&ul_selector).next() {
+                    for li in ul.select(&li_selector) {
+                        let asn = li.select(&a_selector)
+                            .next()
+                            .map(|a| a.text().collect::<Vec<_>>().join("").trim().to_string())
+                            .unwrap_or_default();
+                        // a标签后面的文本节点
+                        let name = li.text().collect::<Vec<_>>().join("").replace(&asn, "").replace("-", "").trim().to_string();
+                        let name = if !name.is_empty() { Some(name) } else { None };
+                        upstreams.push(BgpToolsUpstream { asn, name });
                     }
                 }
             }
         }
+    }
+    upstreams
+}
 
-        info!("获取到 {} 条上游信息", upstreams.len());
-        for u in &upstreams {
-            debug!("BGP Tools 上游: asn={}, name={:?}", u.asn, u.name);
+/// 上游拓扑树中的一个AS，upstreams 为它自己的上游
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UpstreamNode {
+    pub asn: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    pub upstreams: Vec<UpstreamNode>,
+}
+
+/// 读取响应体，Content-Length 或实际读取的字节数超过 max_bytes 时中止
+async fn read_body_limited(response: reqwest::Response, max_bytes: usize) -> Result<String, String> {
+    if let Some(len) = response.content_length()
+        && len > max_bytes as u64
+    {
+        return Err(format!("HTTP响应过大: Content-Length {} 超过上限 {} 字节", len, max_bytes));
+    }
+    let mut body = Vec::new();
+    let mut stream = response.bytes_stream();
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk.map_err(|e| format!("读取HTTP响应失败: {}", e))?;
+        if body.len() + chunk.len() > max_bytes {
+            return Err(format!("HTTP响应过大: 超过上限 {} 字节", max_bytes));
         }
-        Ok(upstreams)
+        body.extend_from_slice(&chunk);
     }
-} 
\ No newline at end of file
+    Ok(String::from_utf8_lossy(&body).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn upstreams_are_parsed_and_asns_normalised() {
+        let html = r#"<div class="grid-row"><div class="column-half">
+            <h2 class="heading-medium">Upstreams</h2>
+            <ul><li><a href="/as/174">AS174</a> - Cogent</li><li><a href="/as/3356">AS3356</a></li></ul>
+        </div></div>"#;
+        let upstreams = parse_upstreams(html);
+        assert_eq!(upstreams.len(), 2);
+        assert_eq!(upstreams[0].asn, "AS174");
+        assert_eq!(upstreams[0].name.as_deref(), Some("Cogent"));
+        assert_eq!(upstreams[1].name, None);
+        assert_eq!(asn_number("as174"), "174");
+        assert_eq!(asn_number("13335"), "13335");
+    }
+
+    #[tokio::test]
+    async fn oversized_bodies_are_rejected() {
+        let app = axum::Router::new()
+            .route("/small", axum::routing::get(|| async { "x".repeat(100) }))
+            .route("/large", axum::routing::get(|| async { "x".repeat(10_000) }))
+            .route("/chunked", axum::routing::get(|| async {
+                // 不带 Content-Length，只能依靠流式读取的上限
+                let chunks = futures::stream::iter((0..10).map(|_| Ok::<_, std::io::Error>("x".repeat(1_000))));
+                axum::body::Body::from_stream(chunks)
+            }));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let client = reqwest::Client::new();
+        let get = |path: &str| client.get(format!("http://{}{}", addr, path)).send();
+
+        let body = read_body_limited(get("/small").await.unwrap(), 1_000).await.unwrap();
+        assert_eq!(body.len(), 100);
+
+        let err = read_body_limited(get("/large").await.unwrap(), 1_000).await.unwrap_err();
+        assert!(err.contains("Content-Length"));
+
+        let chunked = get("/chunked").await.unwrap();
+        assert!(chunked.content_length().is_none());
+        assert!(read_body_limited(chunked, 1_000).await.is_err());
+    }
+}
@@ -0,0 +1,202 @@
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeSet, HashMap};
+use std::fs;
+use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::{info, warn};
+
+/// 磁盘上按键摘要前两位分目录保存，每个条目一个文件
+const ENTRY_EXTENSION: &str = "bin";
+
+#[derive(Serialize, Deserialize)]
+struct DiskEntry<V> {
+    key: String,
+    expires_at: u64,
+    written_at: u64,
+    value: V,
+}
+
+#[derive(Default)]
+struct DiskIndex {
+    // 摘要 -> (文件大小, 最近访问序号)
+    files: HashMap<String, (u64, u64)>,
+    // 按最近访问序号排序，淘汰时取第一项
+    recency: BTreeSet<(u64, String)>,
+    total_bytes: u64,
+    next_seq: u64,
+}
+
+impl DiskIndex {
+    fn touch(&mut self, digest: &str, size: u64) {
+        self.remove(digest);
+        self.next_seq += 1;
+        self.files.insert(digest.to_string(), (size, self.next_seq));
+        self.recency.insert((self.next_seq, digest.to_string()));
+        self.total_bytes += size;
+    }
+
+    fn remove(&mut self, digest: &str) -> bool {
+        let Some((size, seq)) = self.files.remove(digest) else {
+            return false;
+        };
+        self.recency.remove(&(seq, digest.to_string()));
+        self.total_bytes -= size;
+        true
+    }
+}
+
+/// 内存缓存之外的磁盘二级缓存：内存中移出的条目写入磁盘，命中后取回内存
+/// 总大小超过 max_bytes 时删除最久未访问的条目；重启后按文件修改时间恢复访问顺序
+pub struct DiskTier<V> {
+    dir: PathBuf,
+    max_bytes: u64,
+    index: Mutex<DiskIndex>,
+    _value: PhantomData<fn() -> V>,
+}
+
+impl<V: Serialize + DeserializeOwned> DiskTier<V> {
+    /// 打开（或创建）目录并扫描已有条目
+    pub fn open<P: AsRef<Path>>(dir: P, max_bytes: u64) -> Result<Self, String> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir).map_err(|e| format!("创建二级缓存目录失败 ({}): {}", dir.display(), e))?;
+
+        let mut existing = Vec::new();
+        for shard in fs::read_dir(&dir).map_err(|e| format!("读取二级缓存目录失败: {}", e))?.flatten() {
+            let Ok(files) = fs::read_dir(shard.path()) else {
+                continue;
+            };
+            for file in files.flatten() {
+                let path = file.path();
+                let (Some(digest), Ok(metadata)) = (path.file_stem().and_then(|s| s.to_str()), file.metadata()) else {
+                    continue;
+                };
+                let modified = metadata.modified().unwrap_or(UNIX_EPOCH);
+                existing.push((modified, digest.to_string(), metadata.len()));
+            }
+        }
+        existing.sort();
+        let mut index = DiskIndex::default();
+        for (_, digest, size) in existing {
+            index.touch(&digest, size);
+        }
+        info!("二级缓存已打开: {}，条目数: {}，占用: {:.1}MB", dir.display(), index.files.len(), index.total_bytes as f64 / (1024.0 * 1024.0));
+
+        let tier = Self { dir, max_bytes, index: Mutex::new(index), _value: PhantomData };
+        tier.evict_over_capacity();
+        Ok(tier)
+    }
+
+    fn digest(key: &str) -> String {
+        Sha256::digest(key.as_bytes()).iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    fn path_for(&self, digest: &str) -> PathBuf {
+        self.dir.join(&digest[..2]).join(format!("{}.{}", digest, ENTRY_EXTENSION))
+    }
+
+    /// 写入条目（键、值、硬过期时间戳、写入时间），超出容量时淘汰最久未访问的条目
+    pub fn put(&self, key: &str, value: &V, expires_at: u64, written_at: u64) -> Result<(), String> {
+        let entry = DiskEntry { key: key.to_string(), expires_at, written_at, value };
+        let bytes = bincode::serialize(&entry).map_err(|e| format!("序列化二级缓存条目失败: {}", e))?;
+        let digest = Self::digest(key);
+        let path = self.path_for(&digest);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).map_err(|e| format!("创建二级缓存目录失败: {}", e))?;
+        }
+        fs::write(&path, &bytes).map_err(|e| format!("写入二级缓存失败 ({}): {}", path.display(), e))?;
+        self.index.lock().unwrap_or_else(|e| e.into_inner()).touch(&digest, bytes.len() as u64);
+        self.evict_over_capacity();
+        Ok(())
+    }
+
+    /// 取出条目并从磁盘删除（取回内存后由内存缓存负责），返回值、硬过期时间戳和写入时间
+    /// retain_until 之前已过期的条目直接删除
+    pub fn take(&self, key: &str, retain_until: impl Fn(u64) -> u64) -> Option<(V, u64, u64)> {
+        let digest = Self::digest(key);
+        if !self.index.lock().unwrap_or_else(|e| e.into_inner()).remove(&digest) {
+            return None;
+        }
+        let path = self.path_for(&digest);
+        let bytes = fs::read(&path);
+        let _ = fs::remove_file(&path);
+        let entry: DiskEntry<V> = match bytes.map_err(|e| e.to_string()).and_then(|bytes| bincode::deserialize(&bytes).map_err(|e| e.to_string())) {
+            Ok(entry) => entry,
+            Err(e) => {
+                warn!("二级缓存条目无法读取，已删除 ({}): {}", path.display(), e);
+                return None;
+            }
+        };
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        // 摘要冲突时键不一致，按未命中处理
+        (entry.key == key && retain_until(entry.expires_at) > now).then_some((entry.value, entry.expires_at, entry.written_at))
+    }
+
+    pub fn contains(&self, key: &str) -> bool {
+        self.index.lock().unwrap_or_else(|e| e.into_inner()).files.contains_key(&Self::digest(key))
+    }
+
+    pub fn remove(&self, key: &str) {
+        let digest = Self::digest(key);
+        if self.index.lock().unwrap_or_else(|e| e.into_inner()).remove(&digest) {
+            let _ = fs::remove_file(self.path_for(&digest));
+        }
+    }
+
+    fn evict_over_capacity(&self) {
+        let mut evicted = Vec::new();
+        {
+            let mut index = self.index.lock().unwrap_or_else(|e| e.into_inner());
+            while index.total_bytes > self.max_bytes {
+                let Some((_, digest)) = index.recency.first().cloned() else {
+                    break;
+                };
+                index.remove(&digest);
+                evicted.push(digest);
+            }
+        }
+        for digest in evicted {
+            let _ = fs::remove_file(self.path_for(&digest));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn least_recently_written_entries_are_evicted() {
+        let dir = tempfile::tempdir().unwrap();
+        let value = "x".repeat(100);
+        let tier: DiskTier<String> = DiskTier::open(dir.path(), 300).unwrap();
+        for key in ["a", "b", "c"] {
+            tier.put(key, &value, u64::MAX, 1).unwrap();
+        }
+        assert!(!tier.contains("a"));
+        assert!(tier.contains("c"));
+
+        assert_eq!(tier.take("c", |expires_at| expires_at), Some((value, u64::MAX, 1)));
+        assert!(!tier.contains("c"));
+
+        // 重新打开时按已有文件恢复索引
+        let tier: DiskTier<String> = DiskTier::open(dir.path(), 300).unwrap();
+        assert!(tier.contains("b"));
+        assert!(!tier.contains("c"));
+    }
+
+    #[test]
+    fn expired_entries_are_dropped_on_take() {
+        let dir = tempfile::tempdir().unwrap();
+        let tier: DiskTier<u32> = DiskTier::open(dir.path(), 1024).unwrap();
+        tier.put("a", &1, 1, 0).unwrap();
+        assert_eq!(tier.take("a", |expires_at| expires_at), None);
+        assert!(!tier.contains("a"));
+    }
+}
@@ -0,0 +1,126 @@
+use serde::Serialize;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// 按国家代码和ASN统计查询分布
+/// 采用固定时间窗口，窗口结束后清零，内存占用不超过一个窗口内出现的国家和ASN数量
+pub struct Distribution {
+    window: Duration,
+    state: Mutex<WindowState>,
+}
+
+struct WindowState {
+    started_at: u64,
+    countries: HashMap<String, u64>,
+    asns: HashMap<u32, u64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DistributionEntry<K> {
+    pub key: K,
+    pub count: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DistributionSnapshot {
+    /// 当前统计窗口的开始时间（Unix时间戳）
+    pub window_start: u64,
+    pub window_secs: u64,
+    pub countries: Vec<DistributionEntry<String>>,
+    pub asns: Vec<DistributionEntry<u32>>,
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// 按计数降序取前 limit 项，计数相同时按键排序保证输出稳定
+fn top_n<K: Clone + Ord + Hash>(counts: &HashMap<K, u64>, limit: usize) -> Vec<DistributionEntry<K>> {
+    let mut entries: Vec<_> = counts.iter().collect();
+    entries.sort_by(|a, b| b.1.cmp(a.1).then_with(|| a.0.cmp(b.0)));
+    entries
+        .into_iter()
+        .take(limit)
+        .map(|(key, count)| DistributionEntry { key: key.clone(), count: *count })
+        .collect()
+}
+
+impl Distribution {
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            state: Mutex::new(WindowState {
+                started_at: now_secs(),
+                countries: HashMap::new(),
+                asns: HashMap::new(),
+            }),
+        }
+    }
+
+    /// 记录一次查询结果
+    pub fn record(&self, country_code: Option<&str>, asn: Option<u32>) {
+        let mut state = self.current_window();
+        if let Some(code) = country_code {
+            *state.countries.entry(code.to_string()).or_insert(0) += 1;
+        }
+        if let Some(asn) = asn {
+            *state.asns.entry(asn).or_insert(0) += 1;
+        }
+    }
+
+    /// 当前窗口内计数最多的前 limit 个国家和ASN
+    pub fn snapshot(&self, limit: usize) -> DistributionSnapshot {
+        let state = self.current_window();
+        DistributionSnapshot {
+            window_start: state.started_at,
+            window_secs: self.window.as_secs(),
+            countries: top_n(&state.countries, limit),
+            asns: top_n(&state.asns, limit),
+        }
+    }
+
+    /// 获取当前窗口，已过期时先清零
+    fn current_window(&self) -> std::sync::MutexGuard<'_, WindowState> {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        let now = now_secs();
+        if now.saturating_sub(state.started_at) >= self.window.as_secs() {
+            state.started_at = now;
+            state.countries.clear();
+            state.asns.clear();
+        }
+        state
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn snapshot_returns_top_entries_by_count() {
+        let distribution = Distribution::new(Duration::from_secs(3600));
+        distribution.record(Some("US"), Some(15169));
+        distribution.record(Some("US"), Some(13335));
+        distribution.record(Some("CN"), Some(13335));
+        distribution.record(None, None);
+
+        let snapshot = distribution.snapshot(1);
+        assert_eq!(snapshot.countries.len(), 1);
+        assert_eq!(snapshot.countries[0].key, "US");
+        assert_eq!(snapshot.countries[0].count, 2);
+        assert_eq!(snapshot.asns[0].key, 13335);
+        assert_eq!(snapshot.asns[0].count, 2);
+    }
+
+    #[test]
+    fn expired_window_is_reset() {
+        let distribution = Distribution::new(Duration::ZERO);
+        distribution.record(Some("US"), Some(15169));
+        assert!(distribution.snapshot(10).countries.is_empty());
+    }
+}
@@ -0,0 +1,50 @@
+use std::net::SocketAddr;
+use hickory_resolver::config::ResolverOpts;
+use hickory_resolver::TokioAsyncResolver;
+use hyper_014::client::connect::dns::Name;
+use reqwest::dns::{Addrs, Resolve, Resolving};
+
+/// 所有HTTP客户端共享的DNS解析器，按记录的TTL缓存解析结果
+/// 避免每次请求都重新解析 bgp.tools、BGP API、RPKI验证器等上游主机名
+pub struct CachingResolver {
+    resolver: TokioAsyncResolver,
+}
+
+impl CachingResolver {
+    /// 使用系统DNS配置，缓存最多 cache_size 条记录
+    pub fn from_system_conf(cache_size: usize) -> Result<Self, String> {
+        let (config, mut opts) = hickory_resolver::system_conf::read_system_conf()
+            .map_err(|e| format!("读取系统DNS配置失败: {}", e))?;
+        opts.cache_size = cache_size;
+        Ok(Self::new(config, opts))
+    }
+
+    fn new(config: hickory_resolver::config::ResolverConfig, opts: ResolverOpts) -> Self {
+        Self { resolver: TokioAsyncResolver::tokio(config, opts) }
+    }
+}
+
+impl Resolve for CachingResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let resolver = self.resolver.clone();
+        Box::pin(async move {
+            let lookup = resolver.lookup_ip(name.as_str()).await?;
+            // 端口由连接器按URL填写
+            let addrs: Addrs = Box::new(lookup.into_iter().map(|ip| SocketAddr::new(ip, 0)).collect::<Vec<_>>().into_iter());
+            Ok(addrs)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    #[tokio::test]
+    async fn resolves_names_from_hosts_file() {
+        let resolver = CachingResolver::new(Default::default(), ResolverOpts::default());
+        let addrs: Vec<SocketAddr> = resolver.resolve(Name::from_str("localhost").unwrap()).await.unwrap().collect();
+        assert!(addrs.iter().any(|addr| addr.ip().is_loopback()));
+    }
+}
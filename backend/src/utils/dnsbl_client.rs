@@ -0,0 +1,106 @@
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+use std::time::Duration;
+use futures::future::join_all;
+use hickory_resolver::config::{NameServerConfigGroup, ResolverConfig, ResolverOpts};
+use hickory_resolver::error::ResolveErrorKind;
+use hickory_resolver::TokioAsyncResolver;
+use tracing::{debug, warn};
+use crate::config::DnsblConfig;
+
+/// DNS黑名单客户端
+pub struct DnsblClient;
+
+impl DnsblClient {
+    /// 并发查询所有配置的DNSBL区域，返回IP被列入的区域
+    pub async fn check(ip: &str, config: &DnsblConfig) -> Result<Vec<String>, String> {
+        let addr = IpAddr::from_str(ip).map_err(|e| format!("无效的IP地址: {}", e))?;
+        let reversed = Self::reverse_name(addr);
+        let resolver = Self::build_resolver(config)?;
+        let timeout = Duration::from_millis(config.timeout_ms);
+
+        let checks = config.zones.iter().map(|zone| {
+            let resolver = &resolver;
+            let name = format!("{}.{}.", reversed, zone.trim_end_matches('.'));
+            async move {
+                match tokio::time::timeout(timeout, resolver.ipv4_lookup(name.as_str())).await {
+                    Ok(Ok(answers)) => {
+                        let codes: Vec<_> = answers.iter().map(|a| a.0).collect();
+                        // 127.255.255.x 表示查询被拒绝（如使用了公共解析器），不代表被列入
+                        if codes.iter().any(|c| c.octets()[..3] == [127, 255, 255]) {
+                            warn!("DNSBL {} 拒绝了查询，返回码: {:?}", zone, codes);
+                            None
+                        } else if codes.iter().any(|c| c.octets()[0] == 127) {
+                            Some(zone.clone())
+                        } else {
+                            None
+                        }
+                    }
+                    Ok(Err(e)) => {
+                        if !matches!(e.kind(), ResolveErrorKind::NoRecordsFound { .. }) {
+                            warn!("DNSBL {} 查询失败: {}", zone, e);
+                        }
+                        None
+                    }
+                    Err(_) => {
+                        warn!("DNSBL {} 查询超时", zone);
+                        None
+                    }
+                }
+            }
+        });
+
+        let listed: Vec<String> = join_all(checks).await.into_iter().flatten().collect();
+        debug!("DNSBL 查询完成，命中: {:?}", listed);
+        Ok(listed)
+    }
+
+    /// 构建反向查询名：IPv4按字节反转，IPv6按半字节反转
+    fn reverse_name(addr: IpAddr) -> String {
+        match addr {
+            IpAddr::V4(v4) => {
+                let o = v4.octets();
+                format!("{}.{}.{}.{}", o[3], o[2], o[1], o[0])
+            }
+            IpAddr::V6(v6) => v6.octets()
+                .iter()
+                .rev()
+                .flat_map(|b| [b & 0x0f, b >> 4])
+                .map(|n| format!("{:x}", n))
+                .collect::<Vec<_>>()
+                .join("."),
+        }
+    }
+
+    /// 使用配置的解析器，未配置时使用系统解析器配置
+    fn build_resolver(config: &DnsblConfig) -> Result<TokioAsyncResolver, String> {
+        match &config.resolver {
+            Some(resolver) => {
+                let addr = SocketAddr::from_str(resolver)
+                    .or_else(|_| IpAddr::from_str(resolver).map(|ip| SocketAddr::new(ip, 53)))
+                    .map_err(|e| format!("无效的DNSBL解析器地址 {}: {}", resolver, e))?;
+                let servers = NameServerConfigGroup::from_ips_clear(&[addr.ip()], addr.port(), true);
+                Ok(TokioAsyncResolver::tokio(
+                    ResolverConfig::from_parts(None, vec![], servers),
+                    ResolverOpts::default(),
+                ))
+            }
+            None => TokioAsyncResolver::tokio_from_system_conf()
+                .map_err(|e| format!("读取系统DNS配置失败: {}", e)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reverse_name_for_ipv4_and_ipv6() {
+        assert_eq!(DnsblClient::reverse_name("192.0.2.99".parse().unwrap()), "99.2.0.192");
+        assert_eq!(
+            DnsblClient::reverse_name("2001:db8::1".parse().unwrap()),
+            "1.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.8.b.d.0.1.0.0.2"
+        );
+    }
+}
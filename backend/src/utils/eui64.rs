@@ -0,0 +1,68 @@
+use std::net::{IpAddr, Ipv6Addr};
+
+/// 常见厂商的OUI（MAC地址前3字节），用于标注EUI-64地址中嵌入的MAC
+const OUI_VENDORS: &[([u8; 3], &str)] = &[
+    ([0x00, 0x00, 0x0c], "Cisco"),
+    ([0x00, 0x05, 0x69], "VMware"),
+    ([0x00, 0x0c, 0x29], "VMware"),
+    ([0x00, 0x15, 0x5d], "Microsoft Hyper-V"),
+    ([0x00, 0x16, 0x3e], "Xen"),
+    ([0x00, 0x1b, 0x21], "Intel"),
+    ([0x00, 0x1c, 0x42], "Parallels"),
+    ([0x00, 0x50, 0x56], "VMware"),
+    ([0x08, 0x00, 0x27], "VirtualBox"),
+    ([0x3c, 0x5a, 0xb4], "Google"),
+    ([0x52, 0x54, 0x00], "QEMU/KVM"),
+    ([0xb8, 0x27, 0xeb], "Raspberry Pi"),
+    ([0xdc, 0xa6, 0x32], "Raspberry Pi"),
+    ([0xf0, 0x9f, 0xc2], "Ubiquiti"),
+];
+
+/// 从EUI-64格式的接口标识符中提取MAC地址（中间为 ff:fe，并还原U/L位）
+pub fn embedded_mac(addr: Ipv6Addr) -> Option<[u8; 6]> {
+    let octets = addr.octets();
+    let iid = &octets[8..];
+    if iid[3] != 0xff || iid[4] != 0xfe {
+        return None;
+    }
+    Some([iid[0] ^ 0x02, iid[1], iid[2], iid[5], iid[6], iid[7]])
+}
+
+pub fn format_mac(mac: &[u8; 6]) -> String {
+    mac.iter().map(|b| format!("{:02x}", b)).collect::<Vec<_>>().join(":")
+}
+
+/// 按OUI查找厂商，未收录时返回None
+pub fn mac_vendor(mac: &[u8; 6]) -> Option<&'static str> {
+    OUI_VENDORS
+        .iter()
+        .find(|(oui, _)| oui[..] == mac[..3])
+        .map(|(_, vendor)| *vendor)
+}
+
+/// 查询地址为EUI-64格式的IPv6地址时，返回嵌入的MAC（格式化后）及其厂商
+pub fn mac_fields(ip: &str) -> (Option<String>, Option<String>) {
+    let Ok(IpAddr::V6(addr)) = ip.parse::<IpAddr>() else {
+        return (None, None);
+    };
+    match embedded_mac(addr) {
+        Some(mac) => (Some(format_mac(&mac)), mac_vendor(&mac).map(str::to_string)),
+        None => (None, None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn extracts_mac_from_eui64_address() {
+        let addr: Ipv6Addr = "2001:db8::5054:ff:fe12:3456".parse().unwrap();
+        let mac = embedded_mac(addr).unwrap();
+        assert_eq!(format_mac(&mac), "52:54:00:12:34:56");
+        assert_eq!(mac_vendor(&mac), Some("QEMU/KVM"));
+
+        let privacy: Ipv6Addr = "2001:db8::1c2d:3e4f:5a6b:7c8d".parse().unwrap();
+        assert!(embedded_mac(privacy).is_none());
+    }
+}
@@ -1,52 +1,451 @@
+use ipnet::Ipv6Net;
+use std::net::IpAddr;
 use std::path::Path;
 use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use futures::future::BoxFuture;
 use tokio::sync::RwLock;
 use crate::maxmind::reader::IpInfo;
-use super::kv_store::KvStore;
-use tracing::info;
+use super::disk_tier::DiskTier;
+use super::eui64;
+use super::kv_store::{EvictedEntry, KvStore, PersistFormat};
+use super::privacy::{cache_key, hashes_cache_keys, log_ip};
+use tracing::{info, warn};
+
+/// 缓存命中的条目
+#[derive(Debug)]
+pub struct CacheHit {
+    pub info: IpInfo,
+    /// 距离软过期的剩余秒数
+    pub ttl_remaining: u64,
+    /// 已软过期，处于保留窗口内
+    pub stale: bool,
+    /// 写入缓存至今的秒数
+    pub age_secs: u64,
+}
+
+/// IP查询结果缓存的抽象，处理器通过它读写缓存；默认为进程内的 IpCache，配置 cache.redis_url 时为 RedisCache
+pub trait CacheBackend: Send + Sync {
+    /// 获取缓存的IP信息及其剩余有效期、是否已过期（处于保留窗口内）和写入至今的时间
+    fn get_with_ttl<'a>(&'a self, ip: &'a str) -> BoxFuture<'a, Option<CacheHit>>;
+    fn set<'a>(&'a self, ip: &'a str, info: IpInfo) -> BoxFuture<'a, Result<(), String>>;
+    fn contains<'a>(&'a self, ip: &'a str) -> BoxFuture<'a, bool>;
+    /// 缓存条目的完整存活时间
+    fn ttl(&self) -> BoxFuture<'_, Duration>;
+    /// 立即清理过期条目，返回清理数量
+    fn cleanup(&self) -> BoxFuture<'_, usize>;
+    /// 条目数量和内存占用（MB）
+    fn stats(&self) -> BoxFuture<'_, (usize, f64)>;
+    /// 未来 within 时间内将（软）过期的条目数量
+    fn expiring_within(&self, within: Duration) -> BoxFuture<'_, usize>;
+    /// 已硬过期但仍在 error_grace 内的条目（剩余有效期为0），仅在数据源全部不可用时使用
+    fn get_expired<'a>(&'a self, ip: &'a str) -> BoxFuture<'a, Option<CacheHit>>;
+}
+
+/// 计算缓存键：同一IPv6子网内的地址按 ipv6_group_prefix 分组，再按隐私配置处理
+pub fn cache_key_for(ip: &str, ipv6_group_prefix: Option<u8>) -> String {
+    if let Some(prefix_len) = ipv6_group_prefix
+        && let Ok(IpAddr::V6(addr)) = ip.parse::<IpAddr>()
+        && let Ok(network) = Ipv6Net::new(addr, prefix_len) {
+        return cache_key(&network.trunc().to_string());
+    }
+    cache_key(ip)
+}
+
+/// 写入前去掉只属于查询地址本身的字段：嵌入的MAC（条目可能被同一网段的其他地址共享），
+/// 以及缓存键为摘要时的明文IP
+pub fn strip_ip_for_storage(info: &mut IpInfo) {
+    info.embedded_mac = None;
+    info.mac_vendor = None;
+    if hashes_cache_keys() {
+        info.ip.clear();
+        if let Some(bgp_info) = info.bgp_info.as_mut() {
+            bgp_info.ip.clear();
+        }
+    }
+}
+
+/// 读取时用查询的IP补回写入前去掉的明文IP和嵌入的MAC
+pub fn restore_ip_from_query(info: &mut IpInfo, ip: &str) {
+    (info.embedded_mac, info.mac_vendor) = eui64::mac_fields(ip);
+    if info.ip.is_empty() {
+        info.ip = ip.to_string();
+    }
+    if let Some(bgp_info) = info.bgp_info.as_mut()
+        && bgp_info.ip.is_empty() {
+        bgp_info.ip = ip.to_string();
+    }
+}
 
 #[allow(dead_code)]
 pub struct IpCache {
     store: Arc<RwLock<KvStore<String, IpInfo>>>,
+    /// 单个IPv6地址按该长度的前缀共享缓存条目（如 64）
+    ipv6_group_prefix: Option<u8>,
+    /// 内存达到上限时移出的条目写入的磁盘二级缓存
+    disk_tier: Option<Arc<DiskTier<IpInfo>>>,
 }
 
 #[allow(dead_code)]
 impl IpCache {
-    pub fn new<P: AsRef<Path>>(file_path: P) -> Self {
-        let store = KvStore::create_shared(file_path);
-        Self { store }
+    pub fn new<P: AsRef<Path>>(file_path: P, ttl: Duration, format: PersistFormat) -> Self {
+        let store = Arc::new(RwLock::new(KvStore::new(file_path).with_ttl(ttl).with_format(format)));
+        Self { store, ipv6_group_prefix: None, disk_tier: None }
+    }
+    
+    /// 条目过期后在 stale_window 内仍可读取（标记为过期），用于后台刷新期间继续提供旧数据
+    pub fn with_stale_window(mut self, stale_window: Duration) -> Self {
+        Arc::get_mut(&mut self.store)
+            .expect("缓存创建后才能设置过期保留时间")
+            .get_mut()
+            .set_stale_window(stale_window);
+        self
+    }
+    
+    /// 每个条目的存活时间随机延长 0..=ttl_jitter，避免热门条目同时过期引发集中回源
+    pub fn with_ttl_jitter(mut self, ttl_jitter: Duration) -> Self {
+        Arc::get_mut(&mut self.store)
+            .expect("缓存创建后才能设置过期抖动")
+            .get_mut()
+            .set_ttl_jitter(ttl_jitter);
+        self
+    }
+    
+    /// 条目硬过期后再保留 error_grace，数据源全部不可用时仍可返回
+    pub fn with_error_grace(mut self, error_grace: Duration) -> Self {
+        Arc::get_mut(&mut self.store)
+            .expect("缓存创建后才能设置宽限期")
+            .get_mut()
+            .set_error_grace(error_grace);
+        self
+    }
+    
+    /// 持久化耗时超过 threshold 时输出警告
+    pub fn with_slow_persist_threshold(mut self, threshold: Option<Duration>) -> Self {
+        Arc::get_mut(&mut self.store)
+            .expect("缓存创建后才能设置持久化告警阈值")
+            .get_mut()
+            .set_slow_persist_threshold(threshold);
+        self
     }
     
-    pub async fn start_tasks(self: &Self) {
-        KvStore::start_background_tasks(self.store.clone()).await;
+    /// 内存占用上限（估算字节数）
+    pub fn with_memory_limit(mut self, memory_limit: usize) -> Self {
+        Arc::get_mut(&mut self.store)
+            .expect("缓存创建后才能设置内存上限")
+            .get_mut()
+            .set_memory_limit(memory_limit);
+        self
+    }
+    
+    /// 内存达到上限时将最早过期的条目移入磁盘二级缓存，命中时再取回内存
+    pub fn with_disk_tier(mut self, disk_tier: DiskTier<IpInfo>) -> Self {
+        Arc::get_mut(&mut self.store)
+            .expect("缓存创建后才能设置二级缓存")
+            .get_mut()
+            .set_evict_when_full(true);
+        self.disk_tier = Some(Arc::new(disk_tier));
+        self
+    }
+    
+    /// 将内存中移出的条目在后台写入二级缓存
+    fn spill(&self, evicted: Vec<EvictedEntry<String, IpInfo>>) {
+        let Some(disk_tier) = self.disk_tier.clone().filter(|_| !evicted.is_empty()) else {
+            return;
+        };
+        tokio::task::spawn_blocking(move || {
+            for entry in evicted {
+                if let Err(e) = disk_tier.put(&entry.key, &entry.value, entry.expires_at, entry.written_at) {
+                    warn!("{}", e);
+                }
+            }
+        });
+    }
+    
+    /// 内存未命中时从二级缓存取回条目（仍在宽限期内的已过期条目也取回）
+    async fn promote(&self, key: &str) {
+        let Some(disk_tier) = &self.disk_tier else {
+            return;
+        };
+        let mut store = self.store.write().await;
+        let error_grace = store.error_grace().as_secs();
+        let Some((info, expires_at, written_at)) = disk_tier.take(key, |expires_at| expires_at.saturating_add(error_grace)) else {
+            return;
+        };
+        if let Err(e) = store.set_with_expiry(key.to_string(), info, expires_at, written_at) {
+            warn!("二级缓存条目无法取回内存: {}", e);
+        }
+        let evicted = store.take_evicted();
+        drop(store);
+        self.spill(evicted);
+    }
+    
+    /// 持久化文件使用gzip压缩
+    pub fn with_compression(mut self, compress: bool) -> Self {
+        Arc::get_mut(&mut self.store)
+            .expect("缓存创建后才能设置压缩")
+            .get_mut()
+            .set_compression(compress);
+        self
+    }
+    
+    /// 同一IPv6子网内的地址共享缓存条目，prefix_len 为子网前缀长度
+    pub fn with_ipv6_grouping(mut self, prefix_len: Option<u8>) -> Self {
+        self.ipv6_group_prefix = prefix_len;
+        self
+    }
+    
+    fn key(&self, ip: &str) -> String {
+        cache_key_for(ip, self.ipv6_group_prefix)
+    }
+    
+    /// 缓存条目的完整存活时间
+    pub async fn ttl(&self) -> Duration {
+        self.store.read().await.ttl()
+    }
+    
+    /// 从磁盘加载持久化的缓存，完成后才返回，返回加载的条目数
+    pub async fn warm_up(&self) -> usize {
+        KvStore::warm_up(&self.store).await
+    }
+    
+    /// 启动后台任务（定期持久化、过期清理），需在 warm_up 之后调用
+    pub fn start_tasks(&self, cleanup_interval: Duration) {
+        KvStore::spawn_background_tasks(self.store.clone(), cleanup_interval);
     }
     
     pub async fn get(&self, ip: &str) -> Option<IpInfo> {
+        let key = self.key(ip);
+        if !self.store.read().await.contains_key(&key) {
+            self.promote(&key).await;
+        }
         let store = self.store.read().await;
-        store.get(&ip.to_string())
+        let mut info = store.get(&key)?;
+        restore_ip_from_query(&mut info, ip);
+        Some(info)
     }
     
-    pub async fn set(&self, ip: &str, info: IpInfo) -> Result<(), String> {
+    /// 获取缓存的IP信息及其剩余有效期、是否已过期（处于保留窗口内）和写入至今的时间
+    pub async fn get_with_ttl(&self, ip: &str) -> Option<CacheHit> {
+        let key = self.key(ip);
+        if !self.store.read().await.contains_key(&key) {
+            self.promote(&key).await;
+        }
+        let store = self.store.read().await;
+        let (mut info, soft_expires_at, stale, written_at) = store.get_with_freshness(&key)?;
+        restore_ip_from_query(&mut info, ip);
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        Some(CacheHit {
+            info,
+            ttl_remaining: soft_expires_at.saturating_sub(now),
+            stale,
+            age_secs: now.saturating_sub(written_at),
+        })
+    }
+    
+    /// 获取已硬过期但仍在宽限期内的IP信息及写入至今的时间
+    pub async fn get_expired(&self, ip: &str) -> Option<CacheHit> {
+        let key = self.key(ip);
+        if self.store.read().await.get_expired(&key).is_none() {
+            self.promote(&key).await;
+        }
+        let store = self.store.read().await;
+        let (mut info, written_at) = store.get_expired(&key)?;
+        restore_ip_from_query(&mut info, ip);
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        Some(CacheHit { info, ttl_remaining: 0, stale: true, age_secs: now.saturating_sub(written_at) })
+    }
+    
+    pub async fn set(&self, ip: &str, mut info: IpInfo) -> Result<(), String> {
+        strip_ip_for_storage(&mut info);
+        let key = self.key(ip);
         let mut store = self.store.write().await;
-        let result = store.set(ip.to_string(), info);
+        let result = store.set(key.clone(), info);
+        let evicted = store.take_evicted();
+        drop(store);
         if result.is_ok() {
-            info!("IP信息已缓存: {}", ip);
+            info!("IP信息已缓存: {}", log_ip(ip));
+            // 内存中已是新值，二级缓存中的旧值不再需要
+            if let Some(disk_tier) = &self.disk_tier {
+                disk_tier.remove(&key);
+            }
         }
+        self.spill(evicted);
         result
     }
     
     pub async fn contains(&self, ip: &str) -> bool {
-        let store = self.store.read().await;
-        store.contains_key(&ip.to_string())
+        let key = self.key(ip);
+        self.store.read().await.contains_key(&key)
+            || self.disk_tier.as_ref().is_some_and(|disk_tier| disk_tier.contains(&key))
     }
     
     pub async fn remove(&self, ip: &str) -> Option<IpInfo> {
+        let key = self.key(ip);
+        if let Some(disk_tier) = &self.disk_tier {
+            disk_tier.remove(&key);
+        }
+        let mut store = self.store.write().await;
+        store.remove(&key)
+    }
+    
+    /// 立即清理过期条目，返回清理数量
+    pub async fn cleanup(&self) -> usize {
         let mut store = self.store.write().await;
-        store.remove(&ip.to_string())
+        store.cleanup_expired()
+    }
+    
+    /// 未来 within 时间内将（软）过期的条目数量，已过期的条目不计入
+    pub async fn expiring_within(&self, within: Duration) -> usize {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let deadline = now.saturating_add(within.as_secs());
+        let store = self.store.read().await;
+        store.iter_by_expiry()
+            .map(|(_, expires_at)| expires_at)
+            .skip_while(|expires_at| *expires_at <= now)
+            .take_while(|expires_at| *expires_at <= deadline)
+            .count()
     }
     
     pub async fn stats(&self) -> (usize, f64) {
         let store = self.store.read().await;
         (store.len(), store.memory_usage_mb())
     }
-} 
\ No newline at end of file
+}
+
+impl CacheBackend for IpCache {
+    fn get_with_ttl<'a>(&'a self, ip: &'a str) -> BoxFuture<'a, Option<CacheHit>> {
+        Box::pin(IpCache::get_with_ttl(self, ip))
+    }
+    
+    fn set<'a>(&'a self, ip: &'a str, info: IpInfo) -> BoxFuture<'a, Result<(), String>> {
+        Box::pin(IpCache::set(self, ip, info))
+    }
+    
+    fn contains<'a>(&'a self, ip: &'a str) -> BoxFuture<'a, bool> {
+        Box::pin(IpCache::contains(self, ip))
+    }
+    
+    fn ttl(&self) -> BoxFuture<'_, Duration> {
+        Box::pin(IpCache::ttl(self))
+    }
+    
+    fn cleanup(&self) -> BoxFuture<'_, usize> {
+        Box::pin(IpCache::cleanup(self))
+    }
+    
+    fn stats(&self) -> BoxFuture<'_, (usize, f64)> {
+        Box::pin(IpCache::stats(self))
+    }
+    
+    fn expiring_within(&self, within: Duration) -> BoxFuture<'_, usize> {
+        Box::pin(IpCache::expiring_within(self, within))
+    }
+    
+    fn get_expired<'a>(&'a self, ip: &'a str) -> BoxFuture<'a, Option<CacheHit>> {
+        Box::pin(IpCache::get_expired(self, ip))
+    }
+} 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn ipv6_addresses_in_same_subnet_share_entry() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = IpCache::new(dir.path().join("cache.bin"), Duration::from_secs(60), PersistFormat::default())
+            .with_ipv6_grouping(Some(64));
+        let info = IpInfo { ip: "2001:db8::1".to_string(), asn: Some(64496), ..Default::default() };
+        cache.set("2001:db8::1", info).await.unwrap();
+        assert_eq!(cache.get("2001:db8::ffff").await.and_then(|i| i.asn), Some(64496));
+        assert!(cache.get("2001:db8:0:1::1").await.is_none());
+        assert!(cache.get("192.0.2.1").await.is_none());
+    }
+
+    #[tokio::test]
+    async fn entries_evicted_from_memory_are_served_from_disk_tier() {
+        let dir = tempfile::tempdir().unwrap();
+        let disk_tier = DiskTier::open(dir.path().join("tier"), 1024 * 1024).unwrap();
+        let info = |ip: &str| IpInfo { ip: ip.to_string(), asn: Some(64496), ..Default::default() };
+        let cache = IpCache::new(dir.path().join("cache.bin"), Duration::from_secs(60), PersistFormat::default())
+            .with_disk_tier(disk_tier);
+        cache.set("192.0.2.1", info("192.0.2.1")).await.unwrap();
+        // 内存只够容纳一个条目，写入第二个时先移出第一个
+        let usage = cache.store.read().await.memory_usage();
+        cache.store.write().await.set_memory_limit(usage);
+        cache.set("192.0.2.2", info("192.0.2.2")).await.unwrap();
+        assert_eq!(cache.store.read().await.len(), 1);
+        
+        // 等待后台写入磁盘
+        for _ in 0..50 {
+            if cache.contains("192.0.2.1").await {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        let hit = cache.get_with_ttl("192.0.2.1").await.unwrap();
+        assert_eq!(hit.info.asn, Some(64496));
+        assert!(!hit.stale);
+    }
+
+    #[tokio::test]
+    async fn embedded_mac_follows_the_queried_address_within_a_group() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = IpCache::new(dir.path().join("cache.bin"), Duration::from_secs(60), PersistFormat::default())
+            .with_ipv6_grouping(Some(64));
+        let first = "2001:db8::5054:ff:fe12:3456";
+        let (embedded_mac, mac_vendor) = eui64::mac_fields(first);
+        let info = IpInfo { ip: first.to_string(), embedded_mac, mac_vendor, ..Default::default() };
+        cache.set(first, info).await.unwrap();
+        
+        let hit = cache.get_with_ttl("2001:db8::021c:42ff:feab:cdef").await.unwrap();
+        assert_eq!(hit.info.embedded_mac.as_deref(), Some("00:1c:42:ab:cd:ef"));
+        assert_eq!(hit.info.mac_vendor.as_deref(), Some("Parallels"));
+        let hit = cache.get_with_ttl(first).await.unwrap();
+        assert_eq!(hit.info.embedded_mac.as_deref(), Some("52:54:00:12:34:56"));
+        assert!(cache.get_with_ttl("2001:db8::1").await.unwrap().info.embedded_mac.is_none());
+    }
+
+    #[tokio::test]
+    async fn jittered_entries_report_age_from_write_time() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = IpCache::new(dir.path().join("cache.bin"), Duration::from_secs(60), PersistFormat::default())
+            .with_ttl_jitter(Duration::from_secs(1_000_000));
+        cache.set("192.0.2.1", IpInfo { ip: "192.0.2.1".to_string(), ..Default::default() }).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(1100)).await;
+        // ?max_age=1 按 age_secs >= 1 判断需要重新查询，抖动不能让条目显得更新
+        let hit = cache.get_with_ttl("192.0.2.1").await.unwrap();
+        assert!((1..=2).contains(&hit.age_secs), "{}", hit.age_secs);
+        assert!(hit.ttl_remaining >= 58);
+    }
+
+    #[tokio::test]
+    async fn entries_past_ttl_are_served_as_stale_within_window() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = IpCache::new(dir.path().join("cache.bin"), Duration::ZERO, PersistFormat::default())
+            .with_stale_window(Duration::from_secs(60));
+        cache.set("192.0.2.1", IpInfo { ip: "192.0.2.1".to_string(), ..Default::default() }).await.unwrap();
+        let hit = cache.get_with_ttl("192.0.2.1").await.unwrap();
+        assert_eq!(hit.ttl_remaining, 0);
+        assert!(hit.stale);
+        assert!(hit.age_secs <= 1);
+    }
+
+    #[tokio::test]
+    async fn expiring_within_counts_entries_in_window() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = IpCache::new(dir.path().join("cache.bin"), Duration::from_secs(60), PersistFormat::default());
+        cache.set("192.0.2.1", IpInfo { ip: "192.0.2.1".to_string(), ..Default::default() }).await.unwrap();
+        assert_eq!(cache.expiring_within(Duration::from_secs(30)).await, 0);
+        assert_eq!(cache.expiring_within(Duration::from_secs(120)).await, 1);
+    }
+}
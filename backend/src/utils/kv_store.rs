@@ -1,4 +1,4 @@
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap};
 use std::fs::{File, OpenOptions};
 use std::io::{Read, Write};
 use std::path::{Path, PathBuf};
@@ -7,20 +7,141 @@ use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use tokio::sync::RwLock;
 use tokio::time;
 use serde::{Deserialize, Serialize};
-use tracing::{error, info};
+use tracing::{error, info, warn};
 use std::hash::Hash;
+use rand::Rng;
 
 const MAX_MEMORY_BYTES: usize = 1024 * 1024 * 1024; // 1024MB
 const PERSIST_INTERVAL: Duration = Duration::from_secs(60 * 10); // 10分钟
-const EXPIRY_DURATION: Duration = Duration::from_secs(60 * 60 * 24 * 7); // 7天（1周）
+pub const DEFAULT_EXPIRY_DURATION: Duration = Duration::from_secs(60 * 60 * 24 * 7); // 7天（1周）
 
 type SharedStore<K, V> = Arc<RwLock<KvStore<K, V>>>;
 
+/// 一次持久化的条目数、序列化（含压缩）和写入耗时及文件大小
+#[derive(Debug, Clone, Copy)]
+pub struct PersistStats {
+    pub entries: usize,
+    pub serialize: Duration,
+    pub write: Duration,
+    pub bytes: usize,
+}
+
+/// 持久化文件头：`AKKV <版本> <校验和>[ gzip]\n`，其后为序列化数据（带 gzip 标记时为压缩后的数据）
+/// 条目结构发生不兼容变化时需要递增 FILE_VERSION，旧文件会被隔离而不是被错误解析
+const FILE_MAGIC: &str = "AKKV";
+const FILE_VERSION: u32 = 1;
+const GZIP_MARKER: &str = "gzip";
+
+/// FNV-1a 64位校验和，用于发现截断或损坏的持久化文件
+fn checksum(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// 校验文件头并返回（解压后的）数据部分；没有文件头的旧文件（包括手工编辑后删除了文件头的JSON）原样返回
+fn verify_header(buffer: &[u8]) -> Result<std::borrow::Cow<'_, [u8]>, String> {
+    if !buffer.starts_with(FILE_MAGIC.as_bytes()) {
+        return Ok(buffer.into());
+    }
+    let header_end = buffer.iter().position(|b| *b == b'\n').ok_or("文件头不完整")?;
+    let header = std::str::from_utf8(&buffer[..header_end]).map_err(|_| "文件头不是有效的UTF-8")?;
+    let payload = &buffer[header_end + 1..];
+    let mut fields = header.split(' ').skip(1);
+    let version: u32 = fields.next().and_then(|v| v.parse().ok()).ok_or("文件头缺少版本")?;
+    if version != FILE_VERSION {
+        return Err(format!("文件版本 {} 与当前版本 {} 不兼容", version, FILE_VERSION));
+    }
+    let expected = fields.next().and_then(|c| u64::from_str_radix(c, 16).ok()).ok_or("文件头缺少校验和")?;
+    if checksum(payload) != expected {
+        return Err("校验和不匹配，文件可能已损坏".to_string());
+    }
+    match fields.next() {
+        None => Ok(payload.into()),
+        Some(GZIP_MARKER) => {
+            let mut decompressed = Vec::new();
+            flate2::read::GzDecoder::new(payload)
+                .read_to_end(&mut decompressed)
+                .map_err(|e| format!("解压KV存储数据失败: {}", e))?;
+            Ok(decompressed.into())
+        }
+        Some(other) => Err(format!("不支持的压缩方式: {}", other)),
+    }
+}
+
+fn gzip(bytes: &[u8]) -> Result<Vec<u8>, String> {
+    let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+    encoder.write_all(bytes)
+        .and_then(|_| encoder.finish())
+        .map_err(|e| format!("压缩KV存储数据失败: {}", e))
+}
+
+/// 持久化文件的序列化格式
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PersistFormat {
+    /// 紧凑的二进制格式
+    #[default]
+    Bincode,
+    /// 便于查看和手工编辑的JSON格式（键必须能序列化为字符串）
+    Json,
+}
+
+impl PersistFormat {
+    fn serialize<T: Serialize>(self, value: &T) -> Result<Vec<u8>, String> {
+        match self {
+            PersistFormat::Bincode => bincode::serialize(value).map_err(|e| e.to_string()),
+            PersistFormat::Json => serde_json::to_vec_pretty(value).map_err(|e| e.to_string()),
+        }
+    }
+
+    fn deserialize<T: for<'de> Deserialize<'de>>(self, bytes: &[u8]) -> Result<T, String> {
+        match self {
+            PersistFormat::Bincode => bincode::deserialize(bytes).map_err(|e| e.to_string()),
+            PersistFormat::Json => serde_json::from_slice(bytes).map_err(|e| e.to_string()),
+        }
+    }
+
+    fn other(self) -> Self {
+        match self {
+            PersistFormat::Bincode => PersistFormat::Json,
+            PersistFormat::Json => PersistFormat::Bincode,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 struct Entry<V> {
     value: V,
     expires_at: u64,
     size_bytes: usize,
+    // 写入时间；ttl 带随机抖动时无法由过期时间反推，旧文件中的条目为0
+    #[serde(default)]
+    written_at: u64,
+}
+
+/// 增加 written_at 之前的条目格式，bincode不支持缺省字段，读取旧文件时使用
+#[derive(Deserialize)]
+struct LegacyEntry<V> {
+    value: V,
+    expires_at: u64,
+    size_bytes: usize,
+}
+
+#[derive(Deserialize)]
+struct LegacyStoreData<K: Hash + Eq, V> {
+    entries: HashMap<K, LegacyEntry<V>>,
+    created_at: u64,
+}
+
+/// 内存不足时移出的条目
+#[derive(Debug)]
+pub struct EvictedEntry<K, V> {
+    pub key: K,
+    pub value: V,
+    /// 硬过期时间戳
+    pub expires_at: u64,
+    pub written_at: u64,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -36,11 +157,29 @@ where
 #[allow(dead_code)]
 pub struct KvStore<K, V> 
 where 
-    K: Serialize + for<'de> Deserialize<'de> + Clone + Hash + Eq,
+    K: Serialize + for<'de> Deserialize<'de> + Clone + Hash + Eq + Ord,
     V: Serialize + for<'de> Deserialize<'de> + Clone,
 {
     entries: HashMap<K, Entry<V>>,
+    // 按过期时间排序的索引，清理时只需扫描已过期的前缀部分
+    expiry_index: BTreeSet<(u64, K)>,
     current_size_bytes: usize,
+    ttl: Duration,
+    // 软过期（ttl）之后仍保留的时间，期间条目可读但标记为过期
+    stale_window: Duration,
+    // 硬过期之后仍保留的时间，只能通过 get_expired 读取
+    error_grace: Duration,
+    // 写入时在 ttl 上随机增加 0..=ttl_jitter，避免同时写入的条目同时过期
+    ttl_jitter: Duration,
+    memory_limit: usize,
+    // 内存不足时移出最早过期的条目而不是拒绝写入，移出的条目暂存在 evicted 中
+    evict_when_full: bool,
+    evicted: Vec<EvictedEntry<K, V>>,
+    // 持久化总耗时超过该值时输出警告
+    slow_persist_threshold: Option<Duration>,
+    format: PersistFormat,
+    // 持久化时是否gzip压缩，读取时按文件头自动识别
+    compress: bool,
     file_path: PathBuf,
     last_persist: Instant,
 }
@@ -48,7 +187,7 @@ where
 #[allow(dead_code)]
 impl<K, V> KvStore<K, V> 
 where 
-    K: Serialize + for<'de> Deserialize<'de> + Clone + Hash + Eq + Send + Sync + 'static,
+    K: Serialize + for<'de> Deserialize<'de> + Clone + Hash + Eq + Ord + Send + Sync + 'static,
     V: Serialize + for<'de> Deserialize<'de> + Clone + Send + Sync + 'static,
 {
     pub fn new<P: AsRef<Path>>(file_path: P) -> Self {
@@ -56,30 +195,104 @@ where
         
         Self {
             entries: HashMap::new(),
+            expiry_index: BTreeSet::new(),
             current_size_bytes: 0,
+            ttl: DEFAULT_EXPIRY_DURATION,
+            stale_window: Duration::ZERO,
+            error_grace: Duration::ZERO,
+            ttl_jitter: Duration::ZERO,
+            memory_limit: MAX_MEMORY_BYTES,
+            evict_when_full: false,
+            evicted: Vec::new(),
+            slow_persist_threshold: None,
+            format: PersistFormat::default(),
+            compress: false,
             file_path: path,
             last_persist: Instant::now(),
         }
     }
     
+    /// 设置新写入条目的存活时间
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+    
+    pub fn ttl(&self) -> Duration {
+        self.ttl
+    }
+    
+    /// 条目在软过期（ttl）后继续保留 stale_window，到达硬过期才被删除
+    pub fn set_stale_window(&mut self, stale_window: Duration) {
+        self.stale_window = stale_window;
+    }
+    
+    /// 持久化（序列化加写入）耗时超过 threshold 时输出警告
+    pub fn set_slow_persist_threshold(&mut self, threshold: Option<Duration>) {
+        self.slow_persist_threshold = threshold;
+    }
+    
+    /// 条目在硬过期后再保留 error_grace，供数据源全部不可用时兜底
+    pub fn set_error_grace(&mut self, error_grace: Duration) {
+        self.error_grace = error_grace;
+    }
+    
+    pub fn error_grace(&self) -> Duration {
+        self.error_grace
+    }
+    
+    /// 每个条目的存活时间随机延长 0..=ttl_jitter（秒级），分散热门条目的过期时刻
+    pub fn set_ttl_jitter(&mut self, ttl_jitter: Duration) {
+        self.ttl_jitter = ttl_jitter;
+    }
+    
+    /// 内存占用上限（估算字节数），默认1024MB
+    pub fn set_memory_limit(&mut self, memory_limit: usize) {
+        self.memory_limit = memory_limit;
+    }
+    
+    /// 达到内存上限时移出最早过期的条目（通过 take_evicted 取走），而不是拒绝写入
+    pub fn set_evict_when_full(&mut self, evict_when_full: bool) {
+        self.evict_when_full = evict_when_full;
+    }
+    
+    /// 设置持久化文件的序列化格式
+    pub fn with_format(mut self, format: PersistFormat) -> Self {
+        self.format = format;
+        self
+    }
+    
+    /// 持久化时gzip压缩序列化数据；读取时无论此设置如何都能识别压缩和未压缩的文件
+    pub fn set_compression(&mut self, compress: bool) {
+        self.compress = compress;
+    }
+    
     pub fn create_shared<P: AsRef<Path>>(file_path: P) -> SharedStore<K, V> {
         let store = Self::new(file_path);
         Arc::new(RwLock::new(store))
     }
     
-    pub async fn start_background_tasks(store: SharedStore<K, V>) {
-        let persist_store = store.clone();
-        let cleanup_store = store.clone();
-        
-        // 加载持久化数据
-        {
-            let mut store_lock = store.write().await;
-            if let Err(e) = store_lock.load_from_disk() {
-                error!("从磁盘加载KV存储失败: {}", e);
-            } else {
-                info!("从磁盘加载KV存储成功，当前条目数: {}", store_lock.entries.len());
-            }
+    /// 加载持久化数据并启动后台任务
+    pub async fn start_background_tasks(store: SharedStore<K, V>, cleanup_interval: Duration) {
+        Self::warm_up(&store).await;
+        Self::spawn_background_tasks(store, cleanup_interval);
+    }
+    
+    /// 从磁盘加载持久化数据，返回加载后的条目数
+    pub async fn warm_up(store: &SharedStore<K, V>) -> usize {
+        let mut store_lock = store.write().await;
+        if let Err(e) = store_lock.load_from_disk() {
+            error!("从磁盘加载KV存储失败: {}", e);
+        } else {
+            info!("从磁盘加载KV存储成功，当前条目数: {}", store_lock.entries.len());
         }
+        store_lock.entries.len()
+    }
+    
+    /// 启动定期持久化和过期清理任务
+    pub fn spawn_background_tasks(store: SharedStore<K, V>, cleanup_interval: Duration) {
+        let persist_store = store.clone();
+        let cleanup_store = store;
         
         // 启动定期持久化任务
         tokio::spawn(async move {
@@ -87,17 +300,16 @@ where
             loop {
                 interval.tick().await;
                 let mut store = persist_store.write().await;
-                if let Err(e) = store.persist_to_disk() {
-                    error!("持久化KV存储到磁盘失败: {}", e);
-                } else {
-                    info!("KV存储已持久化到磁盘，当前条目数: {}", store.entries.len());
+                match store.persist_to_disk() {
+                    Ok(stats) => store.log_persist(&stats),
+                    Err(e) => error!("持久化KV存储到磁盘失败: {}", e),
                 }
             }
         });
         
         // 启动过期数据清理任务
         tokio::spawn(async move {
-            let mut interval = time::interval(Duration::from_secs(60)); // 每分钟检查一次过期数据
+            let mut interval = time::interval(cleanup_interval);
             loop {
                 interval.tick().await;
                 let mut store = cleanup_store.write().await;
@@ -109,6 +321,59 @@ where
         });
     }
     
+    /// 获取未过期的值及其过期时间戳（秒）
+    pub fn get_with_expiry(&self, key: &K) -> Option<(V, u64)> {
+        let entry = self.entries.get(key)?;
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        if entry.expires_at > now {
+            Some((entry.value.clone(), entry.expires_at))
+        } else {
+            None
+        }
+    }
+    
+    /// 获取已硬过期但仍在 error_grace 内的值及其写入时间（秒）
+    pub fn get_expired(&self, key: &K) -> Option<(V, u64)> {
+        let entry = self.entries.get(key)?;
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let retained_until = entry.expires_at.saturating_add(self.error_grace.as_secs());
+        (entry.expires_at <= now && now < retained_until).then(|| (entry.value.clone(), self.written_at(entry)))
+    }
+    
+    /// 获取未硬过期的条目，返回值、软过期时间、是否已软过期及写入时间
+    pub fn get_with_freshness(&self, key: &K) -> Option<(V, u64, bool, u64)> {
+        let (value, expires_at) = self.get_with_expiry(key)?;
+        let soft_expires_at = expires_at.saturating_sub(self.stale_window.as_secs());
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let written_at = self.entries.get(key).map_or(0, |entry| self.written_at(entry));
+        Some((value, soft_expires_at, now >= soft_expires_at, written_at))
+    }
+    
+    fn written_at(&self, entry: &Entry<V>) -> u64 {
+        match entry.written_at {
+            // 旧文件中的条目没有写入时间，当时尚无抖动，可由过期时间反推
+            0 => entry.expires_at.saturating_sub(self.stale_window.as_secs()).saturating_sub(self.ttl.as_secs()),
+            written_at => written_at,
+        }
+    }
+    
+    /// 按软过期时间从早到晚遍历条目，返回键及软过期时间戳（秒）；包含已软过期但仍在过期窗口内的条目
+    pub fn iter_by_expiry(&self) -> impl Iterator<Item = (&K, u64)> + '_ {
+        let stale_window = self.stale_window.as_secs();
+        self.expiry_index
+            .iter()
+            .map(move |(expires_at, key)| (key, expires_at.saturating_sub(stale_window)))
+    }
+    
     pub fn get(&self, key: &K) -> Option<V> {
         if let Some(entry) = self.entries.get(key) {
             let now = SystemTime::now()
@@ -124,6 +389,21 @@ where
     }
     
     pub fn set(&mut self, key: K, value: V) -> Result<(), String> {
+        // 计算硬过期时间
+        let jitter = match self.ttl_jitter.as_secs() {
+            0 => 0,
+            max => rand::thread_rng().gen_range(0..=max),
+        };
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let expires_at = now + self.ttl.as_secs() + jitter + self.stale_window.as_secs();
+        self.set_with_expiry(key, value, expires_at, now)
+    }
+    
+    /// 按指定的硬过期时间戳和写入时间写入，用于从二级缓存取回的条目保留原有的时间
+    pub fn set_with_expiry(&mut self, key: K, value: V, expires_at: u64, written_at: u64) -> Result<(), String> {
         // 估算条目大小
         let entry_size = self.estimate_size(&key, &value)?;
         
@@ -131,36 +411,38 @@ where
         let old_size = self.entries.get(&key)
             .map(|entry| entry.size_bytes)
             .unwrap_or(0);
-            
-        let new_total_size = self.current_size_bytes - old_size + entry_size;
         
-        if new_total_size > MAX_MEMORY_BYTES {
+        if self.evict_when_full && self.current_size_bytes - old_size + entry_size > self.memory_limit {
+            self.evict_for(&key, entry_size.saturating_sub(old_size));
+        }
+        let new_total_size = self.current_size_bytes - old_size + entry_size;
+        if new_total_size > self.memory_limit {
             return Err("超出内存限制，无法添加新条目".to_string());
         }
-        
-        // 计算过期时间
-        let expires_at = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap_or_default()
-            .as_secs() + EXPIRY_DURATION.as_secs();
             
         // 创建并存储条目
         let entry = Entry {
             value,
             expires_at,
             size_bytes: entry_size,
+            written_at,
         };
         
         // 更新当前大小
         self.current_size_bytes = new_total_size;
         
-        // 存储条目
-        self.entries.insert(key, entry);
+        // 存储条目并更新过期索引
+        // 先移除旧索引再插入新索引：同一秒内重写时两者相同，顺序颠倒会丢失索引
+        if let Some(old_entry) = self.entries.insert(key.clone(), entry) {
+            self.expiry_index.remove(&(old_entry.expires_at, key.clone()));
+        }
+        self.expiry_index.insert((expires_at, key));
         
         // 检查是否需要持久化
         if self.last_persist.elapsed() >= PERSIST_INTERVAL {
-            if let Err(e) = self.persist_to_disk() {
-                error!("自动持久化KV存储失败: {}", e);
+            match self.persist_to_disk() {
+                Ok(stats) => self.log_persist(&stats),
+                Err(e) => error!("自动持久化KV存储失败: {}", e),
             }
             self.last_persist = Instant::now();
         }
@@ -168,9 +450,30 @@ where
         Ok(())
     }
     
+    /// 按过期时间从早到晚移出条目，直到能再容纳 needed 字节；移出的条目暂存，由 take_evicted 取走
+    fn evict_for(&mut self, keep: &K, needed: usize) {
+        while self.current_size_bytes + needed > self.memory_limit {
+            let Some((_, key)) = self.expiry_index.iter().find(|(_, key)| key != keep).cloned() else {
+                break;
+            };
+            let Some(entry) = self.entries.remove(&key) else {
+                break;
+            };
+            self.current_size_bytes -= entry.size_bytes;
+            self.expiry_index.remove(&(entry.expires_at, key.clone()));
+            self.evicted.push(EvictedEntry { key, value: entry.value, expires_at: entry.expires_at, written_at: entry.written_at });
+        }
+    }
+    
+    /// 取走因内存不足被移出的条目
+    pub fn take_evicted(&mut self) -> Vec<EvictedEntry<K, V>> {
+        std::mem::take(&mut self.evicted)
+    }
+    
     pub fn remove(&mut self, key: &K) -> Option<V> {
         if let Some(entry) = self.entries.remove(key) {
             self.current_size_bytes -= entry.size_bytes;
+            self.expiry_index.remove(&(entry.expires_at, key.clone()));
             return Some(entry.value);
         }
         None
@@ -202,29 +505,52 @@ where
         Ok(key_bytes.len() + value_bytes.len() + overhead)
     }
     
-    fn cleanup_expired(&mut self) -> usize {
+    /// 清理所有已过期（含 error_grace）的条目，返回清理数量
+    pub fn cleanup_expired(&mut self) -> usize {
         let now = SystemTime::now()
             .duration_since(UNIX_EPOCH)
             .unwrap_or_default()
             .as_secs();
+        let error_grace = self.error_grace.as_secs();
             
-        let expired_keys: Vec<K> = self.entries.iter()
-            .filter(|(_, entry)| entry.expires_at <= now)
-            .map(|(key, _)| key.clone())
-            .collect();
-            
-        let count = expired_keys.len();
-        
-        for key in expired_keys {
+        let mut count = 0;
+        while let Some((expires_at, _)) = self.expiry_index.first() {
+            if expires_at.saturating_add(error_grace) > now {
+                break;
+            }
+            let Some((_, key)) = self.expiry_index.pop_first() else {
+                break;
+            };
             if let Some(entry) = self.entries.remove(&key) {
                 self.current_size_bytes -= entry.size_bytes;
+                count += 1;
             }
         }
         
         count
     }
     
-    fn persist_to_disk(&mut self) -> Result<(), String> {
+    /// 持久化期间持有写锁，耗时过长时提示调整持久化间隔或拆分缓存
+    fn log_persist(&self, stats: &PersistStats) {
+        info!(
+            entries = stats.entries,
+            serialize_ms = stats.serialize.as_millis() as u64,
+            write_ms = stats.write.as_millis() as u64,
+            bytes = stats.bytes,
+            "KV存储已持久化到磁盘: {}", self.file_path.display()
+        );
+        let total = stats.serialize + stats.write;
+        if let Some(threshold) = self.slow_persist_threshold
+            && total > threshold {
+            warn!(
+                "KV存储持久化耗时 {:?}，超过阈值 {:?}（{} 条，{} 字节），期间查询被阻塞",
+                total, threshold, stats.entries, stats.bytes
+            );
+        }
+    }
+    
+    fn persist_to_disk(&mut self) -> Result<PersistStats, String> {
+        let serialize_start = Instant::now();
         // 创建数据结构
         let store_data = StoreData {
             entries: self.entries.clone(),
@@ -235,8 +561,15 @@ where
         };
         
         // 序列化数据
-        let serialized = bincode::serialize(&store_data)
+        let serialized = self.format.serialize(&store_data)
             .map_err(|e| format!("序列化KV存储失败: {}", e))?;
+        let (payload, marker) = if self.compress {
+            (gzip(&serialized)?, format!(" {}", GZIP_MARKER))
+        } else {
+            (serialized, String::new())
+        };
+        let serialize = serialize_start.elapsed();
+        let write_start = Instant::now();
             
         // 确保目录存在
         if let Some(parent) = self.file_path.parent() {
@@ -253,7 +586,9 @@ where
             .open(&temp_path)
             .map_err(|e| format!("打开临时KV存储文件失败: {}", e))?;
             
-        file.write_all(&serialized)
+        let header = format!("{} {} {:016x}{}\n", FILE_MAGIC, FILE_VERSION, checksum(&payload), marker);
+        file.write_all(header.as_bytes())
+            .and_then(|_| file.write_all(&payload))
             .map_err(|e| format!("写入KV存储数据失败: {}", e))?;
             
         file.flush()
@@ -265,7 +600,12 @@ where
             
         self.last_persist = Instant::now();
         
-        Ok(())
+        Ok(PersistStats {
+            entries: store_data.entries.len(),
+            serialize,
+            write: write_start.elapsed(),
+            bytes: header.len() + payload.len(),
+        })
     }
     
     fn load_from_disk(&mut self) -> Result<(), String> {
@@ -282,23 +622,27 @@ where
         file.read_to_end(&mut buffer)
             .map_err(|e| format!("读取KV存储文件失败: {}", e))?;
             
-        // 反序列化数据
-        let store_data: StoreData<K, V> = bincode::deserialize(&buffer)
-            .map_err(|e| format!("反序列化KV存储数据失败: {}", e))?;
+        // 校验并反序列化数据，失败时隔离文件，以空存储启动
+        let store_data = match Self::decode(self.format, &buffer) {
+            Ok(data) => data,
+            Err(e) => return Err(self.quarantine(e)),
+        };
             
         // 清除当前数据
         self.entries.clear();
+        self.expiry_index.clear();
         self.current_size_bytes = 0;
         
-        // 加载数据，跳过过期条目
+        // 加载数据，跳过过期（含 error_grace）条目
         let now = SystemTime::now()
             .duration_since(UNIX_EPOCH)
             .unwrap_or_default()
             .as_secs();
             
         for (key, entry) in store_data.entries {
-            if entry.expires_at > now {
+            if entry.expires_at.saturating_add(self.error_grace.as_secs()) > now {
                 self.current_size_bytes += entry.size_bytes;
+                self.expiry_index.insert((entry.expires_at, key.clone()));
                 self.entries.insert(key, entry);
             }
         }
@@ -306,6 +650,43 @@ where
         Ok(())
     }
     
+    /// 切换格式后首次启动时，文件仍是另一种格式，尝试按另一种格式读取
+    fn decode(format: PersistFormat, buffer: &[u8]) -> Result<StoreData<K, V>, String> {
+        let payload = verify_header(buffer)?;
+        match format.deserialize(&payload) {
+            Ok(data) => Ok(data),
+            Err(e) => {
+                let fallback = format.other();
+                if let Ok(data) = fallback.deserialize(&payload) {
+                    info!("KV存储文件为 {:?} 格式，下次持久化时转换为 {:?} 格式", fallback, format);
+                    return Ok(data);
+                }
+                let legacy: LegacyStoreData<K, V> = PersistFormat::Bincode.deserialize(&payload)
+                    .map_err(|_| format!("反序列化KV存储数据失败: {}", e))?;
+                info!("KV存储文件为旧版bincode格式，下次持久化时转换");
+                let entries = legacy.entries
+                    .into_iter()
+                    .map(|(key, entry)| {
+                        let entry = Entry { value: entry.value, expires_at: entry.expires_at, size_bytes: entry.size_bytes, written_at: 0 };
+                        (key, entry)
+                    })
+                    .collect();
+                Ok(StoreData { entries, created_at: legacy.created_at })
+            }
+        }
+    }
+    
+    /// 将无法加载的文件重命名为 .corrupt 保留现场，返回包含文件路径的错误信息
+    fn quarantine(&self, reason: String) -> String {
+        let mut corrupt_path = self.file_path.clone().into_os_string();
+        corrupt_path.push(".corrupt");
+        let corrupt_path = PathBuf::from(corrupt_path);
+        match std::fs::rename(&self.file_path, &corrupt_path) {
+            Ok(()) => format!("{}，已将文件移至 {} 以便排查", reason, corrupt_path.display()),
+            Err(e) => format!("{}，隔离文件 {} 失败: {}", reason, self.file_path.display(), e),
+        }
+    }
+    
     pub fn len(&self) -> usize {
         self.entries.len()
     }
@@ -321,4 +702,96 @@ where
     pub fn memory_usage_mb(&self) -> f64 {
         self.current_size_bytes as f64 / (1024.0 * 1024.0)
     }
-} 
\ No newline at end of file
+} 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json_persistence_round_trips_and_reads_bincode_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("store.bin");
+
+        let mut store: KvStore<String, u32> = KvStore::new(&path);
+        store.set("a".to_string(), 1).unwrap();
+        store.persist_to_disk().unwrap();
+
+        // 按JSON格式读取旧的bincode文件，随后以JSON格式写回
+        let mut store: KvStore<String, u32> = KvStore::new(&path).with_format(PersistFormat::Json);
+        store.load_from_disk().unwrap();
+        assert_eq!(store.get(&"a".to_string()), Some(1));
+        store.persist_to_disk().unwrap();
+        let raw = std::fs::read_to_string(&path).unwrap();
+        assert!(raw.contains("\"a\""));
+
+        let mut store: KvStore<String, u32> = KvStore::new(&path).with_format(PersistFormat::Json);
+        store.load_from_disk().unwrap();
+        assert_eq!(store.get(&"a".to_string()), Some(1));
+    }
+
+    #[test]
+    fn ttl_jitter_extends_expiry_within_range() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut store: KvStore<String, u32> = KvStore::new(dir.path().join("store.bin")).with_ttl(Duration::from_secs(60));
+        store.set_ttl_jitter(Duration::from_secs(30));
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+        for i in 0..50 {
+            store.set(i.to_string(), i).unwrap();
+        }
+        let expiries: BTreeSet<u64> = store.entries.values().map(|entry| entry.expires_at).collect();
+        assert!(expiries.iter().all(|expires_at| (now + 60..=now + 91).contains(expires_at)));
+        assert!(expiries.len() > 1);
+    }
+
+    #[test]
+    fn rewriting_a_key_in_the_same_second_keeps_it_indexed() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut store: KvStore<String, u32> = KvStore::new(dir.path().join("store.bin")).with_ttl(Duration::ZERO);
+        store.set("a".to_string(), 1).unwrap();
+        store.set("a".to_string(), 2).unwrap();
+        assert_eq!(store.cleanup_expired(), 1);
+        assert!(store.is_empty());
+        assert_eq!(store.memory_usage(), 0);
+    }
+
+    #[test]
+    fn corrupt_file_is_quarantined() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("store.bin");
+
+        let mut store: KvStore<String, u32> = KvStore::new(&path);
+        store.set("a".to_string(), 1).unwrap();
+        store.persist_to_disk().unwrap();
+        let mut bytes = std::fs::read(&path).unwrap();
+        let last = bytes.len() - 1;
+        bytes[last] ^= 0xff;
+        std::fs::write(&path, bytes).unwrap();
+
+        let mut store: KvStore<String, u32> = KvStore::new(&path);
+        let err = store.load_from_disk().unwrap_err();
+        assert!(err.contains("store.bin.corrupt"), "{}", err);
+        assert!(store.is_empty());
+        assert!(!path.exists());
+        assert!(dir.path().join("store.bin.corrupt").exists());
+    }
+
+    #[test]
+    fn compressed_files_are_detected_on_load() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("store.bin");
+
+        let mut store: KvStore<String, String> = KvStore::new(&path);
+        store.set_compression(true);
+        store.set("a".to_string(), "x".repeat(10_000)).unwrap();
+        let stats = store.persist_to_disk().unwrap();
+        let raw = std::fs::read(&path).unwrap();
+        assert!(raw.len() < 1_000);
+        assert_eq!(stats.bytes, raw.len());
+        assert_eq!(stats.entries, 1);
+
+        // 未开启压缩的实例也能读取压缩文件
+        let mut store: KvStore<String, String> = KvStore::new(&path);
+        store.load_from_disk().unwrap();
+        assert_eq!(store.get(&"a".to_string()).map(|v| v.len()), Some(10_000));
+    }
+}
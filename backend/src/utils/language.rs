@@ -0,0 +1,80 @@
+use std::collections::HashMap;
+
+/// 解析 Accept-Language 请求头，按权重从高到低返回语言列表（忽略 * 和 q=0）
+pub fn parse_accept_language(header: &str) -> Vec<String> {
+    let mut weighted: Vec<(String, f32)> = header
+        .split(',')
+        .filter_map(|part| {
+            let mut pieces = part.split(';');
+            let tag = pieces.next()?.trim();
+            if tag.is_empty() || tag == "*" {
+                return None;
+            }
+            let quality = pieces
+                .find_map(|p| p.trim().strip_prefix("q="))
+                .and_then(|q| q.trim().parse::<f32>().ok())
+                .unwrap_or(1.0);
+            (quality > 0.0).then(|| (tag.to_string(), quality))
+        })
+        .collect();
+    // 稳定排序，权重相同时保持请求头中的顺序
+    weighted.sort_by(|a, b| b.1.total_cmp(&a.1));
+    weighted.into_iter().map(|(tag, _)| tag).collect()
+}
+
+/// 解析 ?lang= 参数（逗号分隔的语言列表）
+pub fn parse_lang_param(param: &str) -> Vec<String> {
+    param
+        .split(',')
+        .map(|l| l.trim())
+        .filter(|l| !l.is_empty())
+        .map(|l| l.to_string())
+        .collect()
+}
+
+/// 按语言优先级从MaxMind名称表中选取名称；完整标签未命中时尝试主语言（如 ja-JP → ja，zh → zh-CN）
+pub fn pick_name(names: &HashMap<String, String>, languages: &[String]) -> Option<String> {
+    for language in languages {
+        if let Some(name) = names.get(language) {
+            return Some(name.clone());
+        }
+        let primary = language.split('-').next().unwrap_or(language);
+        if let Some(name) = names.get(primary) {
+            return Some(name.clone());
+        }
+        let mut variants: Vec<_> = names
+            .iter()
+            .filter(|(key, _)| key.split('-').next() == Some(primary))
+            .collect();
+        variants.sort();
+        if let Some((_, name)) = variants.first() {
+            return Some((*name).clone());
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accept_language_is_ordered_by_weight() {
+        assert_eq!(
+            parse_accept_language("en;q=0.8, ja, *;q=0.1, fr;q=0"),
+            vec!["ja".to_string(), "en".to_string()]
+        );
+    }
+
+    #[test]
+    fn pick_name_falls_back_through_languages() {
+        let names: HashMap<String, String> = [("en", "Tokyo"), ("ja", "東京"), ("zh-CN", "东京")]
+            .into_iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        assert_eq!(pick_name(&names, &["ja-JP".to_string()]), Some("東京".to_string()));
+        assert_eq!(pick_name(&names, &["zh".to_string()]), Some("东京".to_string()));
+        assert_eq!(pick_name(&names, &["ko".to_string(), "en".to_string()]), Some("Tokyo".to_string()));
+        assert_eq!(pick_name(&names, &["ko".to_string()]), None);
+    }
+}
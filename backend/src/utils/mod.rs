@@ -1,6 +1,25 @@
 pub mod kv_store;
+pub mod disk_tier;
 pub mod ip_cache;
 pub mod whois_client;
+pub mod rdap_client;
 pub mod bgptools_client;
 pub mod rpki_client;
-pub mod bgp_api_client; 
\ No newline at end of file
+pub mod bgp_api_client;
+pub mod upstream;
+pub mod privacy;
+pub mod risk;
+pub mod dnsbl_client;
+pub mod language;
+pub mod ripestat_client;
+pub mod distribution;
+pub mod query_history;
+pub mod trace_context;
+pub mod webhook;
+pub mod eui64;
+pub mod country;
+pub mod redis_cache;
+pub mod timezone;
+pub mod dns_cache;
+pub mod api_keys;
+pub mod publisher;
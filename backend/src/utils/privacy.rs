@@ -0,0 +1,101 @@
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::sync::RwLock;
+use std::sync::atomic::{AtomicBool, Ordering};
+use hmac::{Hmac, Mac};
+use ipnet::IpNet;
+use sha2::Sha256;
+use crate::config::PrivacyConfig;
+
+static ANONYMIZE_LOGS: AtomicBool = AtomicBool::new(false);
+static ANONYMIZE_CACHE_KEYS: AtomicBool = AtomicBool::new(false);
+static CACHE_KEY_SECRET: RwLock<Option<Vec<u8>>> = RwLock::new(None);
+
+/// 应用隐私配置（启动及配置热更新时调用）
+pub fn configure(config: &PrivacyConfig) {
+    ANONYMIZE_LOGS.store(config.anonymize_logs, Ordering::Relaxed);
+    ANONYMIZE_CACHE_KEYS.store(config.anonymize_cache_keys, Ordering::Relaxed);
+    let secret = config.cache_key_secret.as_ref().map(|secret| secret.as_bytes().to_vec());
+    *CACHE_KEY_SECRET.write().unwrap_or_else(|e| e.into_inner()) = secret;
+}
+
+/// 截断IP地址：IPv4清零最后一个字节，IPv6清零后80位；CIDR只截断地址部分，无法解析的输入原样返回
+pub fn anonymize_ip(input: &str) -> String {
+    if let Ok(addr) = IpAddr::from_str(input) {
+        return truncate_addr(addr).to_string();
+    }
+    if let Ok(network) = IpNet::from_str(input) {
+        return format!("{}/{}", truncate_addr(network.addr()), network.prefix_len());
+    }
+    input.to_string()
+}
+
+fn truncate_addr(addr: IpAddr) -> IpAddr {
+    match addr {
+        IpAddr::V4(v4) => {
+            let mut octets = v4.octets();
+            octets[3] = 0;
+            IpAddr::from(octets)
+        }
+        IpAddr::V6(v6) => {
+            let bits = u128::from(v6) & !((1u128 << 80) - 1);
+            IpAddr::from(bits.to_be_bytes())
+        }
+    }
+}
+
+/// 返回写入日志时使用的IP表示
+pub fn log_ip(ip: &str) -> String {
+    if ANONYMIZE_LOGS.load(Ordering::Relaxed) {
+        anonymize_ip(ip)
+    } else {
+        ip.to_string()
+    }
+}
+
+/// 返回缓存键使用的IP表示，配置了密钥时为HMAC-SHA256摘要
+pub fn cache_key(ip: &str) -> String {
+    let key = if ANONYMIZE_CACHE_KEYS.load(Ordering::Relaxed) {
+        anonymize_ip(ip)
+    } else {
+        ip.to_string()
+    };
+    match CACHE_KEY_SECRET.read().unwrap_or_else(|e| e.into_inner()).as_deref() {
+        Some(secret) => hash_key(secret, &key),
+        None => key,
+    }
+}
+
+/// 缓存键是否为摘要（此时缓存条目中也不应保存明文IP）
+pub fn hashes_cache_keys() -> bool {
+    CACHE_KEY_SECRET.read().unwrap_or_else(|e| e.into_inner()).is_some()
+}
+
+fn hash_key(secret: &[u8], key: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("HMAC接受任意长度的密钥");
+    mac.update(key.as_bytes());
+    hex::encode(mac.finalize().into_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn anonymize_truncates_addresses_and_cidrs() {
+        assert_eq!(anonymize_ip("203.0.113.77"), "203.0.113.0");
+        assert_eq!(anonymize_ip("2001:db8:1234:5678:9abc::1"), "2001:db8:1234::");
+        assert_eq!(anonymize_ip("198.51.100.10/32"), "198.51.100.0/32");
+        assert_eq!(anonymize_ip("not-an-ip"), "not-an-ip");
+    }
+
+    #[test]
+    fn hashed_keys_depend_on_secret_and_hide_the_address() {
+        let key = hash_key(b"secret", "203.0.113.77");
+        assert_eq!(key.len(), 64);
+        assert!(!key.contains("203.0.113"));
+        assert_eq!(key, hash_key(b"secret", "203.0.113.77"));
+        assert_ne!(key, hash_key(b"other", "203.0.113.77"));
+        assert_ne!(key, hash_key(b"secret", "203.0.113.78"));
+    }
+}
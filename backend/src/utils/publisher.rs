@@ -0,0 +1,156 @@
+use crate::config::PublisherConfig;
+use serde::Serialize;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio::sync::mpsc;
+use tracing::{info, warn};
+
+/// 连接失败后重连前的等待时间，之后每次翻倍直到上限
+const RECONNECT_BASE_DELAY: Duration = Duration::from_millis(500);
+const RECONNECT_MAX_DELAY: Duration = Duration::from_secs(30);
+
+/// 建立连接和发送的超时
+const IO_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// 将查询结果发布到NATS主题，供SIEM、分析等下游消费
+/// 发布不阻塞查询：消息先进入有界队列，由后台任务发送；队列满时丢弃并计数，连接断开时保留未发送的消息并重连
+pub struct LookupPublisher {
+    sender: mpsc::Sender<Vec<u8>>,
+    dropped: Arc<AtomicU64>,
+}
+
+impl LookupPublisher {
+    /// 配置了 publisher.nats_url 时启动后台发送任务
+    pub fn start(config: &PublisherConfig) -> Option<Self> {
+        let address = config.nats_url.as_deref()?;
+        let address = address.trim_start_matches("nats://").to_string();
+        let (sender, receiver) = mpsc::channel(config.queue_size);
+        info!("查询结果将发布到 NATS {} 主题 {}", address, config.subject);
+        tokio::spawn(run(address, config.subject.clone(), receiver));
+        Some(Self { sender, dropped: Arc::new(AtomicU64::new(0)) })
+    }
+
+    /// 序列化并加入发送队列，不等待发送完成
+    pub fn publish<T: Serialize>(&self, message: &T) {
+        let payload = match serde_json::to_vec(message) {
+            Ok(payload) => payload,
+            Err(e) => {
+                warn!("序列化待发布的查询结果失败: {}", e);
+                return;
+            }
+        };
+        if self.sender.try_send(payload).is_err() {
+            let dropped = self.dropped.fetch_add(1, Ordering::Relaxed) + 1;
+            // 避免NATS长时间不可用时刷屏
+            if dropped.is_power_of_two() {
+                warn!("发布队列已满或已关闭，累计丢弃 {} 条查询结果", dropped);
+            }
+        }
+    }
+}
+
+/// 后台发送循环：连接、发送，出错后按指数退避重连；发送失败的消息在重连后重发
+async fn run(address: String, subject: String, mut receiver: mpsc::Receiver<Vec<u8>>) {
+    let mut pending: Option<Vec<u8>> = None;
+    let mut delay = RECONNECT_BASE_DELAY;
+    loop {
+        match connect(&address).await {
+            Ok(stream) => {
+                info!("已连接 NATS {}", address);
+                delay = RECONNECT_BASE_DELAY;
+                match serve(stream, &subject, &mut receiver, &mut pending).await {
+                    Ok(()) => return, // 发送队列已关闭
+                    Err(e) => warn!("NATS 连接中断: {}", e),
+                }
+            }
+            Err(e) => warn!("连接 NATS {} 失败: {}，{:?}后重试", address, e, delay),
+        }
+        tokio::time::sleep(delay).await;
+        delay = (delay * 2).min(RECONNECT_MAX_DELAY);
+    }
+}
+
+async fn connect(address: &str) -> Result<TcpStream, String> {
+    let mut stream = tokio::time::timeout(IO_TIMEOUT, TcpStream::connect(address))
+        .await
+        .map_err(|_| "连接超时".to_string())?
+        .map_err(|e| e.to_string())?;
+    // 服务器先发送INFO，不需要其中的内容；verbose关闭后发布成功不再回复+OK
+    stream
+        .write_all(b"CONNECT {\"verbose\":false,\"pedantic\":false,\"name\":\"akaere-ipapi\"}\r\n")
+        .await
+        .map_err(|e| e.to_string())?;
+    Ok(stream)
+}
+
+/// 在一个连接上持续发送队列中的消息并响应服务器的PING；队列关闭时返回Ok
+async fn serve(
+    stream: TcpStream,
+    subject: &str,
+    receiver: &mut mpsc::Receiver<Vec<u8>>,
+    pending: &mut Option<Vec<u8>>,
+) -> Result<(), String> {
+    let (read_half, mut write_half) = stream.into_split();
+    let mut lines = BufReader::new(read_half).lines();
+    loop {
+        if let Some(payload) = pending.as_ref() {
+            let mut frame = format!("PUB {} {}\r\n", subject, payload.len()).into_bytes();
+            frame.extend_from_slice(payload);
+            frame.extend_from_slice(b"\r\n");
+            tokio::time::timeout(IO_TIMEOUT, write_half.write_all(&frame))
+                .await
+                .map_err(|_| "发送超时".to_string())?
+                .map_err(|e| e.to_string())?;
+            *pending = None;
+        }
+        tokio::select! {
+            message = receiver.recv() => match message {
+                Some(payload) => *pending = Some(payload),
+                None => return Ok(()),
+            },
+            line = lines.next_line() => match line.map_err(|e| e.to_string())? {
+                Some(line) if line.starts_with("PING") => {
+                    write_half.write_all(b"PONG\r\n").await.map_err(|e| e.to_string())?;
+                }
+                Some(line) if line.starts_with("-ERR") => warn!("NATS 返回错误: {}", line),
+                Some(_) => {}
+                None => return Err("服务器关闭了连接".to_string()),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    #[tokio::test]
+    async fn results_are_published_to_the_subject() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let config = PublisherConfig {
+            nats_url: Some(format!("nats://{}", listener.local_addr().unwrap())),
+            subject: "ipapi.lookups".to_string(),
+            queue_size: 8,
+        };
+        let publisher = LookupPublisher::start(&config).unwrap();
+        publisher.publish(&serde_json::json!({ "ip": "1.1.1.1" }));
+
+        let (stream, _) = listener.accept().await.unwrap();
+        let (read_half, mut write_half) = stream.into_split();
+        write_half.write_all(b"INFO {}\r\nPING\r\n").await.unwrap();
+        let mut lines = BufReader::new(read_half).lines();
+        assert!(lines.next_line().await.unwrap().unwrap().starts_with("CONNECT "));
+        let mut received = Vec::new();
+        for _ in 0..3 {
+            received.push(lines.next_line().await.unwrap().unwrap());
+        }
+        // PONG 与 PUB 的先后取决于调度
+        received.sort();
+        let payload = r#"{"ip":"1.1.1.1"}"#;
+        assert_eq!(received, ["PONG".to_string(), format!("PUB ipapi.lookups {}", payload.len()), payload.to_string()]);
+    }
+}
@@ -0,0 +1,91 @@
+use serde::Serialize;
+use std::collections::{BTreeSet, HashMap};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+use super::privacy::log_ip;
+
+/// 按IP记录查询次数和首末查询时间，供滥用调查使用
+/// 容量有限，超出时淘汰最久未被查询的IP；开启 privacy.anonymize_logs 时按截断后的地址记录
+#[derive(Default)]
+pub struct QueryHistory {
+    state: Mutex<HistoryState>,
+}
+
+#[derive(Default)]
+struct HistoryState {
+    entries: HashMap<String, IpHistory>,
+    // 按最后查询时间排序的索引，淘汰时取第一项
+    recency_index: BTreeSet<(u64, String)>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct IpHistory {
+    /// 记录使用的地址（可能已截断）
+    pub ip: String,
+    pub count: u64,
+    /// 首次和最近一次查询的Unix时间戳
+    pub first_queried: u64,
+    pub last_queried: u64,
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+impl QueryHistory {
+    /// 记录一次查询，记录数超过 capacity 时淘汰最久未被查询的IP
+    pub fn record(&self, ip: &str, capacity: usize) {
+        self.record_at(ip, capacity, now_secs());
+    }
+
+    fn record_at(&self, ip: &str, capacity: usize, now: u64) {
+        let key = log_ip(ip);
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        let HistoryState { entries, recency_index } = &mut *state;
+        match entries.get_mut(&key) {
+            Some(entry) => {
+                recency_index.remove(&(entry.last_queried, key.clone()));
+                entry.count += 1;
+                entry.last_queried = now;
+            }
+            None => {
+                entries.insert(key.clone(), IpHistory { ip: key.clone(), count: 1, first_queried: now, last_queried: now });
+            }
+        }
+        recency_index.insert((now, key));
+        while entries.len() > capacity {
+            let Some((_, oldest)) = recency_index.pop_first() else {
+                break;
+            };
+            entries.remove(&oldest);
+        }
+    }
+
+    /// 查询IP的记录，地址按与记录时相同的方式截断
+    pub fn get(&self, ip: &str) -> Option<IpHistory> {
+        let state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        state.entries.get(&log_ip(ip)).cloned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_queries_and_evicts_least_recent() {
+        let history = QueryHistory::default();
+        history.record_at("192.0.2.1", 2, 100);
+        history.record_at("192.0.2.2", 2, 101);
+        history.record_at("192.0.2.1", 2, 102);
+        history.record_at("192.0.2.3", 2, 103);
+
+        let entry = history.get("192.0.2.1").unwrap();
+        assert_eq!((entry.count, entry.first_queried, entry.last_queried), (2, 100, 102));
+        assert!(history.get("192.0.2.2").is_none());
+        assert_eq!(history.get("192.0.2.3").unwrap().count, 1);
+    }
+}
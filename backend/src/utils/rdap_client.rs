@@ -0,0 +1,242 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use ipnet::IpNet;
+use serde_json::Value;
+use tracing::{debug, info};
+use super::privacy::log_ip;
+use super::trace_context::with_trace_context;
+use super::upstream::{http_client_builder, request_timeout};
+use super::whois_client::WhoisInfo;
+
+/// IANA RDAP引导注册表，列出各地址块的权威RDAP服务器
+const IPV4_BOOTSTRAP_URL: &str = "https://data.iana.org/rdap/ipv4.json";
+const IPV6_BOOTSTRAP_URL: &str = "https://data.iana.org/rdap/ipv6.json";
+
+/// 引导注册表很少变化，缓存一天
+const BOOTSTRAP_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+const RDAP_CONTENT_TYPE: &str = "application/rdap+json";
+
+/// 地址块及其RDAP服务器基础地址（以 / 结尾）
+type Bootstrap = Vec<(IpNet, String)>;
+
+struct CachedBootstrap {
+    fetched_at: Instant,
+    services: Bootstrap,
+}
+
+static IPV4_BOOTSTRAP: Mutex<Option<CachedBootstrap>> = Mutex::new(None);
+static IPV6_BOOTSTRAP: Mutex<Option<CachedBootstrap>> = Mutex::new(None);
+
+/// RDAP（RFC 7482）客户端：按IANA引导注册表找到权威服务器，将JSON响应映射为 WhoisInfo
+pub struct RdapClient;
+
+impl RdapClient {
+    /// 查询IP的注册信息，extra_fields 指定额外提取的RDAP顶层字段（如 status, type, parentHandle）
+    pub async fn lookup(ip: &str, extra_fields: &[String]) -> Result<WhoisInfo, String> {
+        let addr: IpAddr = ip.parse().map_err(|e| format!("无效的IP地址: {}", e))?;
+        let server = Self::find_server(addr).await?;
+        let url = format!("{}ip/{}", server, addr);
+        info!("RDAP 请求: {} (服务器: {})", log_ip(ip), server);
+
+        let client = http_client_builder()
+            .timeout(request_timeout(Duration::from_secs(15)))
+            .build()
+            .map_err(|e| format!("创建HTTP客户端失败: {}", e))?;
+        let request = client.get(&url).header(reqwest::header::ACCEPT, RDAP_CONTENT_TYPE);
+        let resp = with_trace_context(request)
+            .send()
+            .await
+            .map_err(|e| format!("RDAP请求失败: {}", e))?;
+        if !resp.status().is_success() {
+            return Err(format!("RDAP请求失败: 状态码 {}", resp.status()));
+        }
+        let raw = resp.text().await.map_err(|e| format!("读取RDAP响应失败: {}", e))?;
+        let json: Value = serde_json::from_str(&raw).map_err(|e| format!("解析RDAP响应失败: {}", e))?;
+        Ok(Self::parse_response(&json, &raw, extra_fields))
+    }
+
+    /// 按最长前缀找到地址所属的RDAP服务器，引导注册表过期时重新下载
+    async fn find_server(addr: IpAddr) -> Result<String, String> {
+        let (cache, url) = match addr {
+            IpAddr::V4(_) => (&IPV4_BOOTSTRAP, IPV4_BOOTSTRAP_URL),
+            IpAddr::V6(_) => (&IPV6_BOOTSTRAP, IPV6_BOOTSTRAP_URL),
+        };
+        let cached = {
+            let cache = cache.lock().unwrap_or_else(|e| e.into_inner());
+            cache
+                .as_ref()
+                .filter(|cached| cached.fetched_at.elapsed() < BOOTSTRAP_TTL)
+                .map(|cached| Self::match_server(&cached.services, addr))
+        };
+        if let Some(server) = cached {
+            return server.ok_or_else(|| format!("RDAP引导注册表中没有 {} 的服务器", log_ip(&addr.to_string())));
+        }
+
+        let services = Self::fetch_bootstrap(url).await?;
+        let server = Self::match_server(&services, addr);
+        *cache.lock().unwrap_or_else(|e| e.into_inner()) = Some(CachedBootstrap { fetched_at: Instant::now(), services });
+        server.ok_or_else(|| format!("RDAP引导注册表中没有 {} 的服务器", log_ip(&addr.to_string())))
+    }
+
+    async fn fetch_bootstrap(url: &str) -> Result<Bootstrap, String> {
+        info!("下载RDAP引导注册表: {}", url);
+        let client = http_client_builder()
+            .timeout(request_timeout(Duration::from_secs(15)))
+            .build()
+            .map_err(|e| format!("创建HTTP客户端失败: {}", e))?;
+        let resp = with_trace_context(client.get(url))
+            .send()
+            .await
+            .map_err(|e| format!("RDAP引导注册表请求失败: {}", e))?;
+        if !resp.status().is_success() {
+            return Err(format!("RDAP引导注册表请求失败: 状态码 {}", resp.status()));
+        }
+        let json: Value = resp.json().await.map_err(|e| format!("解析RDAP引导注册表失败: {}", e))?;
+        Self::parse_bootstrap(&json)
+    }
+
+    /// services 的每一项为 [[地址块...], [服务器地址...]]，优先使用https地址
+    fn parse_bootstrap(json: &Value) -> Result<Bootstrap, String> {
+        let services = json["services"].as_array().ok_or("RDAP引导注册表缺少 services")?;
+        let mut bootstrap = Vec::new();
+        for service in services {
+            let (Some(blocks), Some(urls)) = (service[0].as_array(), service[1].as_array()) else {
+                continue;
+            };
+            let urls: Vec<&str> = urls.iter().filter_map(Value::as_str).collect();
+            let Some(url) = urls.iter().find(|url| url.starts_with("https://")).or(urls.first()) else {
+                continue;
+            };
+            let url = if url.ends_with('/') { url.to_string() } else { format!("{}/", url) };
+            for block in blocks.iter().filter_map(Value::as_str) {
+                if let Ok(network) = block.parse::<IpNet>() {
+                    bootstrap.push((network, url.clone()));
+                }
+            }
+        }
+        debug!("RDAP引导注册表条目数: {}", bootstrap.len());
+        Ok(bootstrap)
+    }
+
+    fn match_server(bootstrap: &Bootstrap, addr: IpAddr) -> Option<String> {
+        bootstrap
+            .iter()
+            .filter(|(network, _)| network.contains(&addr))
+            .max_by_key(|(network, _)| network.prefix_len())
+            .map(|(_, url)| url.clone())
+    }
+
+    /// 将RDAP ip network对象映射为 WhoisInfo；RDAP的地址对象不含路由对象，route 和 origin 为空
+    fn parse_response(json: &Value, raw: &str, extra_fields: &[String]) -> WhoisInfo {
+        let text = |value: &Value| value.as_str().map(str::to_string);
+        let entities = json["entities"].as_array().map(Vec::as_slice).unwrap_or_default();
+        let with_role = |role: &str| {
+            entities.iter().find(|entity| {
+                entity["roles"].as_array().is_some_and(|roles| roles.iter().any(|r| r.as_str() == Some(role)))
+            })
+        };
+        let descr = json["remarks"]
+            .as_array()
+            .and_then(|remarks| remarks.first())
+            .and_then(|remark| remark["description"].as_array())
+            .map(|lines| lines.iter().filter_map(Value::as_str).collect::<Vec<_>>().join(" "))
+            .filter(|descr| !descr.is_empty());
+        let last_modified = json["events"].as_array().and_then(|events| {
+            events
+                .iter()
+                .find(|event| event["eventAction"].as_str() == Some("last changed"))
+                .and_then(|event| text(&event["eventDate"]))
+        });
+
+        let mut extra = HashMap::new();
+        for field in extra_fields {
+            let values: Vec<String> = match &json[field.as_str()] {
+                Value::String(value) => vec![value.clone()],
+                Value::Array(values) => values.iter().filter_map(Value::as_str).map(str::to_string).collect(),
+                _ => Vec::new(),
+            };
+            if !values.is_empty() {
+                extra.insert(field.clone(), values);
+            }
+        }
+
+        WhoisInfo {
+            country: text(&json["country"]),
+            netname: text(&json["name"]),
+            descr,
+            org: with_role("registrant").and_then(vcard_name),
+            admin_c: with_role("administrative").and_then(|entity| text(&entity["handle"])),
+            tech_c: with_role("technical").and_then(|entity| text(&entity["handle"])),
+            mnt_by: None,
+            last_modified,
+            route: None,
+            origin: None,
+            extra,
+            raw_response: raw.to_string(),
+        }
+    }
+}
+
+/// 从实体的 vcardArray 中取 fn（显示名称）
+fn vcard_name(entity: &Value) -> Option<String> {
+    entity["vcardArray"][1]
+        .as_array()?
+        .iter()
+        .find(|property| property[0].as_str() == Some("fn"))
+        .and_then(|property| property[3].as_str())
+        .map(str::to_string)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bootstrap_uses_longest_prefix_and_https() {
+        let json = serde_json::json!({
+            "services": [
+                [["1.0.0.0/8"], ["http://rdap.apnic.net/", "https://rdap.apnic.net/"]],
+                [["1.1.0.0/16"], ["https://rdap.example.net"]]
+            ]
+        });
+        let bootstrap = RdapClient::parse_bootstrap(&json).unwrap();
+        let server = |ip: &str| RdapClient::match_server(&bootstrap, ip.parse().unwrap());
+        assert_eq!(server("1.2.3.4").as_deref(), Some("https://rdap.apnic.net/"));
+        assert_eq!(server("1.1.1.1").as_deref(), Some("https://rdap.example.net/"));
+        assert_eq!(server("8.8.8.8"), None);
+    }
+
+    #[test]
+    fn rdap_network_maps_to_whois_info() {
+        let json = serde_json::json!({
+            "objectClassName": "ip network",
+            "handle": "1.1.1.0 - 1.1.1.255",
+            "name": "APNIC-LABS",
+            "country": "AU",
+            "type": "ASSIGNED PORTABLE",
+            "remarks": [{ "description": ["APNIC and Cloudflare", "DNS Resolver project"] }],
+            "events": [{ "eventAction": "last changed", "eventDate": "2023-04-26T00:42:16Z" }],
+            "entities": [
+                {
+                    "handle": "ORG-ARAD1-AP",
+                    "roles": ["registrant"],
+                    "vcardArray": ["vcard", [["version", {}, "text", "4.0"], ["fn", {}, "text", "APNIC Research and Development"]]]
+                },
+                { "handle": "AIC3-AP", "roles": ["administrative", "technical"] }
+            ]
+        });
+        let info = RdapClient::parse_response(&json, "{}", &["type".to_string()]);
+        assert_eq!(info.netname.as_deref(), Some("APNIC-LABS"));
+        assert_eq!(info.country.as_deref(), Some("AU"));
+        assert_eq!(info.descr.as_deref(), Some("APNIC and Cloudflare DNS Resolver project"));
+        assert_eq!(info.org.as_deref(), Some("APNIC Research and Development"));
+        assert_eq!(info.admin_c.as_deref(), Some("AIC3-AP"));
+        assert_eq!(info.tech_c.as_deref(), Some("AIC3-AP"));
+        assert_eq!(info.last_modified.as_deref(), Some("2023-04-26T00:42:16Z"));
+        assert_eq!(info.extra["type"], vec!["ASSIGNED PORTABLE"]);
+        assert!(info.route.is_none());
+    }
+}
@@ -0,0 +1,345 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use futures::future::BoxFuture;
+use redis::aio::MultiplexedConnection;
+use redis::AsyncCommands;
+use serde::{Deserialize, Serialize};
+use rand::Rng;
+use tokio::sync::Mutex;
+use tracing::{info, warn};
+use crate::maxmind::reader::IpInfo;
+use super::ip_cache::{cache_key_for, CacheHit, restore_ip_from_query, strip_ip_for_storage, CacheBackend};
+use super::privacy::log_ip;
+
+/// 单次Redis命令的响应超时，超时按未命中处理，避免Redis故障拖慢查询
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// 建立Redis连接的超时
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// 统计条目时每次SCAN返回的键数量
+const SCAN_BATCH: usize = 1000;
+
+/// 写入Redis的条目，soft_expires_at 之后为过期数据，再过保留窗口为硬过期，键本身在宽限期结束时由Redis删除
+#[derive(Serialize, Deserialize)]
+struct StoredEntry {
+    soft_expires_at: u64,
+    /// 写入时间；ttl 带随机抖动时无法由 soft_expires_at 反推，旧条目为0
+    #[serde(default)]
+    written_at: u64,
+    info: IpInfo,
+}
+
+/// 多个实例共享的Redis缓存，条目以JSON保存并设置过期时间
+/// Redis不可用时读取按未命中处理、写入返回错误，连接断开后下次访问自动重连
+pub struct RedisCache {
+    client: redis::Client,
+    connection: Mutex<Option<MultiplexedConnection>>,
+    key_prefix: String,
+    ttl: Duration,
+    stale_window: Duration,
+    error_grace: Duration,
+    ttl_jitter: Duration,
+    ipv6_group_prefix: Option<u8>,
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+impl RedisCache {
+    /// 创建Redis缓存，只校验地址，第一次访问时才建立连接
+    pub fn new(url: &str, key_prefix: &str, ttl: Duration) -> Result<Self, String> {
+        let client = redis::Client::open(url).map_err(|e| format!("Redis地址无效: {}", e))?;
+        Ok(Self {
+            client,
+            connection: Mutex::new(None),
+            key_prefix: key_prefix.to_string(),
+            ttl,
+            stale_window: Duration::ZERO,
+            error_grace: Duration::ZERO,
+            ttl_jitter: Duration::ZERO,
+            ipv6_group_prefix: None,
+        })
+    }
+
+    /// 条目过期后在 stale_window 内仍可读取（标记为过期）
+    pub fn with_stale_window(mut self, stale_window: Duration) -> Self {
+        self.stale_window = stale_window;
+        self
+    }
+
+    /// 每个条目的存活时间随机延长 0..=ttl_jitter，避免各实例写入的热门条目同时过期
+    pub fn with_ttl_jitter(mut self, ttl_jitter: Duration) -> Self {
+        self.ttl_jitter = ttl_jitter;
+        self
+    }
+
+    /// 条目硬过期后再保留 error_grace，数据源全部不可用时仍可返回
+    pub fn with_error_grace(mut self, error_grace: Duration) -> Self {
+        self.error_grace = error_grace;
+        self
+    }
+
+    /// 硬过期时间戳，之后的条目只能通过 get_expired 读取
+    fn hard_expires_at(&self, entry: &StoredEntry) -> u64 {
+        entry.soft_expires_at.saturating_add(self.stale_window.as_secs())
+    }
+
+    /// 写入时间；旧版本写入的条目没有该字段，当时尚无抖动，可由过期时间反推
+    fn written_at(&self, entry: &StoredEntry) -> u64 {
+        match entry.written_at {
+            0 => entry.soft_expires_at.saturating_sub(self.ttl.as_secs()),
+            written_at => written_at,
+        }
+    }
+
+    /// 同一IPv6子网内的地址共享缓存条目，prefix_len 为子网前缀长度
+    pub fn with_ipv6_grouping(mut self, prefix_len: Option<u8>) -> Self {
+        self.ipv6_group_prefix = prefix_len;
+        self
+    }
+
+    fn key(&self, ip: &str) -> String {
+        format!("{}{}", self.key_prefix, cache_key_for(ip, self.ipv6_group_prefix))
+    }
+
+    /// 获取共享连接，尚未连接或上次连接已断开时重新连接
+    async fn connection(&self) -> redis::RedisResult<MultiplexedConnection> {
+        let mut connection = self.connection.lock().await;
+        if let Some(connection) = connection.as_ref() {
+            return Ok(connection.clone());
+        }
+        let fresh = self.client
+            .get_multiplexed_tokio_connection_with_response_timeouts(RESPONSE_TIMEOUT, CONNECT_TIMEOUT)
+            .await?;
+        info!("已连接Redis缓存");
+        *connection = Some(fresh.clone());
+        Ok(fresh)
+    }
+
+    /// 连接层面的错误后丢弃连接，下次访问时重连
+    async fn handle_error(&self, e: &redis::RedisError) {
+        if e.is_io_error() || e.is_connection_dropped() || e.is_connection_refusal() || e.is_timeout() {
+            *self.connection.lock().await = None;
+        }
+    }
+
+    async fn get_entry(&self, ip: &str) -> redis::RedisResult<Option<StoredEntry>> {
+        let mut connection = self.connection().await?;
+        let value: Option<String> = connection.get(self.key(ip)).await?;
+        Ok(value.and_then(|value| match serde_json::from_str(&value) {
+            Ok(entry) => Some(entry),
+            Err(e) => {
+                warn!("Redis缓存条目无法解析，按未命中处理: {}", e);
+                None
+            }
+        }))
+    }
+
+    async fn set_entry(&self, ip: &str, mut info: IpInfo) -> Result<(), String> {
+        strip_ip_for_storage(&mut info);
+        let jitter = match self.ttl_jitter.as_secs() {
+            0 => 0,
+            max => rand::thread_rng().gen_range(0..=max),
+        };
+        let ttl = self.ttl.as_secs() + jitter;
+        let now = now_secs();
+        let entry = StoredEntry { soft_expires_at: now.saturating_add(ttl), written_at: now, info };
+        let value = serde_json::to_string(&entry).map_err(|e| format!("序列化缓存条目失败: {}", e))?;
+        // SETEX 要求过期时间大于0
+        let expire_secs = (ttl + (self.stale_window + self.error_grace).as_secs()).max(1);
+        let result: redis::RedisResult<()> = async {
+            let mut connection = self.connection().await?;
+            connection.set_ex(self.key(ip), value, expire_secs).await
+        }.await;
+        if let Err(e) = &result {
+            self.handle_error(e).await;
+        }
+        result.map_err(|e| format!("写入Redis缓存失败: {}", e))?;
+        info!("IP信息已写入Redis缓存: {}", log_ip(ip));
+        Ok(())
+    }
+
+    /// 遍历本实例使用的全部键
+    async fn scan_keys(&self) -> redis::RedisResult<Vec<String>> {
+        let mut connection = self.connection().await?;
+        let pattern = format!("{}*", self.key_prefix);
+        let mut cursor = 0u64;
+        let mut keys = Vec::new();
+        loop {
+            let (next, batch): (u64, Vec<String>) = redis::cmd("SCAN")
+                .arg(cursor)
+                .arg("MATCH")
+                .arg(&pattern)
+                .arg("COUNT")
+                .arg(SCAN_BATCH)
+                .query_async(&mut connection)
+                .await?;
+            keys.extend(batch);
+            if next == 0 {
+                return Ok(keys);
+            }
+            cursor = next;
+        }
+    }
+
+    async fn count_expiring(&self, within: Duration) -> redis::RedisResult<usize> {
+        let keys = self.scan_keys().await?;
+        if keys.is_empty() {
+            return Ok(0);
+        }
+        let mut connection = self.connection().await?;
+        let mut pipeline = redis::pipe();
+        for key in &keys {
+            pipeline.ttl(key);
+        }
+        let ttls: Vec<i64> = pipeline.query_async(&mut connection).await?;
+        // 键的剩余时间包含保留窗口和宽限期，减去后为距离软过期的时间
+        let stale_window = (self.stale_window + self.error_grace).as_secs() as i64;
+        let within = within.as_secs() as i64;
+        Ok(ttls
+            .into_iter()
+            .filter(|ttl| *ttl >= 0)
+            .map(|ttl| ttl - stale_window)
+            .filter(|remaining| *remaining > 0 && *remaining <= within)
+            .count())
+    }
+
+    async fn memory_mb(&self) -> redis::RedisResult<f64> {
+        let mut connection = self.connection().await?;
+        let info: String = redis::cmd("INFO").arg("memory").query_async(&mut connection).await?;
+        let used_memory = info
+            .lines()
+            .find_map(|line| line.strip_prefix("used_memory:"))
+            .and_then(|value| value.trim().parse::<f64>().ok())
+            .unwrap_or(0.0);
+        Ok(used_memory / (1024.0 * 1024.0))
+    }
+}
+
+impl CacheBackend for RedisCache {
+    fn get_with_ttl<'a>(&'a self, ip: &'a str) -> BoxFuture<'a, Option<CacheHit>> {
+        Box::pin(async move {
+            match self.get_entry(ip).await {
+                Ok(entry) => {
+                    let mut entry = entry?;
+                    let now = now_secs();
+                    if now >= self.hard_expires_at(&entry) {
+                        return None;
+                    }
+                    restore_ip_from_query(&mut entry.info, ip);
+                    Some(CacheHit {
+                        ttl_remaining: entry.soft_expires_at.saturating_sub(now),
+                        stale: now >= entry.soft_expires_at,
+                        age_secs: now.saturating_sub(self.written_at(&entry)),
+                        info: entry.info,
+                    })
+                }
+                Err(e) => {
+                    warn!("读取Redis缓存失败，按未命中处理: {}", e);
+                    self.handle_error(&e).await;
+                    None
+                }
+            }
+        })
+    }
+
+    fn set<'a>(&'a self, ip: &'a str, info: IpInfo) -> BoxFuture<'a, Result<(), String>> {
+        Box::pin(self.set_entry(ip, info))
+    }
+
+    fn contains<'a>(&'a self, ip: &'a str) -> BoxFuture<'a, bool> {
+        Box::pin(async move {
+            match self.get_entry(ip).await {
+                Ok(entry) => entry.is_some_and(|entry| now_secs() < self.hard_expires_at(&entry)),
+                Err(e) => {
+                    warn!("查询Redis缓存失败: {}", e);
+                    self.handle_error(&e).await;
+                    false
+                }
+            }
+        })
+    }
+
+    fn ttl(&self) -> BoxFuture<'_, Duration> {
+        Box::pin(async move { self.ttl + self.stale_window })
+    }
+
+    /// Redis按过期时间自行删除条目，无需清理
+    fn cleanup(&self) -> BoxFuture<'_, usize> {
+        Box::pin(async { 0 })
+    }
+
+    /// 条目数为本实例前缀下的键数量，内存为整个Redis实例的占用
+    fn stats(&self) -> BoxFuture<'_, (usize, f64)> {
+        Box::pin(async move {
+            let entries = match self.scan_keys().await {
+                Ok(keys) => keys.len(),
+                Err(e) => {
+                    warn!("统计Redis缓存条目失败: {}", e);
+                    self.handle_error(&e).await;
+                    0
+                }
+            };
+            let memory_mb = self.memory_mb().await.unwrap_or_else(|e| {
+                warn!("读取Redis内存占用失败: {}", e);
+                0.0
+            });
+            (entries, memory_mb)
+        })
+    }
+
+    fn expiring_within(&self, within: Duration) -> BoxFuture<'_, usize> {
+        Box::pin(async move {
+            self.count_expiring(within).await.unwrap_or_else(|e| {
+                warn!("统计Redis缓存过期条目失败: {}", e);
+                0
+            })
+        })
+    }
+
+    fn get_expired<'a>(&'a self, ip: &'a str) -> BoxFuture<'a, Option<CacheHit>> {
+        Box::pin(async move {
+            match self.get_entry(ip).await {
+                Ok(entry) => {
+                    let now = now_secs();
+                    let mut entry = entry.filter(|entry| now >= self.hard_expires_at(entry))?;
+                    restore_ip_from_query(&mut entry.info, ip);
+                    Some(CacheHit { ttl_remaining: 0, stale: true, age_secs: now.saturating_sub(self.written_at(&entry)), info: entry.info })
+                }
+                Err(e) => {
+                    warn!("读取Redis缓存失败: {}", e);
+                    self.handle_error(&e).await;
+                    None
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keys_are_prefixed_and_grouped() {
+        let cache = RedisCache::new("redis://127.0.0.1:6379", "ipapi:ip:", Duration::from_secs(60))
+            .unwrap()
+            .with_ipv6_grouping(Some(64));
+        assert_eq!(cache.key("192.0.2.1"), "ipapi:ip:192.0.2.1");
+        assert_eq!(cache.key("2001:db8::1"), cache.key("2001:db8::ffff"));
+        assert!(RedisCache::new("not a url", "ipapi:ip:", Duration::from_secs(60)).is_err());
+    }
+
+    #[tokio::test]
+    async fn unreachable_redis_is_treated_as_miss() {
+        // 端口1上没有服务，连接被拒绝
+        let cache = RedisCache::new("redis://127.0.0.1:1", "ipapi:ip:", Duration::from_secs(60)).unwrap();
+        assert!(CacheBackend::get_with_ttl(&cache, "192.0.2.1").await.is_none());
+        assert!(!CacheBackend::contains(&cache, "192.0.2.1").await);
+        assert!(CacheBackend::set(&cache, "192.0.2.1", IpInfo::default()).await.is_err());
+    }
+}
@@ -0,0 +1,175 @@
+use chrono::{DateTime, Utc};
+use super::trace_context::with_trace_context;
+use super::upstream::{http_client_builder, request_timeout};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::time::Duration;
+use tracing::info;
+use super::privacy::log_ip;
+
+/// 某一时间点的路由状态（来自RIPEstat routing-status）
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HistoricalRouting {
+    pub resource: String,
+    /// RIPEstat实际使用的查询时间
+    pub query_time: Option<String>,
+    /// 该时间点宣告此前缀的源AS
+    pub origins: Vec<u32>,
+    pub first_seen: Option<String>,
+    pub last_seen: Option<String>,
+}
+
+/// 覆盖查询目标的较大前缀及其源AS（来自RIPEstat related-prefixes）
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RelatedPrefix {
+    pub prefix: String,
+    pub origin_asn: Option<u32>,
+}
+
+pub struct RipeStatClient;
+
+impl RipeStatClient {
+    /// 查询前缀在指定时间点的路由状态
+    pub async fn routing_status(resource: &str, at: DateTime<Utc>) -> Result<HistoricalRouting, String> {
+        let timestamp = at.format("%Y-%m-%dT%H:%M:%S").to_string();
+        let url = "https://stat.ripe.net/data/routing-status/data.json";
+        info!("RIPEstat 历史路由请求: resource={}, timestamp={}", log_ip(resource), timestamp);
+        let client = http_client_builder()
+            .timeout(request_timeout(Duration::from_secs(30)))
+            .build()
+            .map_err(|e| format!("创建HTTP客户端失败: {}", e))?;
+
+        let request = client
+            .get(url)
+            .query(&[("resource", resource), ("timestamp", timestamp.as_str())]);
+        let resp = with_trace_context(request)
+            .send()
+            .await
+            .map_err(|e| format!("RIPEstat请求失败: {}", e))?;
+
+        if !resp.status().is_success() {
+            return Err(format!("RIPEstat请求失败: 状态码 {}", resp.status()));
+        }
+
+        let json: Value = resp.json().await
+            .map_err(|e| format!("解析RIPEstat响应失败: {}", e))?;
+        Self::parse_routing_status(resource, &json)
+    }
+
+    /// 查询覆盖该前缀的所有较大前缀，按前缀长度从大到小排列（最不具体的在前）
+    pub async fn less_specific_prefixes(resource: &str) -> Result<Vec<RelatedPrefix>, String> {
+        let url = "https://stat.ripe.net/data/related-prefixes/data.json";
+        info!("RIPEstat 相关前缀请求: resource={}", log_ip(resource));
+        let client = http_client_builder()
+            .timeout(request_timeout(Duration::from_secs(30)))
+            .build()
+            .map_err(|e| format!("创建HTTP客户端失败: {}", e))?;
+
+        let request = client.get(url).query(&[("resource", resource)]);
+        let resp = with_trace_context(request)
+            .send()
+            .await
+            .map_err(|e| format!("RIPEstat请求失败: {}", e))?;
+
+        if !resp.status().is_success() {
+            return Err(format!("RIPEstat请求失败: 状态码 {}", resp.status()));
+        }
+
+        let json: Value = resp.json().await
+            .map_err(|e| format!("解析RIPEstat响应失败: {}", e))?;
+        Self::parse_related_prefixes(&json)
+    }
+
+    fn parse_related_prefixes(json: &Value) -> Result<Vec<RelatedPrefix>, String> {
+        let prefixes = json
+            .get("data")
+            .and_then(|d| d.get("prefixes"))
+            .and_then(|p| p.as_array())
+            .ok_or("RIPEstat响应无prefixes")?;
+        let mut related: Vec<RelatedPrefix> = prefixes
+            .iter()
+            .filter(|p| {
+                p.get("relationship")
+                    .and_then(|r| r.as_str())
+                    .is_some_and(|r| r.contains("Less Specific"))
+            })
+            .filter_map(|p| {
+                let prefix = p.get("prefix")?.as_str()?.to_string();
+                let origin_asn = p.get("origin_asn").and_then(|o| {
+                    o.as_u64().or_else(|| o.as_str()?.parse().ok())
+                }).and_then(|asn| u32::try_from(asn).ok());
+                Some(RelatedPrefix { prefix, origin_asn })
+            })
+            .collect();
+        let prefix_len = |p: &RelatedPrefix| p.prefix.parse::<ipnet::IpNet>().map(|n| n.prefix_len()).unwrap_or(u8::MAX);
+        related.sort_by(|a, b| prefix_len(a).cmp(&prefix_len(b)).then_with(|| a.prefix.cmp(&b.prefix)));
+        related.dedup();
+        Ok(related)
+    }
+
+    fn parse_routing_status(resource: &str, json: &Value) -> Result<HistoricalRouting, String> {
+        let data = json.get("data").ok_or("RIPEstat响应无data")?;
+        let text = |value: Option<&Value>| value.and_then(|v| v.as_str()).map(|s| s.to_string());
+        let origins = data
+            .get("origins")
+            .and_then(|o| o.as_array())
+            .map(|origins| {
+                origins
+                    .iter()
+                    .filter_map(|o| {
+                        let origin = o.get("origin")?;
+                        origin.as_u64().or_else(|| origin.as_str()?.parse().ok())
+                    })
+                    .filter_map(|asn| u32::try_from(asn).ok())
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        Ok(HistoricalRouting {
+            resource: text(data.get("resource")).unwrap_or_else(|| resource.to_string()),
+            query_time: text(data.get("query_time")),
+            origins,
+            first_seen: text(data.get("first_seen").and_then(|f| f.get("time"))),
+            last_seen: text(data.get("last_seen").and_then(|l| l.get("time"))),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_routing_status_origins() {
+        let json = serde_json::json!({
+            "data": {
+                "resource": "1.1.1.0/24",
+                "query_time": "2020-01-01T00:00:00",
+                "origins": [{"origin": 13335, "route_objects": ["RADB"]}],
+                "first_seen": {"prefix": "1.1.1.0/24", "origin": "13335", "time": "2018-03-31T00:00:00"},
+                "last_seen": {"prefix": "1.1.1.0/24", "origin": "13335", "time": "2020-01-01T00:00:00"}
+            }
+        });
+        let routing = RipeStatClient::parse_routing_status("1.1.1.0/24", &json).unwrap();
+        assert_eq!(routing.origins, vec![13335]);
+        assert_eq!(routing.first_seen.as_deref(), Some("2018-03-31T00:00:00"));
+    }
+
+    #[test]
+    fn parses_less_specific_related_prefixes() {
+        let json = serde_json::json!({
+            "data": {
+                "prefixes": [
+                    {"prefix": "1.1.1.0/25", "origin_asn": "13335", "relationship": "Overlap - More Specific"},
+                    {"prefix": "1.1.0.0/23", "origin_asn": "13335", "relationship": "Overlap - Less Specific"},
+                    {"prefix": "1.0.0.0/8", "origin_asn": 1221, "relationship": "Overlap - Less Specific"}
+                ]
+            }
+        });
+        let related = RipeStatClient::parse_related_prefixes(&json).unwrap();
+        assert_eq!(related, vec![
+            RelatedPrefix { prefix: "1.0.0.0/8".to_string(), origin_asn: Some(1221) },
+            RelatedPrefix { prefix: "1.1.0.0/23".to_string(), origin_asn: Some(13335) },
+        ]);
+    }
+}
@@ -0,0 +1,155 @@
+use crate::config::RiskConfig;
+use crate::maxmind::reader::IpInfo;
+use super::bgptools_client::asn_number;
+
+/// 根据已收集的信息计算 0-100 的风险评分，并返回触发的风险因素
+pub fn score(info: &IpInfo, config: &RiskConfig) -> (u8, Vec<String>) {
+    let weights = &config.weights;
+    let mut total: u32 = 0;
+    let mut factors = Vec::new();
+
+    let mut add = |weight: u32, factor: &str| {
+        if weight > 0 {
+            total += weight;
+            factors.push(factor.to_string());
+        }
+    };
+
+    if info.rpki_info_list.iter().any(|r| r.validity.eq_ignore_ascii_case("invalid")) {
+        add(weights.rpki_invalid, "rpki_invalid");
+    } else if !info.rpki_info_list.is_empty()
+        && info.rpki_info_list.iter().all(|r| r.validity.eq_ignore_ascii_case("not-found")) {
+        add(weights.rpki_not_found, "rpki_not_found");
+    }
+
+    match &info.whois_info {
+        None => add(weights.no_whois, "no_whois"),
+        Some(whois) => {
+            if whois.admin_c.is_none() && whois.tech_c.is_none() {
+                add(weights.no_abuse_contact, "no_abuse_contact");
+            }
+            // 登记的源AS与实际观察到的源AS不一致，可能是路由劫持
+            if let (Some(registered), Some(observed)) = (
+                whois.origin.as_deref(),
+                info.bgp_info.as_ref().and_then(|b| b.asn.as_deref()),
+            ) && asn_number(registered) != asn_number(observed) {
+                add(weights.origin_mismatch, "origin_mismatch");
+            }
+        }
+    }
+
+    if !info.dnsbl.is_empty() {
+        add(weights.dnsbl_listed, "dnsbl_listed");
+    }
+
+    if info.asn.is_none() && info.bgp_info.as_ref().and_then(|b| b.asn.as_ref()).is_none() {
+        add(weights.no_asn, "no_asn");
+    }
+
+    (total.min(100) as u8, factors)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::bgptools_client::BgpToolsInfo;
+    use crate::utils::rpki_client::RpkiValidity;
+    use crate::utils::whois_client::WhoisInfo;
+
+    fn rpki(validity: &str) -> RpkiValidity {
+        RpkiValidity {
+            asn: "AS13335".to_string(),
+            prefix: "1.1.1.0/24".to_string(),
+            validity: validity.to_string(),
+            reason: None,
+            vrps: None,
+        }
+    }
+
+    /// 各项数据齐全且一致的查询结果，不触发任何风险因素
+    fn clean_info() -> IpInfo {
+        IpInfo {
+            asn: Some(13335),
+            whois_info: Some(WhoisInfo {
+                admin_c: Some("AIC3-AP".to_string()),
+                origin: Some("AS13335".to_string()),
+                ..Default::default()
+            }),
+            bgp_info: Some(BgpToolsInfo { asn: Some("13335".to_string()), ..Default::default() }),
+            rpki_info_list: vec![rpki("valid")],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn consistent_records_score_zero() {
+        assert_eq!(score(&clean_info(), &RiskConfig::default()), (0, Vec::new()));
+    }
+
+    #[test]
+    fn registered_and_observed_origins_are_compared_by_number() {
+        let config = RiskConfig::default();
+        let mut info = clean_info();
+        info.bgp_info.as_mut().unwrap().asn = Some("as13335".to_string());
+        assert_eq!(score(&info, &config).1, Vec::<String>::new());
+
+        info.bgp_info.as_mut().unwrap().asn = Some("AS64496".to_string());
+        assert_eq!(score(&info, &config), (30, vec!["origin_mismatch".to_string()]));
+
+        // 任一侧缺少源AS时无法比较，不计入
+        info.bgp_info = None;
+        assert_eq!(score(&info, &config).1, Vec::<String>::new());
+        let mut info = clean_info();
+        info.whois_info.as_mut().unwrap().origin = None;
+        assert_eq!(score(&info, &config).1, Vec::<String>::new());
+    }
+
+    #[test]
+    fn rpki_invalid_takes_precedence_over_not_found() {
+        let config = RiskConfig::default();
+        let mut info = clean_info();
+        info.rpki_info_list = vec![rpki("not-found"), rpki("NOT-FOUND")];
+        assert_eq!(score(&info, &config), (10, vec!["rpki_not_found".to_string()]));
+
+        info.rpki_info_list.push(rpki("Invalid"));
+        assert_eq!(score(&info, &config), (50, vec!["rpki_invalid".to_string()]));
+
+        // 部分路由有效时不算未找到
+        info.rpki_info_list = vec![rpki("not-found"), rpki("valid")];
+        assert_eq!(score(&info, &config).0, 0);
+    }
+
+    #[test]
+    fn missing_data_is_scored() {
+        let config = RiskConfig::default();
+        assert_eq!(
+            score(&IpInfo::default(), &config),
+            (30, vec!["no_whois".to_string(), "no_asn".to_string()])
+        );
+
+        let mut info = clean_info();
+        info.whois_info.as_mut().unwrap().admin_c = None;
+        assert_eq!(score(&info, &config), (10, vec!["no_abuse_contact".to_string()]));
+
+        // MaxMind没有ASN但BGP数据有时不算缺少ASN
+        let mut info = clean_info();
+        info.asn = None;
+        assert_eq!(score(&info, &config).0, 0);
+    }
+
+    #[test]
+    fn dnsbl_listings_are_scored_and_total_is_capped() {
+        let mut config = RiskConfig::default();
+        let mut info = clean_info();
+        info.dnsbl = vec!["zen.spamhaus.org".to_string()];
+        assert_eq!(score(&info, &config), (30, vec!["dnsbl_listed".to_string()]));
+
+        info.rpki_info_list = vec![rpki("invalid")];
+        info.bgp_info.as_mut().unwrap().asn = Some("64496".to_string());
+        assert_eq!(score(&info, &config).0, 100);
+
+        // 权重为0的因素不计入也不列出
+        config.weights.dnsbl_listed = 0;
+        assert_eq!(score(&info, &config), (80, vec!["rpki_invalid".to_string(), "origin_mismatch".to_string()]));
+    }
+}
@@ -1,7 +1,8 @@
 use serde::{Deserialize, Serialize};
-use reqwest::Client;
+use super::upstream::{http_client_builder, request_timeout, send_with_retry};
 use std::time::Duration;
-use tracing::info;
+use futures::future::join_all;
+use tracing::{info, warn};
 use serde_json::Value;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -21,6 +22,41 @@ pub struct RpkiValidity {
     pub vrps: Option<Vec<RpkiVrps>>,
 }
 
+/// 单个验证器对某条路由的结论，查询失败时 validity 为None并记录错误
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RpkiVerdict {
+    pub validator: String,
+    pub validity: Option<String>,
+    pub error: Option<String>,
+}
+
+impl RpkiVerdict {
+    pub fn from_result(validator: &str, result: Result<RpkiValidity, String>) -> Self {
+        match result {
+            Ok(validity) => Self { validator: validator.to_string(), validity: Some(validity.validity), error: None },
+            Err(e) => Self { validator: validator.to_string(), validity: None, error: Some(e) },
+        }
+    }
+}
+
+/// 多个验证器对同一路由的交叉验证结果
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RpkiCrossCheck {
+    pub asn: String,
+    pub prefix: String,
+    /// 所有验证器都给出了结论且结论相同
+    pub agree: bool,
+    pub verdicts: Vec<RpkiVerdict>,
+}
+
+impl RpkiCrossCheck {
+    pub fn new(asn: &str, prefix: &str, verdicts: Vec<RpkiVerdict>) -> Self {
+        let first = verdicts.first().and_then(|v| v.validity.as_ref());
+        let agree = first.is_some() && verdicts.iter().all(|v| v.validity.as_ref() == first);
+        Self { asn: asn.to_string(), prefix: prefix.to_string(), agree, verdicts }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct RpkiResponse {
     pub validated_route: Option<RpkiValidatedRoute>,
@@ -57,24 +93,105 @@ pub struct RpkiRoute {
     pub prefix: String,
 }
 
+/// 批量验证请求中的单条路由
+#[derive(Debug, Clone, Serialize)]
+struct RpkiBatchRoute<'a> {
+    asn: &'a str,
+    prefix: &'a str,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct RpkiBatchRequest<'a> {
+    routes: Vec<RpkiBatchRoute<'a>>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct RpkiBatchResponse {
+    validated_routes: Vec<RpkiValidatedRoute>,
+}
+
 pub struct RpkiClient {
     pub base_url: String,
+    /// 验证器是否支持批量验证接口（POST /api/v1/validity）
+    pub batch: bool,
 }
 
 impl RpkiClient {
     pub fn new(base_url: &str) -> Self {
-        Self { base_url: base_url.trim_end_matches('/').to_string() }
+        Self { base_url: base_url.trim_end_matches('/').to_string(), batch: false }
+    }
+
+    /// 启用批量验证
+    pub fn with_batch(mut self, batch: bool) -> Self {
+        self.batch = batch;
+        self
+    }
+
+    /// 验证同一前缀的多个源AS；验证器支持时使用一次批量请求，否则（或批量请求失败时）逐个并发查询
+    /// 逐个查询时单个ASN失败只记录日志，不影响其他结果
+    pub async fn query_batch(&self, prefix: &str, asns: &[String]) -> Vec<RpkiValidity> {
+        if self.batch && asns.len() > 1 {
+            match self.post_batch(prefix, asns).await {
+                Ok(validities) => return validities,
+                Err(e) => warn!("RPKI批量验证失败，改为逐个查询: {}", e),
+            }
+        }
+
+        let futures = asns.iter().map(|asn| async move {
+            info!("发送RPKI请求: prefix={}, asn={}", prefix, asn);
+            match self.query(prefix, asn).await {
+                Ok(validity) => Some(validity),
+                Err(e) => {
+                    warn!("RPKI查询失败 {}: {}", asn, e);
+                    None
+                }
+            }
+        });
+        join_all(futures).await.into_iter().flatten().collect()
+    }
+
+    async fn post_batch(&self, prefix: &str, asns: &[String]) -> Result<Vec<RpkiValidity>, String> {
+        let url = format!("{}/api/v1/validity", self.base_url);
+        info!("RPKI 批量请求 URL: {}, ASN数量: {}", url, asns.len());
+        let client = http_client_builder()
+            .timeout(request_timeout(Duration::from_secs(30)))
+            .build()
+            .map_err(|e| format!("创建HTTP客户端失败: {}", e))?;
+
+        let body = RpkiBatchRequest {
+            routes: asns.iter().map(|asn| RpkiBatchRoute { asn, prefix }).collect(),
+        };
+        let resp = send_with_retry(client.post(&url).json(&body)).await
+            .map_err(|e| format!("RPKI批量请求失败: {}", e))?;
+
+        if !resp.status().is_success() {
+            return Err(format!("RPKI批量请求失败: 状态码 {}", resp.status()));
+        }
+
+        let json: RpkiBatchResponse = resp.json().await
+            .map_err(|e| format!("解析RPKI批量响应失败: {}", e))?;
+
+        Ok(json.validated_routes
+            .into_iter()
+            .map(|validated| RpkiValidity {
+                asn: validated.route.origin_asn,
+                prefix: validated.route.prefix,
+                validity: validated.validity.state,
+                reason: None,
+                vrps: validated.vrps,
+            })
+            .collect())
     }
 
     pub async fn query(&self, prefix: &str, asn: &str) -> Result<RpkiValidity, String> {
         let url = format!("{}/api/v1/validity/{}/{}", self.base_url, asn, prefix);
         info!("RPKI 请求 URL: {}", url);
-        let client = Client::builder()
-            .timeout(Duration::from_secs(30))
+        let client = http_client_builder()
+            .timeout(request_timeout(Duration::from_secs(30)))
             .build()
             .map_err(|e| format!("创建HTTP客户端失败: {}", e))?;
 
-        let resp = client.get(&url).send().await
+        let resp = send_with_retry(client.get(&url)).await
             .map_err(|e| format!("RPKI请求失败: {}", e))?;
 
         if !resp.status().is_success() {
@@ -102,4 +219,21 @@ impl RpkiClient {
             })
         }
     }
-} 
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cross_check_agrees_only_when_all_verdicts_match() {
+        let verdict = |validity: Option<&str>| RpkiVerdict {
+            validator: "http://validator".to_string(),
+            validity: validity.map(str::to_string),
+            error: validity.is_none().then(|| "timeout".to_string()),
+        };
+        assert!(RpkiCrossCheck::new("13335", "1.1.1.0/24", vec![verdict(Some("valid")), verdict(Some("valid"))]).agree);
+        assert!(!RpkiCrossCheck::new("13335", "1.1.1.0/24", vec![verdict(Some("valid")), verdict(Some("invalid"))]).agree);
+        assert!(!RpkiCrossCheck::new("13335", "1.1.1.0/24", vec![verdict(Some("valid")), verdict(None)]).agree);
+    }
+}
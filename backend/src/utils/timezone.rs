@@ -0,0 +1,24 @@
+use chrono::{DateTime, Offset, TimeZone, Utc};
+use chrono_tz::Tz;
+
+/// IANA时区（如 Asia/Shanghai）在指定时刻的UTC偏移（如 +08:00），考虑夏令时；无法识别的时区返回None
+pub fn utc_offset(time_zone: &str, at: DateTime<Utc>) -> Option<String> {
+    let tz: Tz = time_zone.parse().ok()?;
+    Some(tz.offset_from_utc_datetime(&at.naive_utc()).fix().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn offsets_follow_daylight_saving() {
+        let winter = Utc.with_ymd_and_hms(2024, 1, 15, 12, 0, 0).unwrap();
+        let summer = Utc.with_ymd_and_hms(2024, 7, 15, 12, 0, 0).unwrap();
+        assert_eq!(utc_offset("Asia/Shanghai", winter).as_deref(), Some("+08:00"));
+        assert_eq!(utc_offset("America/New_York", winter).as_deref(), Some("-05:00"));
+        assert_eq!(utc_offset("America/New_York", summer).as_deref(), Some("-04:00"));
+        assert_eq!(utc_offset("Asia/Kolkata", summer).as_deref(), Some("+05:30"));
+        assert_eq!(utc_offset("Not/AZone", summer), None);
+    }
+}
@@ -0,0 +1,109 @@
+use axum::extract::Request;
+use axum::middleware::Next;
+use axum::response::Response;
+use rand::RngCore;
+
+/// W3C Trace Context 请求头
+pub const TRACEPARENT: &str = "traceparent";
+
+/// 当前请求的追踪上下文（traceparent 中的 trace-id 与 trace-flags）
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TraceContext {
+    pub trace_id: [u8; 16],
+    pub flags: u8,
+}
+
+tokio::task_local! {
+    static CURRENT: TraceContext;
+}
+
+impl TraceContext {
+    /// 解析 traceparent：version-traceid-parentid-flags，全零的ID视为无效
+    pub fn parse(header: &str) -> Option<Self> {
+        let mut parts = header.trim().split('-');
+        let version = parts.next()?;
+        let trace_id = parts.next()?;
+        let parent_id = parts.next()?;
+        let flags = parts.next()?;
+        if version.len() != 2 || version == "ff" || trace_id.len() != 32 || parent_id.len() != 16 || flags.len() != 2 {
+            return None;
+        }
+        // 版本00不允许附加字段，更高版本忽略附加字段
+        if version == "00" && parts.next().is_some() {
+            return None;
+        }
+        let trace_id: [u8; 16] = hex_decode(trace_id)?.try_into().ok()?;
+        let parent_id = hex_decode(parent_id)?;
+        let flags = u8::from_str_radix(flags, 16).ok()?;
+        if trace_id == [0; 16] || parent_id.iter().all(|b| *b == 0) {
+            return None;
+        }
+        Some(Self { trace_id, flags })
+    }
+
+    /// 开启新的追踪（请求未携带有效 traceparent 时）
+    pub fn generate() -> Self {
+        let mut trace_id = [0u8; 16];
+        rand::thread_rng().fill_bytes(&mut trace_id);
+        Self { trace_id, flags: 0x01 }
+    }
+
+    /// 生成发往上游的 traceparent，每次调用使用新的 parent-id
+    pub fn child_header(&self) -> String {
+        let mut span_id = [0u8; 8];
+        rand::thread_rng().fill_bytes(&mut span_id);
+        format!("00-{}-{}-{:02x}", hex_encode(&self.trace_id), hex_encode(&span_id), self.flags)
+    }
+}
+
+fn hex_encode(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn hex_decode(s: &str) -> Option<Vec<u8>> {
+    if !s.bytes().all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b)) {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&s[i..i + 2], 16).ok())
+        .collect()
+}
+
+/// 中间件：读取入站请求的 traceparent（缺失时新建），在处理该请求期间生效
+pub async fn propagate(request: Request, next: Next) -> Response {
+    let context = request
+        .headers()
+        .get(TRACEPARENT)
+        .and_then(|v| v.to_str().ok())
+        .and_then(TraceContext::parse)
+        .unwrap_or_else(TraceContext::generate);
+    CURRENT.scope(context, next.run(request)).await
+}
+
+/// 为出站请求附加 traceparent；不在请求上下文中（如定时任务）时原样返回
+pub fn with_trace_context(request: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+    match CURRENT.try_with(|context| context.child_header()) {
+        Ok(header) => request.header(TRACEPARENT, header),
+        Err(_) => request,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn traceparent_is_parsed_and_child_keeps_trace_id() {
+        let context = TraceContext::parse("00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01").unwrap();
+        assert_eq!(context.flags, 1);
+        let child = context.child_header();
+        assert!(child.starts_with("00-4bf92f3577b34da6a3ce929d0e0e4736-"));
+        assert!(child.ends_with("-01"));
+        assert_ne!(&child[36..52], "00f067aa0ba902b7");
+
+        assert!(TraceContext::parse("00-00000000000000000000000000000000-00f067aa0ba902b7-01").is_none());
+        assert!(TraceContext::parse("00-4BF92F3577B34DA6A3CE929D0E0E4736-00f067aa0ba902b7-01").is_none());
+        assert!(TraceContext::parse("garbage").is_none());
+    }
+}
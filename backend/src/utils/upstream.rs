@@ -0,0 +1,634 @@
+use crate::config::{SharedConfig, UpstreamConfig};
+use crate::utils::bgp_api_client::{BgpApiClient, BgpApiResult};
+use crate::utils::bgptools_client::{asn_number, BgpToolsClient, BgpToolsInfo, BgpToolsUpstream, UpstreamNode};
+use crate::utils::dns_cache::CachingResolver;
+use crate::utils::dnsbl_client::DnsblClient;
+use crate::utils::ripestat_client::{HistoricalRouting, RelatedPrefix, RipeStatClient};
+use crate::utils::rpki_client::{RpkiClient, RpkiValidity, RpkiVerdict};
+use chrono::{DateTime, Utc};
+use crate::utils::trace_context::with_trace_context;
+use crate::utils::whois_client::{WhoisClient, WhoisInfo};
+use crate::utils::rdap_client::RdapClient;
+use futures::future::{join_all, BoxFuture};
+use ipnet::IpNet;
+use std::collections::{HashMap, HashSet};
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+
+/// ?upstream_depth= 允许的最大层数（1 为直接上游）
+pub const MAX_UPSTREAM_DEPTH: usize = 3;
+
+/// 单次请求展开上游拓扑时最多抓取的bgp.tools AS页面数
+const MAX_UPSTREAM_SCRAPES: usize = 8;
+
+/// 所有外部查询共享的并发许可，避免批量查询时打开过多连接
+static OUTBOUND_PERMITS: OnceLock<Arc<Semaphore>> = OnceLock::new();
+
+/// 所有HTTP客户端共享的出站代理
+static HTTP_PROXY: OnceLock<Option<String>> = OnceLock::new();
+
+/// 所有HTTP客户端共享的缓存DNS解析器，未启用时使用reqwest默认的系统解析
+static DNS_RESOLVER: OnceLock<Option<Arc<CachingResolver>>> = OnceLock::new();
+
+/// 可重试的HTTP请求失败后的额外尝试次数
+static HTTP_RETRIES: AtomicU32 = AtomicU32::new(2);
+
+/// 所有HTTP客户端的连接超时（毫秒）
+static CONNECT_TIMEOUT_MS: AtomicU64 = AtomicU64::new(3000);
+
+/// 外部查询的整体超时（秒），0 表示使用各数据源的默认值
+static REQUEST_TIMEOUT_SECS: AtomicU64 = AtomicU64::new(0);
+
+/// 第一次重试前的等待时间，之后每次翻倍
+const RETRY_BASE_DELAY: Duration = Duration::from_millis(200);
+
+/// 设置外部查询并发上限、出站代理、重试次数及超时（仅在启动时生效一次）
+pub fn configure(config: &UpstreamConfig) {
+    HTTP_RETRIES.store(config.http_retries, Ordering::Relaxed);
+    CONNECT_TIMEOUT_MS.store(config.connect_timeout_ms, Ordering::Relaxed);
+    REQUEST_TIMEOUT_SECS.store(config.request_timeout_secs.unwrap_or(0), Ordering::Relaxed);
+    if OUTBOUND_PERMITS.set(Arc::new(Semaphore::new(config.max_concurrent_requests))).is_err() {
+        tracing::warn!("外部查询并发上限已初始化，忽略新的设置");
+    }
+    if HTTP_PROXY.set(config.http_proxy.clone()).is_err() {
+        tracing::warn!("出站代理已初始化，忽略新的设置");
+    }
+    let resolver = config.dns_cache_enabled.then(|| {
+        CachingResolver::from_system_conf(config.dns_cache_size)
+            .inspect_err(|e| tracing::warn!("DNS缓存初始化失败，使用系统解析: {}", e))
+            .ok()
+            .map(Arc::new)
+    }).flatten();
+    if DNS_RESOLVER.set(resolver).is_err() {
+        tracing::warn!("DNS缓存已初始化，忽略新的设置");
+    }
+}
+
+/// 创建HTTP客户端构建器，已应用配置的出站代理、DNS缓存和连接超时；整体超时、User-Agent等由调用方继续设置
+pub fn http_client_builder() -> reqwest::ClientBuilder {
+    let mut builder = reqwest::Client::builder()
+        .connect_timeout(Duration::from_millis(CONNECT_TIMEOUT_MS.load(Ordering::Relaxed)));
+    if let Some(resolver) = DNS_RESOLVER.get().and_then(|r| r.clone()) {
+        builder = builder.dns_resolver(resolver);
+    }
+    let Some(proxy) = HTTP_PROXY.get().and_then(|p| p.as_deref()) else {
+        return builder;
+    };
+    match reqwest::Proxy::all(proxy) {
+        Ok(proxy) => builder.proxy(proxy),
+        Err(e) => {
+            tracing::warn!("出站代理配置无效，直接连接: {}", e);
+            builder
+        }
+    }
+}
+
+/// 外部查询的整体超时：配置了 upstream.request_timeout_secs 时使用配置值，否则使用数据源的默认值
+pub fn request_timeout(default: Duration) -> Duration {
+    match REQUEST_TIMEOUT_SECS.load(Ordering::Relaxed) {
+        0 => default,
+        secs => Duration::from_secs(secs),
+    }
+}
+
+/// 发送请求，遇到连接错误（含DNS解析失败）、超时或5xx时按指数退避重试，4xx不重试
+/// 最后一次尝试的结果原样返回，由调用方检查状态码
+/// 每次尝试都携带当前请求的 traceparent
+pub async fn send_with_retry(request: reqwest::RequestBuilder) -> reqwest::Result<reqwest::Response> {
+    let retries = HTTP_RETRIES.load(Ordering::Relaxed);
+    let mut attempt = 0;
+    loop {
+        // 请求体无法复制时（流式请求体）只发送一次
+        let Some(current) = request.try_clone() else {
+            return with_trace_context(request).send().await;
+        };
+        let result = with_trace_context(current).send().await;
+        let retryable = match &result {
+            Ok(resp) => resp.status().is_server_error(),
+            Err(e) => e.is_connect() || e.is_timeout(),
+        };
+        if !retryable || attempt >= retries {
+            return result;
+        }
+        let delay = RETRY_BASE_DELAY * 2u32.pow(attempt);
+        attempt += 1;
+        match &result {
+            Ok(resp) => tracing::warn!("上游请求返回 {}，{:?}后第{}次重试", resp.status(), delay, attempt),
+            Err(e) => tracing::warn!("上游请求失败: {}，{:?}后第{}次重试", e, delay, attempt),
+        }
+        tokio::time::sleep(delay).await;
+    }
+}
+
+/// 在发起外部查询前获取许可，许可在返回值被丢弃时释放
+pub async fn acquire_permit() -> OwnedSemaphorePermit {
+    let permits = OUTBOUND_PERMITS
+        .get_or_init(|| Arc::new(Semaphore::new(UpstreamConfig::default().max_concurrent_requests)))
+        .clone();
+    // 信号量从不关闭，获取不会失败
+    permits.acquire_owned().await.expect("外部查询信号量已关闭")
+}
+
+/// 查询目标在不同上游中的表示形式
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QueryTarget {
+    /// 单个地址形式（CIDR取网络地址），用于WHOIS和BGP Tools
+    pub address: String,
+    /// 前缀形式（单个IP补全为/32或/128，CIDR去除主机位），用于BGP API
+    pub prefix: String,
+}
+
+impl QueryTarget {
+    pub fn parse(input: &str) -> Result<Self, String> {
+        if let Ok(addr) = IpAddr::from_str(input) {
+            let len = if addr.is_ipv4() { 32 } else { 128 };
+            return Ok(Self {
+                address: addr.to_string(),
+                prefix: format!("{}/{}", addr, len),
+            });
+        }
+        let network = IpNet::from_str(input)
+            .map_err(|e| format!("无效的IP或CIDR: {}", e))?
+            .trunc();
+        Ok(Self {
+            address: network.network().to_string(),
+            prefix: network.to_string(),
+        })
+    }
+}
+
+/// 外部数据源抽象，处理器通过它发起所有网络查询，便于在测试中替换为固定数据
+pub trait Upstreams: Send + Sync {
+    /// 查询IP的WHOIS信息
+    fn whois<'a>(&'a self, ip: &'a str) -> BoxFuture<'a, Result<WhoisInfo, String>>;
+    /// 查询IP的BGP Tools信息
+    fn bgp_tools<'a>(&'a self, ip: &'a str) -> BoxFuture<'a, Result<BgpToolsInfo, String>>;
+    /// 查询AS自身的上游AS
+    fn as_upstreams<'a>(&'a self, asn: &'a str) -> BoxFuture<'a, Result<Vec<BgpToolsUpstream>, String>>;
+    /// 查询前缀在BGP API中的信息（prefix 为CIDR形式）
+    fn bgp_api<'a>(&'a self, prefix: &'a str) -> BoxFuture<'a, Result<BgpApiResult, String>>;
+    /// 查询单个前缀和源AS的RPKI验证结果
+    fn rpki<'a>(&'a self, prefix: &'a str, asn: &'a str) -> BoxFuture<'a, Result<RpkiValidity, String>>;
+    /// 批量查询前缀的多个源AS的RPKI验证结果，失败的ASN不出现在结果中
+    fn rpki_batch<'a>(&'a self, prefix: &'a str, asns: &'a [String]) -> BoxFuture<'a, Vec<RpkiValidity>>;
+    /// 向额外配置的验证器查询前缀的各个源AS，返回 (ASN, 结论)；未配置额外验证器时为空
+    fn rpki_cross_check<'a>(&'a self, prefix: &'a str, asns: &'a [String]) -> BoxFuture<'a, Vec<(String, RpkiVerdict)>>;
+    /// 查询前缀在指定时间点的路由状态
+    fn bgp_history<'a>(&'a self, prefix: &'a str, at: DateTime<Utc>) -> BoxFuture<'a, Result<HistoricalRouting, String>>;
+    /// 查询覆盖该前缀的较大前缀及其源AS
+    fn related_prefixes<'a>(&'a self, prefix: &'a str) -> BoxFuture<'a, Result<Vec<RelatedPrefix>, String>>;
+    /// 查询IP被列入的DNS黑名单，未启用时返回None
+    fn dnsbl<'a>(&'a self, ip: &'a str) -> BoxFuture<'a, Option<Result<Vec<String>, String>>>;
+}
+
+/// 访问真实网络服务的数据源
+pub struct LiveUpstreams {
+    config: SharedConfig,
+}
+
+impl LiveUpstreams {
+    pub fn new(config: SharedConfig) -> Self {
+        Self { config }
+    }
+}
+
+impl Upstreams for LiveUpstreams {
+    fn whois<'a>(&'a self, ip: &'a str) -> BoxFuture<'a, Result<WhoisInfo, String>> {
+        Box::pin(async move {
+            let whois = self.config.read().await.whois.clone();
+            let _permit = acquire_permit().await;
+            if whois.prefer_rdap {
+                match RdapClient::lookup(ip, &whois.extra_fields).await {
+                    Ok(info) => return Ok(info),
+                    Err(e) => tracing::warn!("RDAP查询失败，改用WHOIS: {}", e),
+                }
+            }
+            // 端口43查询和限速等待都会阻塞线程，放到阻塞线程池中执行
+            let ip = ip.to_string();
+            tokio::task::spawn_blocking(move || WhoisClient::lookup(&ip, &whois.extra_fields))
+                .await
+                .map_err(|e| format!("WHOIS查询任务失败: {}", e))?
+        })
+    }
+
+    fn bgp_tools<'a>(&'a self, ip: &'a str) -> BoxFuture<'a, Result<BgpToolsInfo, String>> {
+        Box::pin(async move {
+            let _permit = acquire_permit().await;
+            BgpToolsClient::lookup(ip).await
+        })
+    }
+
+    fn as_upstreams<'a>(&'a self, asn: &'a str) -> BoxFuture<'a, Result<Vec<BgpToolsUpstream>, String>> {
+        Box::pin(async move {
+            let _permit = acquire_permit().await;
+            BgpToolsClient::fetch_as_upstreams(asn).await
+        })
+    }
+
+    fn bgp_api<'a>(&'a self, prefix: &'a str) -> BoxFuture<'a, Result<BgpApiResult, String>> {
+        Box::pin(async move {
+            let keep_raw = self.config.read().await.upstream.keep_raw_bgp_api;
+            let _permit = acquire_permit().await;
+            BgpApiClient::query(prefix, keep_raw).await
+        })
+    }
+
+    fn rpki<'a>(&'a self, prefix: &'a str, asn: &'a str) -> BoxFuture<'a, Result<RpkiValidity, String>> {
+        Box::pin(async move {
+            let validator_url = self.config.read().await.rpki.validator_url.clone();
+            let rpki_client = RpkiClient::new(&validator_url);
+            let _permit = acquire_permit().await;
+            rpki_client.query(prefix, asn).await
+        })
+    }
+
+    fn rpki_batch<'a>(&'a self, prefix: &'a str, asns: &'a [String]) -> BoxFuture<'a, Vec<RpkiValidity>> {
+        Box::pin(async move {
+            let rpki = self.config.read().await.rpki.clone();
+            let rpki_client = RpkiClient::new(&rpki.validator_url).with_batch(rpki.batch);
+            let _permit = acquire_permit().await;
+            rpki_client.query_batch(prefix, asns).await
+        })
+    }
+
+    fn rpki_cross_check<'a>(&'a self, prefix: &'a str, asns: &'a [String]) -> BoxFuture<'a, Vec<(String, RpkiVerdict)>> {
+        Box::pin(async move {
+            let validators = self.config.read().await.rpki.extra_validators.clone();
+            let queries = validators.iter().flat_map(|validator| {
+                asns.iter().map(move |asn| async move {
+                    let _permit = acquire_permit().await;
+                    let result = RpkiClient::new(validator).query(prefix, asn).await;
+                    (asn.clone(), RpkiVerdict::from_result(validator, result))
+                })
+            });
+            join_all(queries).await
+        })
+    }
+
+    fn bgp_history<'a>(&'a self, prefix: &'a str, at: DateTime<Utc>) -> BoxFuture<'a, Result<HistoricalRouting, String>> {
+        Box::pin(async move {
+            let _permit = acquire_permit().await;
+            RipeStatClient::routing_status(prefix, at).await
+        })
+    }
+
+    fn related_prefixes<'a>(&'a self, prefix: &'a str) -> BoxFuture<'a, Result<Vec<RelatedPrefix>, String>> {
+        Box::pin(async move {
+            let _permit = acquire_permit().await;
+            RipeStatClient::less_specific_prefixes(prefix).await
+        })
+    }
+
+    fn dnsbl<'a>(&'a self, ip: &'a str) -> BoxFuture<'a, Option<Result<Vec<String>, String>>> {
+        Box::pin(async move {
+            let config = self.config.read().await.dnsbl.clone();
+            if !config.enabled || config.zones.is_empty() {
+                return None;
+            }
+            let _permit = acquire_permit().await;
+            Some(DnsblClient::check(ip, &config).await)
+        })
+    }
+}
+
+/// 从直接上游开始逐层查询上游的上游，共 depth 层（最多 MAX_UPSTREAM_DEPTH）
+/// 源AS及已出现过的AS不再展开，避免环路和重复查询；AS页面逐个抓取，单次请求最多 MAX_UPSTREAM_SCRAPES 个
+pub async fn upstream_tree(
+    upstreams: &dyn Upstreams,
+    origin_asn: Option<&str>,
+    direct: &[BgpToolsUpstream],
+    depth: usize,
+) -> Vec<UpstreamNode> {
+    expand_upstreams(|asn| async move { upstreams.as_upstreams(&asn).await }, origin_asn, direct, depth).await
+}
+
+async fn expand_upstreams<F, Fut>(
+    mut fetch: F,
+    origin_asn: Option<&str>,
+    direct: &[BgpToolsUpstream],
+    depth: usize,
+) -> Vec<UpstreamNode>
+where
+    F: FnMut(String) -> Fut,
+    Fut: std::future::Future<Output = Result<Vec<BgpToolsUpstream>, String>>,
+{
+    let mut seen: HashSet<String> = origin_asn.map(|asn| asn_number(asn).to_string()).into_iter().collect();
+    seen.extend(direct.iter().map(|upstream| asn_number(&upstream.asn).to_string()));
+    // 按ASN号缓存本次请求中已抓取的上游，同一AS只抓取一次
+    let mut children: HashMap<String, Vec<BgpToolsUpstream>> = HashMap::new();
+    let mut frontier: Vec<String> = direct.iter().map(|upstream| upstream.asn.clone()).collect();
+    let mut scrapes = 0;
+    for _ in 1..depth.min(MAX_UPSTREAM_DEPTH) {
+        let mut next = Vec::new();
+        for asn in frontier {
+            let key = asn_number(&asn).to_string();
+            if children.contains_key(&key) {
+                continue;
+            }
+            if scrapes >= MAX_UPSTREAM_SCRAPES {
+                tracing::debug!("上游拓扑已达到单次抓取上限 {}，不再展开 {}", MAX_UPSTREAM_SCRAPES, asn);
+                break;
+            }
+            scrapes += 1;
+            let found = fetch(asn.clone()).await.unwrap_or_else(|e| {
+                tracing::warn!("查询 {} 的上游失败: {}", asn, e);
+                Vec::new()
+            });
+            let unseen: Vec<BgpToolsUpstream> = found
+                .into_iter()
+                .filter(|upstream| seen.insert(asn_number(&upstream.asn).to_string()))
+                .collect();
+            next.extend(unseen.iter().map(|upstream| upstream.asn.clone()));
+            children.insert(key, unseen);
+        }
+        frontier = next;
+    }
+    build_upstream_nodes(direct, &children)
+}
+
+fn build_upstream_nodes(level: &[BgpToolsUpstream], children: &HashMap<String, Vec<BgpToolsUpstream>>) -> Vec<UpstreamNode> {
+    level
+        .iter()
+        .map(|upstream| UpstreamNode {
+            asn: upstream.asn.clone(),
+            name: upstream.name.clone(),
+            upstreams: children
+                .get(asn_number(&upstream.asn))
+                .map(|next| build_upstream_nodes(next, children))
+                .unwrap_or_default(),
+        })
+        .collect()
+}
+
+/// 返回固定数据的数据源，不发起任何网络请求
+#[cfg(test)]
+pub struct MockUpstreams;
+
+#[cfg(test)]
+impl Upstreams for MockUpstreams {
+    fn whois<'a>(&'a self, _ip: &'a str) -> BoxFuture<'a, Result<WhoisInfo, String>> {
+        Box::pin(async move {
+            Ok(WhoisInfo {
+                country: Some("AU".to_string()),
+                netname: Some("APNIC-LABS".to_string()),
+                descr: Some("APNIC and Cloudflare DNS Resolver project".to_string()),
+                org: None,
+                admin_c: Some("AIC3-AP".to_string()),
+                tech_c: Some("AIC3-AP".to_string()),
+                mnt_by: Some("APNIC-HM".to_string()),
+                last_modified: None,
+                route: Some("1.1.1.0/24".to_string()),
+                origin: Some("AS13335".to_string()),
+                extra: std::collections::HashMap::new(),
+                raw_response: String::new(),
+            })
+        })
+    }
+
+    fn bgp_tools<'a>(&'a self, ip: &'a str) -> BoxFuture<'a, Result<BgpToolsInfo, String>> {
+        Box::pin(async move {
+            Ok(BgpToolsInfo {
+                asn: Some("13335".to_string()),
+                ip: ip.to_string(),
+                prefix: Some("1.1.1.0/24".to_string()),
+                country: Some("US".to_string()),
+                registry: Some("ARIN".to_string()),
+                allocated: Some("2010-07-14".to_string()),
+                as_name: Some("Cloudflare, Inc.".to_string()),
+                upstreams: vec![crate::utils::bgptools_client::BgpToolsUpstream {
+                    asn: "AS174".to_string(),
+                    name: Some("Cogent".to_string()),
+                }],
+                raw_response: None,
+            })
+        })
+    }
+
+    fn as_upstreams<'a>(&'a self, asn: &'a str) -> BoxFuture<'a, Result<Vec<BgpToolsUpstream>, String>> {
+        Box::pin(async move {
+            let upstream = |asn: &str| BgpToolsUpstream { asn: asn.to_string(), name: None };
+            // AS174 与 AS3356 互为上游，AS174 还把源AS列为上游，用于检验环路处理
+            Ok(match asn {
+                "AS174" => vec![upstream("AS3356"), upstream("AS13335")],
+                "AS3356" => vec![upstream("AS174"), upstream("AS1299")],
+                "AS1299" => vec![upstream("AS3356")],
+                _ => Vec::new(),
+            })
+        })
+    }
+
+    fn bgp_api<'a>(&'a self, _ip: &'a str) -> BoxFuture<'a, Result<BgpApiResult, String>> {
+        Box::pin(async move {
+            Ok(BgpApiResult {
+                prefix: "1.1.1.0/24".to_string(),
+                meta: vec![crate::utils::bgp_api_client::BgpApiMeta {
+                    source_type: Some("bgp".to_string()),
+                    source_id: None,
+                    origin_asns: Some(vec!["13335".to_string()]),
+                    r#type: None,
+                }],
+                raw_response: None,
+            })
+        })
+    }
+
+    fn rpki<'a>(&'a self, prefix: &'a str, asn: &'a str) -> BoxFuture<'a, Result<RpkiValidity, String>> {
+        Box::pin(async move {
+            Ok(RpkiValidity {
+                asn: asn.to_string(),
+                prefix: prefix.to_string(),
+                validity: "valid".to_string(),
+                reason: None,
+                vrps: None,
+            })
+        })
+    }
+
+    fn rpki_batch<'a>(&'a self, prefix: &'a str, asns: &'a [String]) -> BoxFuture<'a, Vec<RpkiValidity>> {
+        Box::pin(async move {
+            asns.iter()
+                .map(|asn| RpkiValidity {
+                    asn: asn.to_string(),
+                    prefix: prefix.to_string(),
+                    validity: "valid".to_string(),
+                    reason: None,
+                    vrps: None,
+                })
+                .collect()
+        })
+    }
+
+    fn rpki_cross_check<'a>(&'a self, _prefix: &'a str, asns: &'a [String]) -> BoxFuture<'a, Vec<(String, RpkiVerdict)>> {
+        Box::pin(async move {
+            asns.iter()
+                .map(|asn| {
+                    let verdict = RpkiVerdict {
+                        validator: "http://second-validator".to_string(),
+                        validity: Some("valid".to_string()),
+                        error: None,
+                    };
+                    (asn.clone(), verdict)
+                })
+                .collect()
+        })
+    }
+
+    fn bgp_history<'a>(&'a self, prefix: &'a str, at: DateTime<Utc>) -> BoxFuture<'a, Result<HistoricalRouting, String>> {
+        Box::pin(async move {
+            Ok(HistoricalRouting {
+                resource: prefix.to_string(),
+                query_time: Some(at.format("%Y-%m-%dT%H:%M:%S").to_string()),
+                origins: vec![13335],
+                first_seen: Some("2018-03-31T00:00:00".to_string()),
+                last_seen: None,
+            })
+        })
+    }
+
+    fn related_prefixes<'a>(&'a self, _prefix: &'a str) -> BoxFuture<'a, Result<Vec<RelatedPrefix>, String>> {
+        Box::pin(async move {
+            Ok(vec![RelatedPrefix { prefix: "1.0.0.0/8".to_string(), origin_asn: None }])
+        })
+    }
+
+    fn dnsbl<'a>(&'a self, _ip: &'a str) -> BoxFuture<'a, Option<Result<Vec<String>, String>>> {
+        Box::pin(async move { Some(Ok(vec!["dnsbl.example.org".to_string()])) })
+    }
+}
+
+/// 所有查询都失败的数据源，模拟外部服务全部不可用
+#[cfg(test)]
+pub struct FailingUpstreams;
+
+#[cfg(test)]
+impl Upstreams for FailingUpstreams {
+    fn whois<'a>(&'a self, _ip: &'a str) -> BoxFuture<'a, Result<WhoisInfo, String>> {
+        Box::pin(async { Err("上游不可用".to_string()) })
+    }
+
+    fn bgp_tools<'a>(&'a self, _ip: &'a str) -> BoxFuture<'a, Result<BgpToolsInfo, String>> {
+        Box::pin(async { Err("上游不可用".to_string()) })
+    }
+
+    fn as_upstreams<'a>(&'a self, _asn: &'a str) -> BoxFuture<'a, Result<Vec<BgpToolsUpstream>, String>> {
+        Box::pin(async { Err("上游不可用".to_string()) })
+    }
+
+    fn bgp_api<'a>(&'a self, _prefix: &'a str) -> BoxFuture<'a, Result<BgpApiResult, String>> {
+        Box::pin(async { Err("上游不可用".to_string()) })
+    }
+
+    fn rpki<'a>(&'a self, _prefix: &'a str, _asn: &'a str) -> BoxFuture<'a, Result<RpkiValidity, String>> {
+        Box::pin(async { Err("上游不可用".to_string()) })
+    }
+
+    fn rpki_batch<'a>(&'a self, _prefix: &'a str, _asns: &'a [String]) -> BoxFuture<'a, Vec<RpkiValidity>> {
+        Box::pin(async { Vec::new() })
+    }
+
+    fn rpki_cross_check<'a>(&'a self, _prefix: &'a str, _asns: &'a [String]) -> BoxFuture<'a, Vec<(String, RpkiVerdict)>> {
+        Box::pin(async { Vec::new() })
+    }
+
+    fn bgp_history<'a>(&'a self, _prefix: &'a str, _at: DateTime<Utc>) -> BoxFuture<'a, Result<HistoricalRouting, String>> {
+        Box::pin(async { Err("上游不可用".to_string()) })
+    }
+
+    fn related_prefixes<'a>(&'a self, _prefix: &'a str) -> BoxFuture<'a, Result<Vec<RelatedPrefix>, String>> {
+        Box::pin(async { Err("上游不可用".to_string()) })
+    }
+
+    fn dnsbl<'a>(&'a self, _ip: &'a str) -> BoxFuture<'a, Option<Result<Vec<String>, String>>> {
+        Box::pin(async { None })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn query_target_for_single_addresses() {
+        let v4 = QueryTarget::parse("1.1.1.1").unwrap();
+        assert_eq!(v4.address, "1.1.1.1");
+        assert_eq!(v4.prefix, "1.1.1.1/32");
+
+        let v6 = QueryTarget::parse("2606:4700:4700::1111").unwrap();
+        assert_eq!(v6.address, "2606:4700:4700::1111");
+        assert_eq!(v6.prefix, "2606:4700:4700::1111/128");
+    }
+
+    #[test]
+    fn query_target_for_cidrs_strips_host_bits() {
+        let v4 = QueryTarget::parse("1.1.1.77/24").unwrap();
+        assert_eq!(v4.address, "1.1.1.0");
+        assert_eq!(v4.prefix, "1.1.1.0/24");
+
+        let v6 = QueryTarget::parse("2001:db8:abcd:12::1/48").unwrap();
+        assert_eq!(v6.address, "2001:db8:abcd::");
+        assert_eq!(v6.prefix, "2001:db8:abcd::/48");
+
+        assert!(QueryTarget::parse("1.1.1.0/33").is_err());
+    }
+
+    #[tokio::test]
+    async fn upstream_expansion_fetches_each_asn_once_within_the_scrape_budget() {
+        let upstream = |asn: &str| BgpToolsUpstream { asn: asn.to_string(), name: None };
+        let mut fetched = Vec::new();
+        // 每个AS都有5个新的上游，不加限制时第三层需要抓取几十个页面
+        let tree = expand_upstreams(
+            |asn| {
+                fetched.push(asn_number(&asn).to_string());
+                let found = (1..=5).map(|i| upstream(&format!("AS{}{}", asn_number(&asn), i))).collect();
+                async move { Ok(found) }
+            },
+            Some("AS13335"),
+            &[upstream("AS174"), upstream("174"), upstream("AS3356")],
+            MAX_UPSTREAM_DEPTH,
+        )
+        .await;
+
+        assert_eq!(fetched.len(), MAX_UPSTREAM_SCRAPES);
+        assert_eq!(fetched.iter().collect::<HashSet<_>>().len(), fetched.len());
+        // 写法不同的同一AS共享抓取结果
+        assert_eq!(tree[0].upstreams.len(), 5);
+        assert_eq!(tree[1].upstreams.len(), 5);
+    }
+
+    #[tokio::test]
+    async fn server_errors_are_retried_but_client_errors_are_not() {
+        use axum::http::StatusCode;
+        use std::sync::atomic::AtomicUsize;
+
+        let flaky_hits = Arc::new(AtomicUsize::new(0));
+        let missing_hits = Arc::new(AtomicUsize::new(0));
+        let flaky = flaky_hits.clone();
+        let missing = missing_hits.clone();
+        let app = axum::Router::new()
+            .route("/flaky", axum::routing::get(move || async move {
+                // 第一次返回503，之后成功
+                if flaky.fetch_add(1, Ordering::SeqCst) == 0 { StatusCode::SERVICE_UNAVAILABLE } else { StatusCode::OK }
+            }))
+            .route("/missing", axum::routing::get(move || async move {
+                missing.fetch_add(1, Ordering::SeqCst);
+                StatusCode::NOT_FOUND
+            }));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let client = reqwest::Client::new();
+        let resp = send_with_retry(client.get(format!("http://{}/flaky", addr))).await.unwrap();
+        assert_eq!(resp.status(), reqwest::StatusCode::OK);
+        assert_eq!(flaky_hits.load(Ordering::SeqCst), 2);
+
+        let resp = send_with_retry(client.get(format!("http://{}/missing", addr))).await.unwrap();
+        assert_eq!(resp.status(), reqwest::StatusCode::NOT_FOUND);
+        assert_eq!(missing_hits.load(Ordering::SeqCst), 1);
+    }
+}
@@ -0,0 +1,116 @@
+use crate::config::{RiskConfig, WebhookConfig};
+use crate::maxmind::reader::IpInfo;
+use crate::utils::privacy::log_ip;
+use crate::utils::risk;
+use crate::utils::upstream::{acquire_permit, http_client_builder, request_timeout};
+use serde::Serialize;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::{info, warn};
+
+/// 推送给Webhook的查询摘要
+#[derive(Debug, Clone, Serialize)]
+pub struct WebhookEvent {
+    pub ip: String,
+    pub country_code: Option<String>,
+    pub asn: Option<u32>,
+    pub organization: Option<String>,
+    pub risk_score: u8,
+    /// 命中的规则
+    pub matched: Vec<String>,
+    pub timestamp: u64,
+}
+
+/// 按配置的规则检查查询结果，返回命中的规则名称
+pub fn matched_rules(info: &IpInfo, config: &WebhookConfig, risk_score: u8) -> Vec<String> {
+    let mut matched = Vec::new();
+    if let Some(code) = info.country_code.as_deref()
+        && config.countries.iter().any(|c| c.eq_ignore_ascii_case(code)) {
+        matched.push(format!("country:{}", code));
+    }
+    if config.rpki_invalid && info.rpki_info_list.iter().any(|r| r.validity.eq_ignore_ascii_case("invalid")) {
+        matched.push("rpki_invalid".to_string());
+    }
+    if config.dnsbl_listed && !info.dnsbl.is_empty() {
+        matched.push("dnsbl_listed".to_string());
+    }
+    if let Some(min) = config.min_risk_score
+        && risk_score >= min {
+        matched.push(format!("risk_score>={}", min));
+    }
+    matched
+}
+
+/// 命中规则时在后台推送通知，不阻塞查询响应，推送失败只记录日志
+pub fn notify(info: &IpInfo, config: &WebhookConfig, risk_config: &RiskConfig) {
+    if config.urls.is_empty() {
+        return;
+    }
+    let (risk_score, _) = risk::score(info, risk_config);
+    let matched = matched_rules(info, config, risk_score);
+    if matched.is_empty() {
+        return;
+    }
+    let event = WebhookEvent {
+        ip: info.ip.clone(),
+        country_code: info.country_code.clone(),
+        asn: info.asn,
+        organization: info.organization.clone(),
+        risk_score,
+        matched,
+        timestamp: SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs(),
+    };
+    info!("查询结果命中Webhook规则 {:?}: {}", event.matched, log_ip(&event.ip));
+    for url in config.urls.clone() {
+        let event = event.clone();
+        tokio::spawn(async move {
+            if let Err(e) = deliver(&url, &event).await {
+                warn!("Webhook推送失败 {}: {}", url, e);
+            }
+        });
+    }
+}
+
+async fn deliver(url: &str, event: &WebhookEvent) -> Result<(), String> {
+    let _permit = acquire_permit().await;
+    let client = http_client_builder()
+        .timeout(request_timeout(Duration::from_secs(10)))
+        .build()
+        .map_err(|e| format!("创建HTTP客户端失败: {}", e))?;
+    let resp = client.post(url).json(event).send().await
+        .map_err(|e| format!("请求失败: {}", e))?;
+    if !resp.status().is_success() {
+        return Err(format!("状态码 {}", resp.status()));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::rpki_client::RpkiValidity;
+
+    #[test]
+    fn rules_match_country_rpki_and_risk() {
+        let config = WebhookConfig {
+            urls: vec!["http://127.0.0.1/hook".to_string()],
+            countries: vec!["kp".to_string()],
+            rpki_invalid: true,
+            dnsbl_listed: true,
+            min_risk_score: Some(50),
+        };
+        let info = IpInfo {
+            ip: "175.45.176.1".to_string(),
+            country_code: Some("KP".to_string()),
+            rpki_info_list: vec![RpkiValidity {
+                asn: "131279".to_string(),
+                prefix: "175.45.176.0/22".to_string(),
+                validity: "invalid".to_string(),
+                reason: None,
+                vrps: None,
+            }],
+            ..Default::default()
+        };
+        assert_eq!(matched_rules(&info, &config, 10), vec!["country:KP", "rpki_invalid"]);
+        assert_eq!(matched_rules(&IpInfo::default(), &config, 50), vec!["risk_score>=50"]);
+    }
+}
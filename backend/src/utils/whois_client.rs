@@ -1,16 +1,65 @@
+use std::collections::HashMap;
 use std::io::{BufRead, BufReader, Write};
 use std::net::TcpStream;
-use std::time::Duration;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
 use serde::{Deserialize, Serialize};
-use tracing::{debug, error};
+use tracing::{debug, error, warn};
+use crate::config::WhoisConfig;
 
 // WHOIS服务器
 const RIPE_WHOIS_SERVER: &str = "whois.ripe.net";
 const WHOIS_PORT: u16 = 43;
 const WHOIS_TIMEOUT: Duration = Duration::from_secs(10);
 
+/// 被WHOIS服务器限速时返回的错误前缀，调用方据此避免缓存不完整的结果
+pub const RATE_LIMITED: &str = "WHOIS查询被限速";
+
+static MIN_INTERVAL_MS: AtomicU64 = AtomicU64::new(200);
+static BACKOFF_SECS: AtomicU64 = AtomicU64::new(60);
+
+/// 查询节流状态：下一次允许发起查询的时间，以及限速后的暂停截止时间
+struct Throttle {
+    next_allowed: Option<Instant>,
+    backoff_until: Option<Instant>,
+}
+
+static THROTTLE: Mutex<Throttle> = Mutex::new(Throttle { next_allowed: None, backoff_until: None });
+
+/// 应用WHOIS节流配置（启动及配置热更新时调用）
+pub fn configure(config: &WhoisConfig) {
+    MIN_INTERVAL_MS.store(config.min_interval_ms, Ordering::Relaxed);
+    BACKOFF_SECS.store(config.backoff_secs, Ordering::Relaxed);
+}
+
+/// 识别响应中的错误块：RIPE的 %ERROR（101 无结果除外）及ARIN等服务器的限速提示
+fn check_response(response: &str) -> Result<(), String> {
+    let lower = response.to_lowercase();
+    let rate_limited = response.contains("%ERROR:201")
+        || response.contains("%ERROR:202")
+        || lower.contains("rate limit")
+        || lower.contains("limit exceeded")
+        || lower.contains("query limit");
+    if rate_limited {
+        return Err(format!("{}: {}", RATE_LIMITED, first_error_line(response)));
+    }
+    if let Some(line) = response.lines().find(|l| l.starts_with("%ERROR:") && !l.starts_with("%ERROR:101")) {
+        return Err(format!("WHOIS服务器返回错误: {}", line.trim()));
+    }
+    Ok(())
+}
+
+fn first_error_line(response: &str) -> &str {
+    response
+        .lines()
+        .find(|l| l.contains("ERROR") || l.to_lowercase().contains("limit"))
+        .map(|l| l.trim())
+        .unwrap_or("")
+}
+
 /// WHOIS查询结果
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct WhoisInfo {
     /// 国家代码 (如 CN, US, JP)
     pub country: Option<String>,
@@ -28,17 +77,109 @@ pub struct WhoisInfo {
     pub mnt_by: Option<String>,
     /// 最后更新时间
     pub last_modified: Option<String>,
+    /// 注册的路由对象前缀 (route / route6)
+    pub route: Option<String>,
+    /// 路由对象登记的源AS (origin)
+    pub origin: Option<String>,
+    /// 按配置额外提取的属性，保留多值属性的全部取值
+    pub extra: HashMap<String, Vec<String>>,
     /// 原始WHOIS响应
     pub raw_response: String,
 }
 
+/// WHOIS反查得到的对象摘要
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WhoisObject {
+    /// 对象类型 (如 inetnum, route, aut-num)
+    pub object_type: String,
+    /// 对象主键
+    pub key: String,
+    /// 描述
+    pub descr: Option<String>,
+    /// 维护者
+    pub mnt_by: Option<String>,
+}
+
 /// WHOIS客户端
 #[allow(dead_code)]
 pub struct WhoisClient;
 
 impl WhoisClient {
-    /// 查询IP的WHOIS信息
-    pub fn lookup(ip: &str) -> Result<WhoisInfo, String> {
+    /// 查询IP的WHOIS信息，extra_fields 指定需要额外提取的属性名
+    pub fn lookup(ip: &str, extra_fields: &[String]) -> Result<WhoisInfo, String> {
+        let response = Self::query(ip)?;
+
+        // 解析响应
+        let whois_info = Self::parse_response(&response, extra_fields);
+        Ok(whois_info)
+    }
+
+    /// 反查与维护者/联系人句柄或邮箱关联的WHOIS对象
+    pub fn inverse_lookup(handle: &str) -> Result<Vec<WhoisObject>, String> {
+        // 以 - 开头的句柄会被WHOIS服务器当作查询参数
+        if handle.is_empty()
+            || handle.starts_with('-')
+            || !handle.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | '@')) {
+            return Err(format!("无效的句柄: {}", handle));
+        }
+
+        // 邮箱通过 notify 属性反查，其余按维护者/联系人/组织句柄反查
+        let attributes = if handle.contains('@') {
+            "notify"
+        } else {
+            "mnt-by,admin-c,tech-c,org"
+        };
+        let response = Self::query(&format!("-r -B -i {} {}", attributes, handle))?;
+        if response.contains("%ERROR:101") {
+            return Ok(Vec::new());
+        }
+        Ok(Self::parse_objects(&response))
+    }
+
+    /// 等待到下一个允许的查询时间；处于限速暂停期时直接返回错误
+    fn throttle() -> Result<(), String> {
+        let wait = {
+            let mut throttle = THROTTLE.lock().unwrap_or_else(|e| e.into_inner());
+            let now = Instant::now();
+            if let Some(until) = throttle.backoff_until {
+                if until > now {
+                    return Err(format!("{}: 暂停中，{}秒后恢复", RATE_LIMITED, (until - now).as_secs() + 1));
+                }
+                throttle.backoff_until = None;
+            }
+            // 预约下一个查询时间片，在锁外等待
+            let slot = throttle.next_allowed.map_or(now, |next| next.max(now));
+            throttle.next_allowed = Some(slot + Duration::from_millis(MIN_INTERVAL_MS.load(Ordering::Relaxed)));
+            slot - now
+        };
+        if !wait.is_zero() {
+            std::thread::sleep(wait);
+        }
+        Ok(())
+    }
+
+    /// 被限速后暂停一段时间的查询
+    fn start_backoff() {
+        let backoff = Duration::from_secs(BACKOFF_SECS.load(Ordering::Relaxed));
+        warn!("WHOIS服务器限速，暂停查询 {:?}", backoff);
+        let mut throttle = THROTTLE.lock().unwrap_or_else(|e| e.into_inner());
+        throttle.backoff_until = Some(Instant::now() + backoff);
+    }
+
+    /// 向WHOIS服务器发送查询并返回原始响应；响应为错误块（限速、拒绝访问等）时返回错误
+    fn query(query: &str) -> Result<String, String> {
+        Self::throttle()?;
+        let response = Self::send_query(query)?;
+        if let Err(e) = check_response(&response) {
+            if e.starts_with(RATE_LIMITED) {
+                Self::start_backoff();
+            }
+            return Err(e);
+        }
+        Ok(response)
+    }
+
+    fn send_query(query: &str) -> Result<String, String> {
         // 建立TCP连接
         let mut stream = match TcpStream::connect((RIPE_WHOIS_SERVER, WHOIS_PORT)) {
             Ok(s) => s,
@@ -54,7 +195,7 @@ impl WhoisClient {
         }
 
         // 发送查询请求
-        let query = format!("{}\r\n", ip);
+        let query = format!("{}\r\n", query);
         if let Err(e) = stream.write_all(query.as_bytes()) {
             return Err(format!("无法发送WHOIS查询: {}", e));
         }
@@ -76,14 +217,55 @@ impl WhoisClient {
         }
 
         debug!("WHOIS响应: {}", response);
+        Ok(response)
+    }
 
-        // 解析响应
-        let whois_info = Self::parse_response(&response);
-        Ok(whois_info)
+    /// 将WHOIS响应按空行切分为对象，取每个对象首行作为类型和主键
+    fn parse_objects(response: &str) -> Vec<WhoisObject> {
+        let mut objects = Vec::new();
+        let mut current: Option<WhoisObject> = None;
+
+        for line in response.lines() {
+            let line = line.trim();
+            if line.is_empty() {
+                if let Some(object) = current.take() {
+                    objects.push(object);
+                }
+                continue;
+            }
+            if line.starts_with('%') || line.starts_with('#') {
+                continue;
+            }
+
+            let Some((key, value)) = line.split_once(':') else {
+                continue;
+            };
+            let (key, value) = (key.trim(), value.trim());
+            match current.as_mut() {
+                None => {
+                    current = Some(WhoisObject {
+                        object_type: key.to_string(),
+                        key: value.to_string(),
+                        descr: None,
+                        mnt_by: None,
+                    });
+                }
+                Some(object) => match key {
+                    "descr" if object.descr.is_none() => object.descr = Some(value.to_string()),
+                    "mnt-by" if object.mnt_by.is_none() => object.mnt_by = Some(value.to_string()),
+                    _ => {}
+                },
+            }
+        }
+        if let Some(object) = current {
+            objects.push(object);
+        }
+
+        objects
     }
 
     /// 解析WHOIS响应
-    fn parse_response(response: &str) -> WhoisInfo {
+    fn parse_response(response: &str, extra_fields: &[String]) -> WhoisInfo {
         let mut country = None;
         let mut netname = None;
         let mut descr = None;
@@ -92,6 +274,9 @@ impl WhoisClient {
         let mut tech_c = None;
         let mut mnt_by = None;
         let mut last_modified = None;
+        let mut route = None;
+        let mut origin = None;
+        let mut extra: HashMap<String, Vec<String>> = HashMap::new();
 
         for line in response.lines() {
             let line = line.trim();
@@ -107,19 +292,21 @@ impl WhoisClient {
             let key = parts[0].trim();
             let value = parts[1].trim();
 
+            if extra_fields.iter().any(|f| f.eq_ignore_ascii_case(key)) {
+                extra.entry(key.to_lowercase()).or_default().push(value.to_string());
+            }
+
             match key {
                 "country" => country = Some(value.to_string()),
                 "netname" => netname = Some(value.to_string()),
-                "descr" => {
-                    if descr.is_none() {
-                        descr = Some(value.to_string());
-                    }
-                }
+                "descr" if descr.is_none() => descr = Some(value.to_string()),
                 "org" | "organisation" => org = Some(value.to_string()),
                 "admin-c" => admin_c = Some(value.to_string()),
                 "tech-c" => tech_c = Some(value.to_string()),
                 "mnt-by" => mnt_by = Some(value.to_string()),
                 "last-modified" => last_modified = Some(value.to_string()),
+                "route" | "route6" if route.is_none() => route = Some(value.to_string()),
+                "origin" if origin.is_none() => origin = Some(value.to_string()),
                 _ => {}
             }
         }
@@ -133,7 +320,33 @@ impl WhoisClient {
             tech_c,
             mnt_by,
             last_modified,
+            route,
+            origin,
+            extra,
             raw_response: response.to_string(),
         }
     }
-} 
\ No newline at end of file
+} 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn handles_that_look_like_flags_are_rejected() {
+        for handle in ["", "-T", "--version", "-i mnt-by", "AS13335;ls"] {
+            assert!(WhoisClient::inverse_lookup(handle).unwrap_err().starts_with("无效的句柄"), "{}", handle);
+        }
+    }
+
+    #[test]
+    fn detects_rate_limit_and_error_blocks() {
+        let denied = "% This is the RIPE Database query service.\n%ERROR:201: access denied for 192.0.2.1\n";
+        assert!(check_response(denied).unwrap_err().starts_with(RATE_LIMITED));
+        let arin = "Query rate limit exceeded. Please try again later.\n";
+        assert!(check_response(arin).unwrap_err().starts_with(RATE_LIMITED));
+        let other = "%ERROR:104: unknown source\n";
+        assert!(!check_response(other).unwrap_err().starts_with(RATE_LIMITED));
+        assert!(check_response("%ERROR:101: no entries found\n").is_ok());
+        assert!(check_response("inetnum: 1.1.1.0 - 1.1.1.255\n").is_ok());
+    }
+}